@@ -0,0 +1,143 @@
+//! Derive macro for [gerrymander](https://docs.rs/gerrymander)'s `State` trait.
+//! Use it through gerrymander's `derive` feature, rather than depending on this directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Type};
+
+/// Implement `State` on an enum by handing every method to whichever variant it is.
+///
+/// Every variant needs to hold exactly one thing, which implements `State` with the same context.
+/// The enum implements `State<Ctx>` for every `Ctx` that all of them do.
+///
+/// Anything else, like a unit variant or a struct, is a compile error pointing at that variant.
+///
+/// The transitions from `update` are converted with `Transition::map`, so they can't have a
+/// `Transition::FromFn` in them. That's only known once `update` returns, so it can't be a compile
+/// error; it panics, naming the variant whose `update` returned it.
+#[proc_macro_derive(State)]
+pub fn derive_state(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  match expand(input) {
+    Ok(tokens) => tokens.into(),
+    Err(err) => err.to_compile_error().into(),
+  }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+  let Data::Enum(data) = &input.data else {
+    return Err(Error::new_spanned(
+      &input.ident,
+      "State can only be derived for enums",
+    ));
+  };
+
+  let mut variants = Vec::new();
+  let mut inner_types: Vec<&Type> = Vec::new();
+  for variant in &data.variants {
+    match &variant.fields {
+      Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+        variants.push(&variant.ident);
+        inner_types.push(&fields.unnamed[0].ty);
+      }
+      _ => return Err(Error::new_spanned(
+        variant,
+        "every variant needs to hold exactly one state, like `Variant(State)`",
+      )),
+    }
+  }
+
+  let name = &input.ident;
+  let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+  let params = input.generics.params.iter();
+  let existing = where_clause.map(|clause| &clause.predicates);
+
+  // The same method call on every variant
+  let each = |call: TokenStream2| {
+    quote! {
+      match self {
+        #( #name::#variants(__state) => __state.#call, )*
+      }
+    }
+  };
+  let draw_previous = each(quote!(draw_previous()));
+  let update_previous = each(quote!(update_previous()));
+  let on_enter = each(quote!(on_enter(__ctx)));
+  let on_exit = each(quote!(on_exit(__ctx)));
+  let on_pause = each(quote!(on_pause(__ctx)));
+  let on_result = each(quote!(on_result(__result, __ctx)));
+  let on_resume = each(quote!(on_resume(__ctx)));
+  let from_fn = variants.iter().map(|variant| {
+    format!(
+      "`{}::{}` returned a Transition::FromFn from `update`, \
+       which can't be converted to `{}`'s transitions",
+      name, variant, name
+    )
+  });
+
+  Ok(quote! {
+    impl<#( #params, )* __Ctx> ::gerrymander::State<__Ctx> for #name #ty_generics
+    where
+      Self: 'static,
+      #( #inner_types: ::gerrymander::State<__Ctx> + 'static, )*
+      #existing
+    {
+      fn update(
+        &mut self,
+        __ctx: &mut __Ctx,
+      ) -> ::gerrymander::Transition<Self> {
+        match self {
+          #(
+            #name::#variants(__state) => __state
+              .update(__ctx)
+              .map(#name::#variants, |__state| match __state {
+                #name::#variants(__state) => ::core::option::Option::Some(__state),
+                #[allow(unreachable_patterns)]
+                _ => ::core::option::Option::None,
+              })
+              .unwrap_or_else(|_| ::core::panic!(#from_fn)),
+          )*
+        }
+      }
+
+      fn draw_previous(&self) -> bool {
+        #draw_previous
+      }
+
+      fn update_previous(&self) -> bool {
+        #update_previous
+      }
+
+      fn on_enter(
+        &mut self,
+        __ctx: &mut __Ctx,
+      ) -> ::core::result::Result<(), ::std::borrow::Cow<'static, str>> {
+        #on_enter
+      }
+
+      fn on_exit(
+        &mut self,
+        __ctx: &mut __Ctx,
+      ) -> ::core::result::Result<(), ::std::borrow::Cow<'static, str>> {
+        #on_exit
+      }
+
+      fn on_pause(&mut self, __ctx: &mut __Ctx) {
+        #on_pause
+      }
+
+      fn on_result(
+        &mut self,
+        __result: ::std::boxed::Box<dyn ::core::any::Any>,
+        __ctx: &mut __Ctx,
+      ) {
+        #on_result
+      }
+
+      fn on_resume(&mut self, __ctx: &mut __Ctx) {
+        #on_resume
+      }
+    }
+  })
+}
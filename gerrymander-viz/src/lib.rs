@@ -0,0 +1,42 @@
+//! The rendering behind the `gerrymander-viz` binary, for drawing
+//! [`AuditTrail`](gerrymander::AuditTrail)s from your own code.
+
+use std::{fmt::Write, time::UNIX_EPOCH};
+
+use gerrymander::{AuditEntry, TransitionGraph};
+
+/// One line per entry, with the time since the first one.
+pub fn timeline(entries: &[AuditEntry]) -> String {
+  let start = entries.first().map_or(UNIX_EPOCH, |entry| entry.at);
+  let mut out = String::new();
+  for entry in entries {
+    let since = entry.at.duration_since(start).unwrap_or_default();
+    let _ = write!(
+      out,
+      "+{:>10.3}s  {:<16} depth {} -> {}",
+      since.as_secs_f64(),
+      entry.kind.name(),
+      entry.depth_before,
+      entry.depth_after
+    );
+    if let Some(label) = &entry.label {
+      let _ = write!(out, "  {}", label);
+    }
+    if let Err(err) = &entry.outcome {
+      let _ = write!(out, "  FAILED: {}", err);
+    }
+    out.push('\n');
+  }
+  out
+}
+
+/// The graph of labels the active state went between.
+pub fn graph(entries: &[AuditEntry]) -> TransitionGraph<()> {
+  let graph = TransitionGraph::new(|_| String::new());
+  for entry in entries {
+    if let (Ok(_), Some(label)) = (&entry.outcome, &entry.label) {
+      graph.record(label.as_str());
+    }
+  }
+  graph
+}
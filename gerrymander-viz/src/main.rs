@@ -0,0 +1,54 @@
+//! Read an [`AuditTrail`](gerrymander::AuditTrail) saved as JSON, and write out a timeline
+//! and the graph of which states led to which.
+//!
+//! ```text
+//! cargo install gerrymander-viz --features viz
+//! gerrymander-viz <trail.json> [out-dir]
+//! ```
+//!
+//! This writes `timeline.txt`, `graph.dot` and `graph.mmd` into the output directory, which is
+//! the current one by default. The graph is keyed by the entries' labels, so the trail should be
+//! [labeled](gerrymander::AuditTrail::labeled), and [started](gerrymander::AuditTrail::start) so
+//! the graph knows where the machine began.
+
+use std::{
+  error::Error,
+  path::{Path, PathBuf},
+};
+
+use gerrymander::AuditEntry;
+use gerrymander_viz::{graph, timeline};
+
+fn main() {
+  if let Err(err) = run() {
+    eprintln!("gerrymander-viz: {}", err);
+    std::process::exit(1);
+  }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+  let mut args = std::env::args_os().skip(1);
+  let Some(input) = args.next() else {
+    return Err("usage: gerrymander-viz <trail.json> [out-dir]".into());
+  };
+  let out = args
+    .next()
+    .map_or_else(|| PathBuf::from("."), PathBuf::from);
+
+  let json = std::fs::read_to_string(&input)?;
+  let entries: Vec<AuditEntry> = serde_json::from_str(&json)?;
+
+  std::fs::create_dir_all(&out)?;
+  let graph = graph(&entries);
+  write(&out, "timeline.txt", &timeline(&entries))?;
+  write(&out, "graph.dot", &graph.to_dot())?;
+  write(&out, "graph.mmd", &graph.to_mermaid())?;
+  Ok(())
+}
+
+fn write(dir: &Path, name: &str, contents: &str) -> std::io::Result<()> {
+  let path = dir.join(name);
+  std::fs::write(&path, contents)?;
+  println!("wrote {}", path.display());
+  Ok(())
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use gerrymander::{
+  AuditEntry, TransitionError, TransitionKind, TransitionShape,
+};
+use gerrymander_viz::{graph, timeline};
+
+fn trail() -> Vec<AuditEntry> {
+  let entry =
+    |millis, kind, outcome, depth_before, depth_after, label: &str| {
+      AuditEntry {
+        at: UNIX_EPOCH + Duration::from_millis(millis),
+        kind,
+        outcome,
+        depth_before,
+        depth_after,
+        label: Some(label.to_owned()),
+      }
+    };
+  vec![
+    entry(
+      0,
+      TransitionKind::None,
+      Ok(TransitionShape::None),
+      1,
+      1,
+      "title",
+    ),
+    entry(
+      1000,
+      TransitionKind::Push,
+      Ok(TransitionShape::Pushed(1)),
+      1,
+      2,
+      "menu",
+    ),
+    entry(
+      2500,
+      TransitionKind::Pop,
+      Ok(TransitionShape::Revealed(1)),
+      2,
+      1,
+      "title",
+    ),
+    entry(
+      3000,
+      TransitionKind::Pop,
+      Err(TransitionError::PoppedTooMany {
+        popcnt: 1,
+        available: 0,
+      }),
+      1,
+      1,
+      "title",
+    ),
+  ]
+}
+
+#[test]
+fn renders_timeline() {
+  assert_eq!(
+    timeline(&trail()),
+    "+     0.000s  None             depth 1 -> 1  title\n\
+     +     1.000s  Push             depth 1 -> 2  menu\n\
+     +     2.500s  Pop              depth 2 -> 1  title\n\
+     +     3.000s  Pop              depth 1 -> 1  title  \
+     FAILED: Tried to pop 1 states, but could only pop 0\n"
+  );
+}
+
+#[test]
+fn renders_graphs() {
+  // The start entry gives the edge out of the title, and the failed pop doesn't go anywhere
+  let graph = graph(&trail());
+  assert_eq!(
+    graph.to_dot(),
+    "digraph states {\n  \
+     \"menu\";\n  \
+     \"title\";\n  \
+     \"menu\" -> \"title\" [label=\"1\"];\n  \
+     \"title\" -> \"menu\" [label=\"1\"];\n}\n"
+  );
+  assert_eq!(
+    graph.to_mermaid(),
+    "stateDiagram-v2\n  \
+     state \"menu\" as s0\n  \
+     state \"title\" as s1\n  \
+     [*] --> s1\n  \
+     s0 --> s1: 1\n  \
+     s1 --> s0: 1\n"
+  );
+}
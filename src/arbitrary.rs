@@ -0,0 +1,54 @@
+//! [`Arbitrary`] impls, for fuzzing.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{StateId, StateMachine, Transition};
+
+/// Always generates a machine with at least one state.
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for StateMachine<T> {
+  fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+    let mut stack = vec![T::arbitrary(u)?];
+    for state in u.arbitrary_iter()? {
+      stack.push(state?);
+    }
+    Ok(StateMachine::new_many(stack))
+  }
+}
+
+impl<'a> Arbitrary<'a> for StateId {
+  fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+    // Ids are handed out counting up from zero, so small ones are the interesting ones
+    Ok(StateId(u.int_in_range(0..=64)?))
+  }
+}
+
+/// Only generates the variants that don't hold closures or iterators.
+///
+/// Counts, indices, and depths are kept small, so they're actually in range now and then.
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Transition<T> {
+  fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+    let small = |u: &mut Unstructured<'a>| u.int_in_range(0..=16usize);
+    Ok(match u.int_in_range(0..=19)? {
+      0 => Transition::None,
+      1 => Transition::Push(u.arbitrary()?),
+      2 => Transition::Pop,
+      3 => Transition::PopN(small(u)?),
+      4 => Transition::PopTo(u.arbitrary()?),
+      5 => Transition::PopToMark(String::arbitrary(u)?.into()),
+      6 => Transition::RemoveId(u.arbitrary()?),
+      7 => Transition::Swap(u.arbitrary()?),
+      8 => Transition::PopNAndPush(small(u)?, u.arbitrary()?),
+      9 => Transition::PopAll,
+      10 => Transition::Reset(u.arbitrary()?),
+      11 => Transition::ReplaceAll(u.arbitrary()?),
+      12 => Transition::InsertAt(small(u)?, u.arbitrary()?),
+      13 => Transition::RemoveAt(small(u)?),
+      14 => Transition::SwapAt(small(u)?, u.arbitrary()?),
+      15 => Transition::PopBelow(small(u)?),
+      16 => Transition::Sequence(u.arbitrary()?),
+      17 => Transition::PopAllAndPush(u.arbitrary()?),
+      18 => Transition::PushKey(String::arbitrary(u)?.into()),
+      _ => Transition::Defer(u.arbitrary()?),
+    })
+  }
+}
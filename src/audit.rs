@@ -0,0 +1,141 @@
+use std::{cell::RefCell, rc::Rc, time::SystemTime};
+
+use crate::{
+  naming::Namer, Observer, StateMachine, TransitionError, TransitionEvent,
+  TransitionKind, TransitionShape,
+};
+
+/// One transition in an [`AuditTrail`].
+///
+/// With the `serde` feature, entries can be serialized, so the trail can be saved as JSON
+/// or anything else serde can write.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+  /// When it was applied, by the wall clock.
+  pub at: SystemTime,
+  /// Which transition it was.
+  pub kind: TransitionKind,
+  /// What it did, or why it didn't do anything.
+  pub outcome: Result<TransitionShape, TransitionError>,
+  /// How many states were on the stack before.
+  pub depth_before: usize,
+  /// How many states were on the stack after.
+  pub depth_after: usize,
+  /// The label of the active state afterwards, if the trail labels states.
+  pub label: Option<String>,
+}
+
+/// An [`Observer`] that writes down every transition applied to a machine, including the ones
+/// that fail, for when you need a record of every screen change.
+///
+/// Unlike [`StateMachine::record_history`](crate::StateMachine::record_history), this never
+/// forgets anything, uses the wall clock, and can label each entry with the state it ended up in.
+///
+/// Keep a clone to export, [like any observer](crate::StateMachine::add_observer). With the
+/// `serde` feature, the trail serializes as a list of its entries.
+pub struct AuditTrail<T> {
+  label: Option<Namer<T>>,
+  entries: Rc<RefCell<Vec<AuditEntry>>>,
+}
+
+impl<T> AuditTrail<T> {
+  /// Make an empty trail that doesn't label its entries.
+  pub fn new() -> Self {
+    Self {
+      label: None,
+      entries: Rc::default(),
+    }
+  }
+
+  /// Label each entry with the active state after the transition, using this function.
+  pub fn labeled(mut self, label: impl Fn(&T) -> String + 'static) -> Self {
+    self.label = Some(Rc::new(label));
+    self
+  }
+
+  /// Write down where the machine starts, as a [`Transition::None`](crate::Transition::None)
+  /// entry labeled with the active state.
+  ///
+  /// Without this, the trail doesn't say which state the first transition left, so a graph
+  /// drawn from it is missing the edge out of the first state.
+  pub fn start(&self, machine: &StateMachine<T>) {
+    let depth = machine.get_stack().len();
+    self.entries.borrow_mut().push(AuditEntry {
+      at: SystemTime::now(),
+      kind: TransitionKind::None,
+      outcome: Ok(TransitionShape::None),
+      depth_before: depth,
+      depth_after: depth,
+      label: self.label.as_ref().map(|label| label(machine.active())),
+    });
+  }
+
+  /// Get a copy of every entry so far, oldest first.
+  pub fn entries(&self) -> Vec<AuditEntry> {
+    self.entries.borrow().clone()
+  }
+
+  /// Get how many entries there are.
+  pub fn len(&self) -> usize {
+    self.entries.borrow().len()
+  }
+
+  /// Whether nothing's been written down yet.
+  pub fn is_empty(&self) -> bool {
+    self.entries.borrow().is_empty()
+  }
+
+  /// Take every entry so far out of the trail, oldest first, leaving it empty.
+  pub fn take(&self) -> Vec<AuditEntry> {
+    std::mem::take(&mut self.entries.borrow_mut())
+  }
+}
+
+impl<T> Observer<T> for AuditTrail<T> {
+  fn observe(&self, event: &TransitionEvent<'_, T>) {
+    let entry = AuditEntry {
+      at: SystemTime::now(),
+      kind: event.kind,
+      outcome: event.result.clone().map(|outcome| outcome.shape()),
+      depth_before: event.depth_before,
+      depth_after: event.depth_after,
+      label: self.label.as_ref().map(|label| label(event.active)),
+    };
+    self.entries.borrow_mut().push(entry);
+  }
+}
+
+impl<T> Default for AuditTrail<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T> Clone for AuditTrail<T> {
+  fn clone(&self) -> Self {
+    Self {
+      label: self.label.clone(),
+      entries: self.entries.clone(),
+    }
+  }
+}
+
+impl<T> std::fmt::Debug for AuditTrail<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("AuditTrail")
+      .field("labeled", &self.label.is_some())
+      .field("entries", &self.entries.borrow())
+      .finish()
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for AuditTrail<T> {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    self.entries.borrow().serialize(serializer)
+  }
+}
@@ -0,0 +1,105 @@
+use crate::{Transition, TransitionError};
+
+/// Builds up a transition out of pops and pushes, one step at a time.
+///
+/// Make one with [`Transition::build`].
+///
+/// Popping after pushing takes the pushed states back off first, so `.push(a).pop(1)` does nothing.
+/// [`TransitionBuilder::finish`] turns the whole thing into the simplest transition that does the
+/// same thing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionBuilder<T> {
+  pop_count: usize,
+  to_push: Vec<T>,
+}
+
+impl<T> TransitionBuilder<T> {
+  /// Create a builder that doesn't do anything yet.
+  pub fn new() -> Self {
+    Self {
+      pop_count: 0,
+      to_push: Vec::new(),
+    }
+  }
+
+  /// Pop this many states.
+  pub fn pop(mut self, count: usize) -> Self {
+    let cancelled = count.min(self.to_push.len());
+    self.to_push.truncate(self.to_push.len() - cancelled);
+    self.pop_count += count - cancelled;
+    self
+  }
+
+  /// Push a state on top.
+  pub fn push(mut self, state: T) -> Self {
+    self.to_push.push(state);
+    self
+  }
+
+  /// Push all of these states on top, in order.
+  pub fn push_many(mut self, states: impl IntoIterator<Item = T>) -> Self {
+    self.to_push.extend(states);
+    self
+  }
+
+  /// How many states the transition will pop off the stack before pushing.
+  pub fn pop_count(&self) -> usize {
+    self.pop_count
+  }
+
+  /// The states the transition will push.
+  pub fn to_push(&self) -> &[T] {
+    &self.to_push
+  }
+
+  /// How much the stack will grow (or shrink, if negative) when this is applied.
+  pub fn net_change(&self) -> isize {
+    self.to_push.len() as isize - self.pop_count as isize
+  }
+
+  /// Check if this would work on a stack with `len` states in it, without applying it.
+  ///
+  /// If `len` is 0 and this doesn't push anything, it would leave the stack empty, so it fails
+  /// with [`TransitionError::EmptyReplacement`].
+  pub fn validate(&self, len: usize) -> Result<(), TransitionError> {
+    let available = if self.to_push.is_empty() {
+      len
+        .checked_sub(1)
+        .ok_or(TransitionError::EmptyReplacement)?
+    } else {
+      len
+    };
+    if self.pop_count > available {
+      Err(TransitionError::PoppedTooMany {
+        popcnt: self.pop_count,
+        available,
+      })
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Turn this into a transition.
+  pub fn finish(mut self) -> Transition<T> {
+    match (self.pop_count, self.to_push.len()) {
+      (0, 0) => Transition::None,
+      (0, 1) => Transition::Push(self.to_push.pop().unwrap()),
+      (1, 0) => Transition::Pop,
+      (1, 1) => Transition::Swap(self.to_push.pop().unwrap()),
+      (count, 0) => Transition::PopN(count),
+      (count, _) => Transition::PopNAndPush(count, self.to_push),
+    }
+  }
+}
+
+impl<T> Default for TransitionBuilder<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T> From<TransitionBuilder<T>> for Transition<T> {
+  fn from(builder: TransitionBuilder<T>) -> Self {
+    builder.finish()
+  }
+}
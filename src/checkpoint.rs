@@ -0,0 +1,48 @@
+use std::borrow::Cow;
+
+use crate::{StateMachine, Transition, TransitionError, TransitionOutcome};
+
+impl<T> StateMachine<T> {
+  /// Save a copy of the whole stack with a label, so you can go back to it later with
+  /// [`StateMachine::rollback_to`], however much happens in between.
+  ///
+  /// If there was already a checkpoint with this label, it's replaced.
+  pub fn checkpoint(&mut self, label: impl Into<Cow<'static, str>>)
+  where
+    T: Clone,
+  {
+    self.checkpoints.insert(label.into(), self.stack.clone());
+  }
+
+  /// Put the stack back how it was when the checkpoint with this label was made.
+  ///
+  /// This applies [`Transition::ReplaceAll`] with a copy of the saved states, so guards and
+  /// protected states get a say. The checkpoint stays around, so you can roll back to it again.
+  /// The states that come back get new [`StateId`](crate::StateId)s.
+  ///
+  /// Fails with [`TransitionError::NoSuchCheckpoint`] if there's no checkpoint with this label.
+  pub fn rollback_to(
+    &mut self,
+    label: &str,
+  ) -> Result<TransitionOutcome<T>, TransitionError>
+  where
+    T: Clone,
+  {
+    let states = self
+      .checkpoints
+      .get(label)
+      .ok_or(TransitionError::NoSuchCheckpoint)?
+      .clone();
+    self.apply(Transition::ReplaceAll(states))
+  }
+
+  /// Check whether there's a checkpoint with this label.
+  pub fn has_checkpoint(&self, label: &str) -> bool {
+    self.checkpoints.contains_key(label)
+  }
+
+  /// Throw away the checkpoint with this label, and get its states back.
+  pub fn remove_checkpoint(&mut self, label: &str) -> Option<Vec<T>> {
+    self.checkpoints.remove(label)
+  }
+}
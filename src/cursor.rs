@@ -0,0 +1,120 @@
+use crate::{Stack, StateId, StateMachine, Tracked, TransitionError};
+
+/// A cursor for walking up and down a [`StateMachine`]'s stack and editing it in place.
+///
+/// Get one with [`StateMachine::cursor`]. It starts on the active state.
+///
+/// The cursor won't remove the last state, so the stack is never empty, or any protected states,
+/// and ids keep up with every change. Edits made through a cursor aren't transitions, so guards
+/// aren't asked about them, and observers, the history, and invariants don't hear about them.
+pub struct StackCursor<'a, T> {
+  stack: Tracked<'a, T>,
+  index: usize,
+}
+
+impl<T> StackCursor<'_, T> {
+  /// Get the index of the state the cursor's on, counting up from the bottom.
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  /// Get the id of the state the cursor's on.
+  pub fn id(&self) -> StateId {
+    self.stack.ids.ids[self.index]
+  }
+
+  /// Borrow the state the cursor's on.
+  pub fn get(&self) -> &T {
+    &self.stack.stack[self.index]
+  }
+
+  /// Mutably borrow the state the cursor's on.
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.stack.stack[self.index]
+  }
+
+  /// Move the cursor up one state, towards the top. Returns `false` if it was already at the top.
+  pub fn move_up(&mut self) -> bool {
+    if self.index + 1 < self.stack.len() {
+      self.index += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Move the cursor down one state, towards the bottom. Returns `false` if it was already at the
+  /// bottom.
+  pub fn move_down(&mut self) -> bool {
+    if self.index > 0 {
+      self.index -= 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Insert a state directly above the cursor. The cursor stays where it is.
+  pub fn insert_above(&mut self, state: T) {
+    self.stack.insert(self.index + 1, state);
+  }
+
+  /// Insert a state directly below the cursor. The cursor stays on the same state.
+  pub fn insert_below(&mut self, state: T) {
+    self.stack.insert(self.index, state);
+    self.index += 1;
+  }
+
+  /// Replace the state the cursor's on, and return the old one. The new state gets a new id.
+  ///
+  /// This fails with [`TransitionError::Protected`] if the state is protected with
+  /// [`StateMachine::protect_bottom`].
+  pub fn replace(&mut self, state: T) -> Result<T, TransitionError> {
+    self.check_protected()?;
+    let old = self.stack.remove(self.index);
+    self.stack.insert(self.index, state);
+    Ok(old)
+  }
+
+  /// Remove the state the cursor's on, and return it. The cursor moves down to the state below,
+  /// or stays at the bottom if it was there.
+  ///
+  /// This fails if it's the only state left, or if it's protected with
+  /// [`StateMachine::protect_bottom`].
+  pub fn remove(&mut self) -> Result<T, TransitionError> {
+    if self.stack.len() == 1 {
+      return Err(TransitionError::PoppedTooMany {
+        popcnt: 1,
+        available: 0,
+      });
+    }
+    self.check_protected()?;
+    let removed = self.stack.remove(self.index);
+    self.index = self.index.saturating_sub(1);
+    Ok(removed)
+  }
+
+  fn check_protected(&self) -> Result<(), TransitionError> {
+    if self.stack.protected.contains(&self.id()) {
+      Err(TransitionError::Protected)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Get a cursor for making changes anywhere in the stack. It starts on the active state.
+  ///
+  /// This fails with [`TransitionError::Locked`] if the machine is [frozen](StateMachine::freeze).
+  pub fn cursor(&mut self) -> Result<StackCursor<'_, T>, TransitionError> {
+    if self.frozen {
+      return Err(TransitionError::Locked);
+    }
+    let index = self.stack.len() - 1;
+    Ok(StackCursor {
+      stack: self.tracked(),
+      index,
+    })
+  }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Debug;
+
+use crate::StateMachine;
+
+/// Prints the stack of a [`StateMachine`] one state per line, for when the states are too big
+/// to read in a `Vec`'s debug output.
+///
+/// Each line has the state's index from the bottom, its depth from the top, its
+/// [name](StateMachine::name_states) if it has one, the state itself, and whether it's active
+/// or protected:
+///
+/// ```text
+/// 2 (top)      "PAUSE"  Pause { selected: 0 }  [active]
+/// 1 (depth 1)  "WORLD"  World { .. }
+/// 0 (depth 2)  "TITLE"  Title  [protected]
+/// ```
+///
+/// With `{:#?}`, states are pretty-printed, so they can take up more than one line.
+///
+/// Get one with [`StateMachine::debug_stack`].
+pub struct DebugStack<'a, T> {
+  machine: &'a StateMachine<T>,
+  bottom_first: bool,
+  names: bool,
+  states: bool,
+}
+
+impl<T> DebugStack<'_, T> {
+  /// Whether to print the bottommost state first, instead of the topmost.
+  pub fn bottom_first(mut self, bottom_first: bool) -> Self {
+    self.bottom_first = bottom_first;
+    self
+  }
+
+  /// Whether to print the names of states, if the machine has them. This is on by default.
+  pub fn names(mut self, names: bool) -> Self {
+    self.names = names;
+    self
+  }
+
+  /// Whether to print the states themselves. This is on by default.
+  pub fn states(mut self, states: bool) -> Self {
+    self.states = states;
+    self
+  }
+
+  fn line(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+    index: usize,
+  ) -> std::fmt::Result
+  where
+    T: Debug,
+  {
+    let machine = self.machine;
+    let depth = machine.stack.len() - 1 - index;
+    let position = match depth {
+      0 => String::from("(top)"),
+      depth => format!("(depth {})", depth),
+    };
+    write!(f, "{} {:<9}", index, position)?;
+    if let Some(name) = machine.name_at(index).filter(|_| self.names) {
+      write!(f, "  {:?}", name)?;
+    }
+    if self.states {
+      if f.alternate() {
+        write!(f, "  {:#?}", machine.stack[index])?;
+      } else {
+        write!(f, "  {:?}", machine.stack[index])?;
+      }
+    }
+    if depth == 0 {
+      f.write_str("  [active]")?;
+    }
+    let id = machine.id_at(index);
+    if id.is_some_and(|id| machine.protected.contains(&id)) {
+      f.write_str("  [protected]")?;
+    }
+    writeln!(f)
+  }
+}
+
+impl<T: Debug> Debug for DebugStack<'_, T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let len = self.machine.stack.len();
+    if self.bottom_first {
+      (0..len).try_for_each(|index| self.line(f, index))
+    } else {
+      (0..len).rev().try_for_each(|index| self.line(f, index))
+    }
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Get something to print the stack with one state per line, topmost first. See [`DebugStack`].
+  pub fn debug_stack(&self) -> DebugStack<'_, T> {
+    DebugStack {
+      machine: self,
+      bottom_first: false,
+      names: true,
+      states: true,
+    }
+  }
+}
@@ -0,0 +1,58 @@
+use std::fmt::Display;
+
+use crate::StateMachine;
+
+/// Prints the stack of a [`StateMachine`], bottommost state first, like `world › pause › settings
+/// (active)`.
+///
+/// Get one with [`StateMachine::display`] to change how it looks. Displaying the machine itself
+/// uses the defaults.
+#[derive(Debug)]
+pub struct StackDisplay<'a, T> {
+  stack: &'a [T],
+  separator: &'a str,
+  active_suffix: &'a str,
+}
+
+impl<'a, T> StackDisplay<'a, T> {
+  /// Put this between each state, instead of `" › "`.
+  pub fn separator(mut self, separator: &'a str) -> Self {
+    self.separator = separator;
+    self
+  }
+
+  /// Put this after the active state, instead of `" (active)"`.
+  pub fn active_suffix(mut self, suffix: &'a str) -> Self {
+    self.active_suffix = suffix;
+    self
+  }
+}
+
+impl<T: Display> Display for StackDisplay<'_, T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for (i, state) in self.stack.iter().enumerate() {
+      if i != 0 {
+        f.write_str(self.separator)?;
+      }
+      write!(f, "{}", state)?;
+    }
+    f.write_str(self.active_suffix)
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Get something to print the stack with, which you can change the look of.
+  pub fn display(&self) -> StackDisplay<'_, T> {
+    StackDisplay {
+      stack: &self.stack,
+      separator: " › ",
+      active_suffix: " (active)",
+    }
+  }
+}
+
+impl<T: Display> Display for StateMachine<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.display().fmt(f)
+  }
+}
@@ -0,0 +1,114 @@
+use crate::{
+  StateMachine, Transition, TransitionError, TransitionOutcome, TransitionShape,
+};
+
+/// The states a transition removed, from [`StateMachine::apply_draining`].
+///
+/// This iterates over the removed states in the order they were on the stack, so the old
+/// active state comes last. Whatever isn't taken is dropped along with it.
+#[derive(Debug)]
+pub struct Drained<'a, T> {
+  shape: TransitionShape,
+  removed: std::vec::Drain<'a, T>,
+}
+
+impl<T> Drained<'_, T> {
+  /// Get what the transition did, with the removed states counted instead of collected.
+  pub fn shape(&self) -> &TransitionShape {
+    &self.shape
+  }
+}
+
+impl<T> Iterator for Drained<'_, T> {
+  type Item = T;
+
+  fn next(&mut self) -> Option<T> {
+    self.removed.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.removed.size_hint()
+  }
+}
+
+impl<T> DoubleEndedIterator for Drained<'_, T> {
+  fn next_back(&mut self) -> Option<T> {
+    self.removed.next_back()
+  }
+}
+
+impl<T> ExactSizeIterator for Drained<'_, T> {}
+
+impl<T> StateMachine<T> {
+  /// Apply the given transition, just like [`StateMachine::apply`], but hand back the states it
+  /// removed as an iterator instead of in the outcome.
+  ///
+  /// The machine keeps the `Vec` the states were removed into, and uses it again next time,
+  /// so a pop or a swap doesn't have to allocate once the machine has done one before. This only
+  /// works for one `Vec` at a time; a [`Transition::Sequence`] that removes states more than
+  /// once still allocates for the rest.
+  pub fn apply_draining(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<Drained<'_, T>, TransitionError> {
+    let outcome = self.apply(transition)?;
+    let shape = outcome.shape();
+    let mut removed = std::mem::take(&mut self.spare);
+    outcome.into_removed(&mut removed);
+    self.spare = removed;
+    Ok(Drained {
+      shape,
+      removed: self.spare.drain(..),
+    })
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Apply the given transition, just like [`StateMachine::apply`], but drop the states it
+  /// removed instead of handing them back, and only say what it did.
+  ///
+  /// The states are still collected while the transition is applied, in case it needs to be
+  /// rolled back, but into the same `Vec` as [`StateMachine::apply_draining`] uses. So once the
+  /// machine has removed states once, doing it again doesn't allocate, which adds up when there
+  /// are thousands of machines.
+  pub fn apply_discard(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<TransitionShape, TransitionError> {
+    let Drained { shape, removed } = self.apply_draining(transition)?;
+    drop(removed);
+    Ok(shape)
+  }
+}
+
+impl<T> TransitionOutcome<T> {
+  /// Move every state this outcome says was removed onto the end of `out`, in the order they
+  /// were on the stack.
+  fn into_removed(self, out: &mut Vec<T>) {
+    match self {
+      TransitionOutcome::Revealed(removed)
+      | TransitionOutcome::SwappedIn(removed, _)
+      | TransitionOutcome::Replaced(removed)
+      | TransitionOutcome::RemovedBelow(removed) => {
+        if out.is_empty() && out.capacity() < removed.capacity() {
+          // keep whichever has more room
+          *out = removed;
+        } else {
+          out.extend(removed);
+        }
+      }
+      TransitionOutcome::Removed(_, state)
+      | TransitionOutcome::SwappedAt(_, state) => out.push(state),
+      TransitionOutcome::Sequence(outcomes) => {
+        for outcome in outcomes {
+          outcome.into_removed(out);
+        }
+      }
+      TransitionOutcome::Conditional(_, outcome) => outcome.into_removed(out),
+      TransitionOutcome::None
+      | TransitionOutcome::Pushed(_)
+      | TransitionOutcome::Inserted(_)
+      | TransitionOutcome::Deferred => {}
+    }
+  }
+}
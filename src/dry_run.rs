@@ -0,0 +1,435 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::{
+  registry::Factory, Ids, StateId, StateMachine, Transition, TransitionError,
+  TransitionOutcome,
+};
+
+/// What a transition would do to a stack, without the states themselves.
+///
+/// This is what [`Transition::dry_run`] returns. It mirrors [`TransitionOutcome`], but has counts
+/// in place of the states that would be removed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TransitionShape {
+  /// Nothing would happen.
+  None,
+  /// A new state would be pushed on top of the stack, along with N things below it.
+  Pushed(usize),
+  /// This many states would be removed from on top of the stack, revealing the one under them.
+  Revealed(usize),
+  /// This many states would be removed, and then a new state pushed on top along with N things
+  /// below it.
+  SwappedIn(usize, usize),
+  /// The entire stack would be replaced. This is how many states it had.
+  Replaced(usize),
+  /// A state would be inserted underneath the active state, at the given index.
+  Inserted(usize),
+  /// A state underneath the active state would be removed from the given index.
+  Removed(usize),
+  /// A state underneath the active state at the given index would be replaced.
+  SwappedAt(usize),
+  /// This many states directly underneath the active state would be removed.
+  RemovedBelow(usize),
+  /// The shape of every transition in a sequence, flattened the same way as
+  /// [`TransitionOutcome::Sequence`].
+  Sequence(Vec<TransitionShape>),
+  /// The transition would be put on the queue for later.
+  Deferred,
+  /// The `bool` is `true` if the `then` branch would run, and this has the shape of whichever
+  /// branch it is.
+  Conditional(bool, Box<TransitionShape>),
+  /// There's no telling without running a closure that can only be run once, like the one in
+  /// [`Transition::FromFn`], or asking a predicate about a state that doesn't exist yet.
+  ///
+  /// The transition might work, or it might not.
+  Unknown,
+}
+
+impl<T> TransitionOutcome<T> {
+  /// Get the shape of this outcome, which is what [`Transition::dry_run`] would have said about it.
+  pub fn shape(&self) -> TransitionShape {
+    match self {
+      TransitionOutcome::None => TransitionShape::None,
+      TransitionOutcome::Pushed(under) => TransitionShape::Pushed(*under),
+      TransitionOutcome::Revealed(removed) => {
+        TransitionShape::Revealed(removed.len())
+      }
+      TransitionOutcome::SwappedIn(removed, under) => {
+        TransitionShape::SwappedIn(removed.len(), *under)
+      }
+      TransitionOutcome::Replaced(old) => TransitionShape::Replaced(old.len()),
+      TransitionOutcome::Inserted(index) => TransitionShape::Inserted(*index),
+      TransitionOutcome::Removed(index, _) => TransitionShape::Removed(*index),
+      TransitionOutcome::SwappedAt(index, _) => {
+        TransitionShape::SwappedAt(*index)
+      }
+      TransitionOutcome::RemovedBelow(removed) => {
+        TransitionShape::RemovedBelow(removed.len())
+      }
+      TransitionOutcome::Sequence(outcomes) => TransitionShape::Sequence(
+        outcomes.iter().map(TransitionOutcome::shape).collect(),
+      ),
+      TransitionOutcome::Deferred => TransitionShape::Deferred,
+      TransitionOutcome::Conditional(took_then, outcome) => {
+        TransitionShape::Conditional(*took_then, Box::new(outcome.shape()))
+      }
+    }
+  }
+}
+
+impl<T> Transition<T> {
+  /// Check whether this transition would work on the given state machine, and what it would do,
+  /// without changing anything. Whether the machine is frozen, its guards, and its protected states
+  /// get checked too.
+  ///
+  /// Closures that can only be run once don't get run, so sometimes the answer is
+  /// [`TransitionShape::Unknown`].
+  pub fn dry_run(
+    &self,
+    machine: &StateMachine<T>,
+  ) -> Result<TransitionShape, TransitionError> {
+    machine.check_writable()?;
+    machine.check_guards(self)?;
+    self.dry_run_on(&machine.stack, Some(machine.tracking_info()))
+  }
+
+  /// Dry run on a bare stack, with the ids and so on to go with it if there are any.
+  pub(crate) fn dry_run_on(
+    &self,
+    stack: &[T],
+    tracking: Option<Tracking<'_, T>>,
+  ) -> Result<TransitionShape, TransitionError> {
+    let mut sim = Sim {
+      stack,
+      tracking,
+      slots: (0..stack.len()).map(Some).collect(),
+    };
+    let Some(shape) = sim.run(self)? else {
+      return Ok(TransitionShape::Unknown);
+    };
+    if sim.lost_protected() {
+      return Err(TransitionError::Protected);
+    }
+    Ok(shape)
+  }
+}
+
+impl<T> StateMachine<T> {
+  fn tracking_info(&self) -> Tracking<'_, T> {
+    Tracking {
+      ids: &self.ids,
+      marks: &self.marks,
+      protected: &self.protected,
+      factories: &self.factories,
+    }
+  }
+
+  /// Dry run a batch of transitions, like [`dry_run_each`]. Guards aren't asked.
+  pub(crate) fn dry_run_all(
+    &self,
+    transitions: &[Transition<T>],
+  ) -> Result<(), (usize, TransitionError)> {
+    dry_run_each(transitions, &self.stack, Some(self.tracking_info()))
+  }
+}
+
+/// Dry run transitions one after another, and find the first one that would fail.
+///
+/// This stops at the first one there's no telling about, since then there's no telling
+/// about the ones after it either.
+pub(crate) fn dry_run_each<T>(
+  transitions: &[Transition<T>],
+  stack: &[T],
+  tracking: Option<Tracking<'_, T>>,
+) -> Result<(), (usize, TransitionError)> {
+  let mut sim = Sim {
+    stack,
+    tracking,
+    slots: (0..stack.len()).map(Some).collect(),
+  };
+  for (index, transition) in transitions.iter().enumerate() {
+    match sim.run(transition) {
+      Err(err) => return Err((index, err)),
+      Ok(_) if sim.lost_protected() => {
+        return Err((index, TransitionError::Protected))
+      }
+      Ok(Some(_)) => {}
+      Ok(None) => break,
+    }
+  }
+  Ok(())
+}
+
+/// Everything a [`StateMachine`] keeps track of besides its states.
+pub(crate) struct Tracking<'a, T> {
+  pub ids: &'a Ids,
+  pub marks: &'a HashMap<Cow<'static, str>, StateId>,
+  pub protected: &'a [StateId],
+  pub factories: &'a HashMap<Cow<'static, str>, Factory<T>>,
+}
+
+// Deriving these would want `T: Copy`.
+impl<T> Clone for Tracking<'_, T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T> Copy for Tracking<'_, T> {}
+
+/// A pretend stack. Each slot is the index of a state on the real stack, or `None` for a new state.
+struct Sim<'a, T> {
+  stack: &'a [T],
+  tracking: Option<Tracking<'a, T>>,
+  slots: Vec<Option<usize>>,
+}
+
+impl<T> Sim<'_, T> {
+  fn state(&self, slot: Option<usize>) -> Option<&T> {
+    slot.map(|index| &self.stack[index])
+  }
+
+  fn position_of(&self, id: StateId) -> Option<usize> {
+    let original = self.tracking?.ids.position_of(id)?;
+    self.slots.iter().rposition(|slot| *slot == Some(original))
+  }
+
+  fn lost_protected(&self) -> bool {
+    let Some(tracking) = self.tracking else {
+      return false;
+    };
+    tracking.protected.iter().any(|id| {
+      tracking.ids.position_of(*id).is_some() && self.position_of(*id).is_none()
+    })
+  }
+
+  /// `Ok(None)` means there's no telling what would happen.
+  fn run(
+    &mut self,
+    transition: &Transition<T>,
+  ) -> Result<Option<TransitionShape>, TransitionError> {
+    let len = self.slots.len();
+    let (pop_count, push_count) = match transition {
+      Transition::None => return Ok(Some(TransitionShape::None)),
+      Transition::Defer(_) => {
+        return match self.tracking {
+          Some(_) => Ok(Some(TransitionShape::Deferred)),
+          None => Err(TransitionError::NowhereToDefer),
+        }
+      }
+      Transition::Sequence(transitions) => {
+        let mut log = Vec::with_capacity(transitions.len());
+        for transition in transitions {
+          if !self.run_logged(transition, &mut log)? {
+            return Ok(None);
+          }
+        }
+        return Ok(Some(TransitionShape::Sequence(log)));
+      }
+      Transition::FromFn(_) => return Ok(None),
+      Transition::If {
+        cond,
+        then,
+        otherwise,
+      } => {
+        let Some(active) = self.state(*self.slots.last().unwrap()) else {
+          return Ok(None);
+        };
+        let took_then = cond(active);
+        let transition = if took_then { then } else { otherwise };
+        return Ok(self.run(transition)?.map(|shape| {
+          TransitionShape::Conditional(took_then, Box::new(shape))
+        }));
+      }
+      Transition::Push(_) | Transition::PushWith(_) => (0, 1),
+      Transition::PushKey(key) => match self.tracking {
+        Some(tracking) if tracking.factories.contains_key(key) => (0, 1),
+        _ => return Err(TransitionError::NoSuchKey),
+      },
+      Transition::PushMany(states) => match states.size_hint() {
+        (lo, Some(hi)) if lo == hi => {
+          self.slots.extend(std::iter::repeat_n(None, lo));
+          return Ok(Some(if lo == 0 {
+            TransitionShape::None
+          } else {
+            TransitionShape::Pushed(lo - 1)
+          }));
+        }
+        _ => return Ok(None),
+      },
+      Transition::Pop | Transition::PopWith(_) => (1, 0),
+      Transition::PopN(count) => (*count, 0),
+      Transition::PopUntil(pred) => {
+        let mut count = 0;
+        for slot in self.slots.iter().rev() {
+          match self.state(*slot) {
+            Some(state) if pred(state) => break,
+            Some(_) => count += 1,
+            None => return Ok(None),
+          }
+        }
+        (count.min(len - 1), 0)
+      }
+      Transition::PopTo(id) => match self.position_of(*id) {
+        Some(index) => (len - 1 - index, 0),
+        None => return Err(TransitionError::NoSuchState(*id)),
+      },
+      Transition::PopToMark(label) => {
+        let index = self
+          .tracking
+          .and_then(|tracking| tracking.marks.get(label))
+          .and_then(|id| self.position_of(*id));
+        match index {
+          Some(index) => (len - 1 - index, 0),
+          None => return Err(TransitionError::NoSuchMark),
+        }
+      }
+      Transition::Swap(_) | Transition::SwapAt(0, _) => (1, 1),
+      Transition::PopNAndPush(count, states) => (*count, states.len()),
+      Transition::PopNAndPushWith(count, _) => {
+        if *count > len {
+          return Err(TransitionError::PoppedTooMany {
+            popcnt: *count,
+            available: len - 1,
+          });
+        }
+        return Ok(None);
+      }
+      Transition::PopAll => (len - 1, 0),
+      Transition::Reset(_) => (len, 1),
+      Transition::PopAllAndPush(states) => {
+        if states.is_empty() {
+          return Err(TransitionError::EmptyReplacement);
+        }
+        (len, states.len())
+      }
+      Transition::ReplaceAll(states) => {
+        if states.is_empty() {
+          return Err(TransitionError::EmptyReplacement);
+        }
+        self.slots = vec![None; states.len()];
+        return Ok(Some(TransitionShape::Replaced(len)));
+      }
+      Transition::InsertAt(index, _) => {
+        if *index > len {
+          return Err(TransitionError::OutOfBounds { index: *index, len });
+        }
+        self.slots.insert(*index, None);
+        return Ok(Some(if *index == len {
+          TransitionShape::Pushed(0)
+        } else {
+          TransitionShape::Inserted(*index)
+        }));
+      }
+      Transition::RemoveAt(index) => return self.remove_at(*index).map(Some),
+      Transition::SwapAt(depth, _) => {
+        if *depth >= len {
+          return Err(TransitionError::OutOfBounds { index: *depth, len });
+        }
+        let index = len - 1 - depth;
+        self.slots[index] = None;
+        return Ok(Some(TransitionShape::SwappedAt(index)));
+      }
+      Transition::PopBelow(count) => {
+        let available = len - 1;
+        if *count > available {
+          return Err(TransitionError::PoppedTooMany {
+            popcnt: *count,
+            available,
+          });
+        }
+        self.slots.drain(available - count..available);
+        return Ok(Some(TransitionShape::RemovedBelow(*count)));
+      }
+      Transition::RemoveId(id) => match self.position_of(*id) {
+        Some(index) => return self.remove_at(index).map(Some),
+        None => return Err(TransitionError::NoSuchState(*id)),
+      },
+    };
+
+    self.pop_n_and_push(pop_count, push_count).map(Some)
+  }
+
+  /// Run the transition and log its shape, flattening sequences and conditionals like
+  /// [`Transition::apply_logged`] does. Returns `false` if there's no telling what would happen.
+  fn run_logged(
+    &mut self,
+    transition: &Transition<T>,
+    log: &mut Vec<TransitionShape>,
+  ) -> Result<bool, TransitionError> {
+    match transition {
+      Transition::Sequence(transitions) => {
+        for transition in transitions {
+          if !self.run_logged(transition, log)? {
+            return Ok(false);
+          }
+        }
+        Ok(true)
+      }
+      Transition::If {
+        cond,
+        then,
+        otherwise,
+      } => match self.state(*self.slots.last().unwrap()) {
+        Some(active) if cond(active) => self.run_logged(then, log),
+        Some(_) => self.run_logged(otherwise, log),
+        None => Ok(false),
+      },
+      transition => match self.run(transition)? {
+        Some(shape) => {
+          log.push(shape);
+          Ok(true)
+        }
+        None => Ok(false),
+      },
+    }
+  }
+
+  fn pop_n_and_push(
+    &mut self,
+    pop_count: usize,
+    push_count: usize,
+  ) -> Result<TransitionShape, TransitionError> {
+    let len = self.slots.len();
+    let allowed_popcnt = if push_count == 0 { len - 1 } else { len };
+    if pop_count > allowed_popcnt {
+      return Err(TransitionError::PoppedTooMany {
+        popcnt: pop_count,
+        available: allowed_popcnt,
+      });
+    }
+
+    self.slots.truncate(len - pop_count);
+    self.slots.extend(std::iter::repeat_n(None, push_count));
+    Ok(if push_count == 0 {
+      TransitionShape::Revealed(pop_count)
+    } else if pop_count == 0 {
+      TransitionShape::Pushed(push_count - 1)
+    } else {
+      TransitionShape::SwappedIn(pop_count, push_count - 1)
+    })
+  }
+
+  fn remove_at(
+    &mut self,
+    index: usize,
+  ) -> Result<TransitionShape, TransitionError> {
+    let len = self.slots.len();
+    if index >= len {
+      return Err(TransitionError::OutOfBounds { index, len });
+    }
+    if len == 1 {
+      return Err(TransitionError::PoppedTooMany {
+        popcnt: 1,
+        available: 0,
+      });
+    }
+    self.slots.remove(index);
+    Ok(if index == len - 1 {
+      TransitionShape::Revealed(1)
+    } else {
+      TransitionShape::Removed(index)
+    })
+  }
+}
@@ -0,0 +1,37 @@
+use crate::StateMachine;
+
+/// Whether a state dealt with an event it was offered by [`StateMachine::dispatch_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventResponse {
+  /// The state dealt with the event, so the states under it don't get offered it.
+  Handled,
+  /// The state didn't want the event, so offer it to the state under this one.
+  Pass,
+}
+
+/// Something that can go on a [`StateMachine`] and be offered events of type `E`,
+/// like input, with [`StateMachine::dispatch_event`].
+pub trait HandleEvent<E, Ctx = ()> {
+  /// Deal with the event, or pass it on to the state underneath.
+  fn handle_event(&mut self, event: &E, ctx: &mut Ctx) -> EventResponse;
+}
+
+impl<T> StateMachine<T> {
+  /// Offer an event to the active state, and then to each state under it in turn,
+  /// until one of them says it [handled](EventResponse::Handled) it.
+  ///
+  /// This returns the index of the state that handled it, counting up from the bottom,
+  /// or `None` if they all passed.
+  pub fn dispatch_event<E, Ctx>(
+    &mut self,
+    event: E,
+    ctx: &mut Ctx,
+  ) -> Option<usize>
+  where
+    T: HandleEvent<E, Ctx>,
+  {
+    self.stack.iter_mut().rposition(|state| {
+      state.handle_event(&event, ctx) == EventResponse::Handled
+    })
+  }
+}
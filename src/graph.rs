@@ -0,0 +1,167 @@
+use std::{
+  cell::RefCell,
+  collections::{BTreeMap, BTreeSet},
+  fmt::Write,
+  rc::Rc,
+};
+
+use crate::{Observer, StateMachine, TransitionEvent};
+
+/// An [`Observer`] that keeps track of which kinds of states led to which, so you can see
+/// how people actually get around your game.
+///
+/// States are grouped by a key function, like "which screen is this". Every time a transition
+/// changes the key of the active state, the edge from the old key to the new one is counted.
+/// [`TransitionGraph::to_dot`] draws the whole thing as a Graphviz graph, and
+/// [`TransitionGraph::to_mermaid`] as a Mermaid state diagram.
+pub struct TransitionGraph<T> {
+  key: Rc<dyn Fn(&T) -> String>,
+  seen: Rc<RefCell<Seen>>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+  first: Option<String>,
+  last: Option<String>,
+  nodes: BTreeSet<String>,
+  edges: BTreeMap<(String, String), usize>,
+}
+
+impl<T> TransitionGraph<T> {
+  /// Make an empty graph, which groups states with the given key function.
+  pub fn new(key: impl Fn(&T) -> String + 'static) -> Self {
+    Self {
+      key: Rc::new(key),
+      seen: Rc::default(),
+    }
+  }
+
+  /// Start from the machine's active state, which [observers](Observer) don't get told about.
+  ///
+  /// Without this, the first transition the graph sees only tells it where it is, so the edge
+  /// out of the first state is missing.
+  pub fn start(&self, machine: &StateMachine<T>) {
+    self.record((self.key)(machine.active()));
+  }
+
+  /// Get how many times the active state went from the first key to the second.
+  pub fn count(&self, from: &str, to: &str) -> usize {
+    let seen = self.seen.borrow();
+    seen
+      .edges
+      .get(&(from.to_owned(), to.to_owned()))
+      .copied()
+      .unwrap_or(0)
+  }
+
+  /// Get every edge that was seen along with how many times, sorted by key.
+  pub fn edges(&self) -> Vec<(String, String, usize)> {
+    let seen = self.seen.borrow();
+    seen
+      .edges
+      .iter()
+      .map(|((from, to), count)| (from.clone(), to.clone(), *count))
+      .collect()
+  }
+
+  /// Forget everything that was seen. The next transition starts the graph off again.
+  pub fn clear(&self) {
+    *self.seen.borrow_mut() = Seen::default();
+  }
+
+  /// Draw the graph in Graphviz's DOT language, with every edge labeled by how many times
+  /// it was seen.
+  pub fn to_dot(&self) -> String {
+    let seen = self.seen.borrow();
+    let mut out = String::from("digraph states {\n");
+    for node in &seen.nodes {
+      let _ = writeln!(out, "  {};", quote(node));
+    }
+    for ((from, to), count) in &seen.edges {
+      let _ = writeln!(
+        out,
+        "  {} -> {} [label=\"{}\"];",
+        quote(from),
+        quote(to),
+        count
+      );
+    }
+    out.push_str("}\n");
+    out
+  }
+
+  /// Draw the graph as a Mermaid `stateDiagram-v2`, with every edge labeled by how many times
+  /// it was seen, so it can be pasted straight into Markdown.
+  ///
+  /// The key the graph started from is marked as the start state.
+  pub fn to_mermaid(&self) -> String {
+    let seen = self.seen.borrow();
+    let ids: BTreeMap<_, _> = seen
+      .nodes
+      .iter()
+      .enumerate()
+      .map(|(index, node)| (node, format!("s{}", index)))
+      .collect();
+    let mut out = String::from("stateDiagram-v2\n");
+    for (node, id) in &ids {
+      let label = node.replace('"', "#quot;");
+      let _ = writeln!(out, "  state \"{}\" as {}", label, id);
+    }
+    if let Some(first) = &seen.first {
+      let _ = writeln!(out, "  [*] --> {}", ids[first]);
+    }
+    for ((from, to), count) in &seen.edges {
+      let _ = writeln!(out, "  {} --> {}: {}", ids[from], ids[to], count);
+    }
+    out
+  }
+
+  /// Tell the graph the active state has this key now, as if a transition had just been applied.
+  ///
+  /// This is for building a graph from something other than a live machine, like a saved
+  /// [`AuditTrail`](crate::AuditTrail).
+  pub fn record(&self, key: impl Into<String>) {
+    let key = key.into();
+    let mut seen = self.seen.borrow_mut();
+    if seen.first.is_none() {
+      seen.first = Some(key.clone());
+    }
+    if let Some(last) = seen.last.take() {
+      if last != key {
+        *seen.edges.entry((last, key.clone())).or_insert(0) += 1;
+      }
+    }
+    seen.nodes.insert(key.clone());
+    seen.last = Some(key);
+  }
+}
+
+impl<T> Observer<T> for TransitionGraph<T> {
+  fn observe(&self, event: &TransitionEvent<'_, T>) {
+    if event.result.is_ok() {
+      self.record((self.key)(event.active));
+    }
+  }
+}
+
+impl<T> Clone for TransitionGraph<T> {
+  fn clone(&self) -> Self {
+    Self {
+      key: self.key.clone(),
+      seen: self.seen.clone(),
+    }
+  }
+}
+
+impl<T> std::fmt::Debug for TransitionGraph<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TransitionGraph")
+      .field("seen", &self.seen.borrow())
+      .finish()
+  }
+}
+
+/// Quote a key as a DOT id.
+fn quote(key: &str) -> String {
+  format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+}
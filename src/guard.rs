@@ -0,0 +1,33 @@
+use crate::Transition;
+
+/// A rule that gets to look at every transition before it's applied to a
+/// [`StateMachine`](crate::StateMachine), and veto it.
+///
+/// If any guard on a machine rejects a transition,
+/// [`StateMachine::apply`](crate::StateMachine::apply) fails with
+/// [`TransitionError::Rejected`](crate::TransitionError::Rejected) and nothing changes.
+///
+/// This is implemented for closures with the same signature as [`Guard::check`].
+pub trait Guard<T> {
+  /// Check the transition against the stack it's about to be applied to.
+  ///
+  /// Return `Err` with a reason to stop it from happening.
+  fn check(
+    &self,
+    stack: &[T],
+    transition: &Transition<T>,
+  ) -> Result<(), &'static str>;
+}
+
+impl<T, F> Guard<T> for F
+where
+  F: Fn(&[T], &Transition<T>) -> Result<(), &'static str>,
+{
+  fn check(
+    &self,
+    stack: &[T],
+    transition: &Transition<T>,
+  ) -> Result<(), &'static str> {
+    self(stack, transition)
+  }
+}
@@ -0,0 +1,108 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{StateMachine, TransitionKind, TransitionOutcome};
+
+/// One transition in the history of a [`StateMachine`]. See [`StateMachine::record_history`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HistoryEntry {
+  /// Which transition it was.
+  pub kind: TransitionKind,
+  /// How many states it removed.
+  pub removed: usize,
+  /// How many states were on the stack afterwards.
+  pub depth: usize,
+  /// When it was applied.
+  pub at: Instant,
+}
+
+/// The last few transitions applied to a machine, oldest first.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+  entries: VecDeque<HistoryEntry>,
+  capacity: usize,
+}
+
+impl History {
+  fn push(&mut self, entry: HistoryEntry) {
+    if self.capacity == 0 {
+      return;
+    }
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(entry);
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Start keeping track of the last `capacity` transitions that were applied successfully,
+  /// so you can look back at what the stack did with [`StateMachine::history`].
+  ///
+  /// If the machine was already recording, this keeps the newest entries that fit.
+  pub fn record_history(&mut self, capacity: usize) {
+    let history = self.history.get_or_insert_with(|| History {
+      entries: VecDeque::with_capacity(capacity),
+      capacity,
+    });
+    history.capacity = capacity;
+    while history.entries.len() > capacity {
+      history.entries.pop_front();
+    }
+  }
+
+  /// Stop keeping track of transitions, and throw away the history.
+  pub fn stop_recording_history(&mut self) {
+    self.history = None;
+  }
+
+  /// Iterate over the recorded transitions, oldest first.
+  ///
+  /// This is empty unless the machine is [recording](StateMachine::record_history).
+  pub fn history(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+    self.history.iter().flat_map(|history| &history.entries)
+  }
+
+  /// Throw away the recorded transitions, but keep recording.
+  pub fn clear_history(&mut self) {
+    if let Some(history) = &mut self.history {
+      history.entries.clear();
+    }
+  }
+
+  pub(crate) fn record(
+    &mut self,
+    kind: TransitionKind,
+    outcome: &TransitionOutcome<T>,
+  ) {
+    let depth = self.stack.len();
+    if let Some(history) = &mut self.history {
+      history.push(HistoryEntry {
+        kind,
+        removed: outcome.removed_count(),
+        depth,
+        at: Instant::now(),
+      });
+    }
+  }
+}
+
+impl<T> TransitionOutcome<T> {
+  /// How many states this outcome says were removed.
+  pub(crate) fn removed_count(&self) -> usize {
+    match self {
+      TransitionOutcome::Revealed(removed)
+      | TransitionOutcome::SwappedIn(removed, _)
+      | TransitionOutcome::Replaced(removed)
+      | TransitionOutcome::RemovedBelow(removed) => removed.len(),
+      TransitionOutcome::Removed(..) | TransitionOutcome::SwappedAt(..) => 1,
+      TransitionOutcome::Sequence(outcomes) => {
+        outcomes.iter().map(TransitionOutcome::removed_count).sum()
+      }
+      TransitionOutcome::Conditional(_, outcome) => outcome.removed_count(),
+      TransitionOutcome::None
+      | TransitionOutcome::Pushed(_)
+      | TransitionOutcome::Inserted(_)
+      | TransitionOutcome::Deferred => 0,
+    }
+  }
+}
@@ -0,0 +1,143 @@
+use std::{borrow::Cow, time::Instant};
+
+use crate::{StateMachine, Transition};
+
+/// Something that makes a state for a test button.
+type MakeState<'a, T> = Box<dyn FnMut() -> T + 'a>;
+
+/// An egui widget for poking at a [`StateMachine`] while the game is running.
+///
+/// It shows:
+///
+/// - the stack, topmost first, with each state's [name](StateMachine::name_states) or id,
+///   and how long it's been on the stack, active, and paused (see [`StateMachine::track_time`])
+/// - the [history](StateMachine::record_history), newest first, if the machine is recording it
+/// - a button to pop the active state, and one to push each [registered](StateMachine::register)
+///   state, plus any test states given with [`StackInspector::test_state`]
+///
+/// If a button's transition fails, the error is shown under the buttons.
+pub struct StackInspector<'a, T> {
+  machine: &'a mut StateMachine<T>,
+  test_states: Vec<(Cow<'static, str>, MakeState<'a, T>)>,
+  history: usize,
+}
+
+impl<'a, T> StackInspector<'a, T> {
+  /// Make an inspector for the machine.
+  pub fn new(machine: &'a mut StateMachine<T>) -> Self {
+    Self {
+      machine,
+      test_states: Vec::new(),
+      history: 10,
+    }
+  }
+
+  /// Add a button that pushes a state made by the function.
+  pub fn test_state(
+    mut self,
+    label: impl Into<Cow<'static, str>>,
+    make: impl FnMut() -> T + 'a,
+  ) -> Self {
+    self.test_states.push((label.into(), Box::new(make)));
+    self
+  }
+
+  /// Show at most this many history entries. The default is 10.
+  pub fn history(mut self, count: usize) -> Self {
+    self.history = count;
+    self
+  }
+}
+
+impl<T> egui::Widget for StackInspector<'_, T> {
+  fn ui(mut self, ui: &mut egui::Ui) -> egui::Response {
+    let error_id = ui.id().with("gerrymander inspector error");
+    ui.vertical(|ui| {
+      ui.heading(format!("Stack ({})", self.machine.stack.len()));
+      egui::Grid::new("gerrymander stack")
+        .striped(true)
+        .show(ui, |ui| {
+          for header in ["#", "State", "On stack", "Active", "Paused"] {
+            ui.strong(header);
+          }
+          ui.end_row();
+          let now = Instant::now();
+          for index in (0..self.machine.stack.len()).rev() {
+            ui.label(index.to_string());
+            let name = self.machine.name_at(index).unwrap_or_else(|| {
+              format!("#{}", self.machine.id_at(index).unwrap().0)
+            });
+            if index == self.machine.stack.len() - 1 {
+              ui.strong(name);
+            } else {
+              ui.label(name);
+            }
+            if let Some(times) = self.machine.times_at(index) {
+              let on_stack = now.duration_since(times.pushed_at);
+              ui.label(format!("{:.1}s", on_stack.as_secs_f32()));
+              ui.label(format!("{:.1}s", times.active.as_secs_f32()));
+              ui.label(format!("{:.1}s", times.paused.as_secs_f32()));
+            }
+            ui.end_row();
+          }
+        });
+
+      let mut transition = None;
+      ui.horizontal_wrapped(|ui| {
+        if ui
+          .add_enabled(self.machine.stack.len() > 1, egui::Button::new("Pop"))
+          .clicked()
+        {
+          transition = Some(Transition::Pop);
+        }
+        let mut keys: Vec<_> = self.machine.factories.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+          if ui.button(format!("Push {}", key)).clicked() {
+            transition = Some(Transition::PushKey(key));
+          }
+        }
+        for (label, make) in &mut self.test_states {
+          if ui.button(format!("Push {}", label)).clicked() {
+            transition = Some(Transition::Push(make()));
+          }
+        }
+      });
+      if let Some(transition) = transition {
+        let error = self.machine.apply(transition).err().map(|e| e.to_string());
+        ui.data_mut(|data| match error {
+          Some(error) => data.insert_temp(error_id, error),
+          None => data.remove::<String>(error_id),
+        });
+      }
+      if let Some(error) = ui.data(|data| data.get_temp::<String>(error_id)) {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+      }
+
+      if self.machine.history.is_some() {
+        ui.separator();
+        ui.heading("History");
+        let now = Instant::now();
+        for entry in self.machine.history().rev().take(self.history) {
+          ui.label(format!(
+            "{:.1}s ago: {}, removed {}, depth {}",
+            now.duration_since(entry.at).as_secs_f32(),
+            entry.kind.name(),
+            entry.removed,
+            entry.depth
+          ));
+        }
+      }
+    })
+    .response
+  }
+}
+
+impl<T> std::fmt::Debug for StackInspector<'_, T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("StackInspector")
+      .field("test_states", &self.test_states.len())
+      .field("history", &self.history)
+      .finish()
+  }
+}
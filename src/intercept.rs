@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use crate::{
+  State, StateMachine, Transition, TransitionError, TransitionOutcome,
+};
+
+/// What an [`Interceptor`] decided to do with a transition.
+#[derive(Debug)]
+pub enum Verdict<T> {
+  /// Go ahead and apply this transition. It doesn't have to be the one that was intercepted.
+  Apply(Transition<T>),
+  /// Hold on to this transition until it's [released](StateMachine::release_next).
+  Hold(Transition<T>),
+}
+
+/// Something that gets to see every transition before it's applied to a [`StateMachine`],
+/// and decide whether it goes ahead, gets changed, or waits.
+///
+/// This is for stepping through transitions one at a time while debugging: hold everything,
+/// look at what's queued up, and [release](StateMachine::release_next) them one by one.
+///
+/// Held transitions make [`StateMachine::apply`] succeed with [`TransitionOutcome::Deferred`],
+/// without guards or observers seeing them yet. They see them when they're released.
+/// Transitions held from [`StateMachine::apply_with_hooks`] and the methods built on it, like
+/// [`StateMachine::tick`], only call the hooks if they're released with
+/// [`StateMachine::release_next_with_hooks`].
+/// Helpers that hand back what a transition removed, like [`StateMachine::pop`], can't do
+/// that for a held transition, so they fail with [`TransitionError::Held`] instead.
+///
+/// This is implemented for closures with the same signature as [`Interceptor::intercept`].
+pub trait Interceptor<T> {
+  /// Look at a transition that's about to be applied to the stack, and decide what happens to it.
+  fn intercept(&mut self, stack: &[T], transition: Transition<T>)
+    -> Verdict<T>;
+}
+
+impl<T, F> Interceptor<T> for F
+where
+  F: FnMut(&[T], Transition<T>) -> Verdict<T>,
+{
+  fn intercept(
+    &mut self,
+    stack: &[T],
+    transition: Transition<T>,
+  ) -> Verdict<T> {
+    self(stack, transition)
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Set the interceptor, which sees every transition from now on, replacing the old one.
+  ///
+  /// Unlike guards, the interceptor isn't shared with clones of the machine.
+  pub fn set_interceptor(
+    &mut self,
+    interceptor: impl Interceptor<T> + 'static,
+  ) {
+    self.interceptor = Some(Box::new(interceptor));
+  }
+
+  /// Remove the interceptor. Transitions it held stay held until they're released.
+  pub fn clear_interceptor(&mut self) {
+    self.interceptor = None;
+  }
+
+  /// Iterate over the held transitions, next to be released first.
+  pub fn held(&self) -> impl ExactSizeIterator<Item = &Transition<T>> {
+    self.held.iter().map(|holding| &holding.transition)
+  }
+
+  /// Apply the oldest held transition, without asking the interceptor about it again.
+  ///
+  /// This never calls any hooks, even if the transition was held from
+  /// [`StateMachine::apply_with_hooks`]; use [`StateMachine::release_next_with_hooks`] for that.
+  /// This returns `None` if nothing's being held.
+  pub fn release_next(
+    &mut self,
+  ) -> Option<Result<TransitionOutcome<T>, TransitionError>> {
+    let holding = self.held.pop_front()?;
+    let interceptor = self.interceptor.take();
+    let res = self.apply(holding.transition);
+    self.interceptor = interceptor;
+    Some(res)
+  }
+
+  /// Apply the oldest held transition like [`StateMachine::release_next`], but if it was held
+  /// from [`StateMachine::apply_with_hooks`], apply it with the hooks.
+  pub fn release_next_with_hooks<Ctx>(
+    &mut self,
+    ctx: &mut Ctx,
+  ) -> Option<Result<TransitionOutcome<T>, TransitionError>>
+  where
+    T: State<Ctx>,
+  {
+    let holding = self.held.pop_front()?;
+    let interceptor = self.interceptor.take();
+    let res = if holding.hooks {
+      self.apply_with_hooks(holding.transition, ctx)
+    } else {
+      self.apply(holding.transition)
+    };
+    self.interceptor = interceptor;
+    Some(res)
+  }
+
+  /// Apply every held transition in order with [`StateMachine::release_next`], and return the
+  /// result of each one. The interceptor doesn't see them again, so none of them get held again.
+  pub fn release_all(
+    &mut self,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>> {
+    std::iter::from_fn(|| self.release_next()).collect()
+  }
+
+  /// Apply every held transition in order with [`StateMachine::release_next_with_hooks`], and
+  /// return the result of each one.
+  pub fn release_all_with_hooks<Ctx>(
+    &mut self,
+    ctx: &mut Ctx,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>>
+  where
+    T: State<Ctx>,
+  {
+    std::iter::from_fn(|| self.release_next_with_hooks(ctx)).collect()
+  }
+
+  /// Throw away every held transition.
+  pub fn discard_held(&mut self) {
+    self.held.clear();
+  }
+
+  /// Hand the transition to the interceptor, if there is one. If it's held, this returns `None`.
+  ///
+  /// `hooks` is whether it came from [`StateMachine::apply_with_hooks`], so it can be released the
+  /// same way.
+  pub(crate) fn intercept(
+    &mut self,
+    transition: Transition<T>,
+    hooks: bool,
+  ) -> Option<Transition<T>> {
+    let Some(interceptor) = &mut self.interceptor else {
+      return Some(transition);
+    };
+    match interceptor.intercept(&self.stack, transition) {
+      Verdict::Apply(transition) => Some(transition),
+      Verdict::Hold(transition) => {
+        self.held.push_back(Holding { transition, hooks });
+        None
+      }
+    }
+  }
+}
+
+/// Transitions held by an [`Interceptor`], oldest first.
+pub(crate) type Held<T> = VecDeque<Holding<T>>;
+
+/// A held transition, and whether it came from [`StateMachine::apply_with_hooks`].
+#[derive(Debug)]
+pub(crate) struct Holding<T> {
+  transition: Transition<T>,
+  hooks: bool,
+}
@@ -0,0 +1,91 @@
+use std::rc::Rc;
+
+use crate::{StateId, StateMachine, TransitionError, TransitionOutcome};
+
+/// Something that must always be true about the stack of a [`StateMachine`], like
+/// "there's never more than one pause menu".
+///
+/// Invariants are checked after every transition applied with [`StateMachine::apply`] and the
+/// methods built on it, in debug builds or with the `invariants` feature. What happens when one
+/// fails depends on [`StateMachine::on_violation`]; by default, it panics with the message.
+///
+/// This is implemented for closures with the same signature as [`Invariant::check`].
+pub trait Invariant<T> {
+  /// Check the stack, which a transition was just applied to.
+  ///
+  /// Return `Err` with a message saying what's wrong.
+  fn check(&self, stack: &[T]) -> Result<(), String>;
+}
+
+impl<T, F> Invariant<T> for F
+where
+  F: Fn(&[T]) -> Result<(), String>,
+{
+  fn check(&self, stack: &[T]) -> Result<(), String> {
+    self(stack)
+  }
+}
+
+/// What happens when an [`Invariant`] fails. See [`StateMachine::on_violation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnViolation {
+  /// Panic with the invariant's message, right where it happened.
+  #[default]
+  Panic,
+  /// Undo the transition, and fail with [`TransitionError::InvariantViolated`].
+  Reject,
+}
+
+impl<T> StateMachine<T> {
+  /// Add an invariant, which gets checked after every transition from now on.
+  ///
+  /// Invariants are only checked in debug builds, unless the `invariants` feature is on.
+  pub fn add_invariant(&mut self, invariant: impl Invariant<T> + 'static) {
+    self.invariants.push(Rc::new(invariant));
+  }
+
+  /// Remove every invariant.
+  pub fn clear_invariants(&mut self) {
+    self.invariants.clear();
+  }
+
+  /// Choose what happens when an invariant fails.
+  pub fn on_violation(&mut self, on_violation: OnViolation) {
+    self.on_violation = on_violation;
+  }
+
+  /// Whether there are invariants to check after each transition.
+  pub(crate) fn checks_invariants(&self) -> bool {
+    cfg!(any(debug_assertions, feature = "invariants"))
+      && !self.invariants.is_empty()
+  }
+
+  /// Check the invariants against the stack a transition just left behind, and deal with it
+  /// if one fails.
+  ///
+  /// To undo the transition, this needs the ids there were before it, and how many deferred
+  /// transitions there were.
+  pub(crate) fn check_invariants(
+    &mut self,
+    outcome: TransitionOutcome<T>,
+    old_ids: Vec<StateId>,
+    old_deferred: usize,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    let Some(message) = self
+      .invariants
+      .iter()
+      .find_map(|invariant| invariant.check(&self.stack).err())
+    else {
+      return Ok(outcome);
+    };
+    match self.on_violation {
+      OnViolation::Panic => panic!("stack invariant violated: {}", message),
+      OnViolation::Reject => {
+        outcome.undo(&mut self.stack);
+        self.ids.ids = old_ids;
+        self.deferred.transitions.truncate(old_deferred);
+        Err(TransitionError::InvariantViolated)
+      }
+    }
+  }
+}
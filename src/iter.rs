@@ -0,0 +1,112 @@
+use crate::StateMachine;
+
+/// An iterator over the states from topmost (active) to bottommost.
+///
+/// Get one with [`StateMachine::iter_top_down`].
+#[derive(Debug, Clone)]
+pub struct TopDown<'a, T> {
+  inner: std::iter::Rev<std::slice::Iter<'a, T>>,
+}
+
+impl<'a, T> Iterator for TopDown<'a, T> {
+  type Item = &'a T;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.inner.size_hint()
+  }
+}
+
+impl<T> DoubleEndedIterator for TopDown<'_, T> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.inner.next_back()
+  }
+}
+
+impl<T> ExactSizeIterator for TopDown<'_, T> {}
+
+/// An iterator over the states from bottommost to topmost (active).
+///
+/// Get one with [`StateMachine::iter_bottom_up`].
+#[derive(Debug, Clone)]
+pub struct BottomUp<'a, T> {
+  inner: std::slice::Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for BottomUp<'a, T> {
+  type Item = &'a T;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.inner.size_hint()
+  }
+}
+
+impl<T> DoubleEndedIterator for BottomUp<'_, T> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.inner.next_back()
+  }
+}
+
+impl<T> ExactSizeIterator for BottomUp<'_, T> {}
+
+/// An iterator over every state and the state directly above it, as `(parent, child)` pairs,
+/// from the bottom of the stack up. Reverse it to go from the top down.
+///
+/// Get one with [`StateMachine::pairs`].
+#[derive(Debug, Clone)]
+pub struct Pairs<'a, T> {
+  windows: std::slice::Windows<'a, T>,
+}
+
+impl<'a, T> Iterator for Pairs<'a, T> {
+  type Item = (&'a T, &'a T);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.windows.next().map(|pair| (&pair[0], &pair[1]))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.windows.size_hint()
+  }
+}
+
+impl<T> DoubleEndedIterator for Pairs<'_, T> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.windows.next_back().map(|pair| (&pair[0], &pair[1]))
+  }
+}
+
+impl<T> ExactSizeIterator for Pairs<'_, T> {}
+
+impl<T> StateMachine<T> {
+  /// Iterate over the states from topmost (active) to bottommost.
+  pub fn iter_top_down(&self) -> TopDown<'_, T> {
+    TopDown {
+      inner: self.stack.iter().rev(),
+    }
+  }
+
+  /// Iterate over the states from bottommost to topmost (active).
+  pub fn iter_bottom_up(&self) -> BottomUp<'_, T> {
+    BottomUp {
+      inner: self.stack.iter(),
+    }
+  }
+
+  /// Iterate over every state and the state directly above it, as `(parent, child)` pairs,
+  /// from the bottom of the stack up.
+  ///
+  /// A stack with only one state has no pairs.
+  pub fn pairs(&self) -> Pairs<'_, T> {
+    Pairs {
+      windows: self.stack.windows(2),
+    }
+  }
+}
@@ -0,0 +1,133 @@
+use crate::Transition;
+
+/// Which variant of [`Transition`] a transition is, without anything in it.
+///
+/// This is handy for logging, since it's `Copy` and doesn't care what the states are.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransitionKind {
+  /// [`Transition::None`]
+  None,
+  /// [`Transition::Push`]
+  Push,
+  /// [`Transition::Pop`]
+  Pop,
+  /// [`Transition::PushMany`]
+  PushMany,
+  /// [`Transition::PopN`]
+  PopN,
+  /// [`Transition::PopUntil`]
+  PopUntil,
+  /// [`Transition::PopTo`]
+  PopTo,
+  /// [`Transition::PopToMark`]
+  PopToMark,
+  /// [`Transition::PushKey`]
+  PushKey,
+  /// [`Transition::RemoveId`]
+  RemoveId,
+  /// [`Transition::Swap`]
+  Swap,
+  /// [`Transition::PopNAndPush`]
+  PopNAndPush,
+  /// [`Transition::PopAll`]
+  PopAll,
+  /// [`Transition::Reset`]
+  Reset,
+  /// [`Transition::ReplaceAll`]
+  ReplaceAll,
+  /// [`Transition::PopAllAndPush`]
+  PopAllAndPush,
+  /// [`Transition::InsertAt`]
+  InsertAt,
+  /// [`Transition::RemoveAt`]
+  RemoveAt,
+  /// [`Transition::SwapAt`]
+  SwapAt,
+  /// [`Transition::PopBelow`]
+  PopBelow,
+  /// [`Transition::Sequence`]
+  Sequence,
+  /// [`Transition::If`]
+  If,
+  /// [`Transition::FromFn`]
+  FromFn,
+  /// [`Transition::PushWith`]
+  PushWith,
+  /// [`Transition::PopNAndPushWith`]
+  PopNAndPushWith,
+  /// [`Transition::PopWith`]
+  PopWith,
+  /// [`Transition::Defer`]
+  Defer,
+}
+
+impl TransitionKind {
+  /// Get the name of the variant, like `"PopN"`.
+  pub fn name(self) -> &'static str {
+    match self {
+      TransitionKind::None => "None",
+      TransitionKind::Push => "Push",
+      TransitionKind::Pop => "Pop",
+      TransitionKind::PushMany => "PushMany",
+      TransitionKind::PopN => "PopN",
+      TransitionKind::PopUntil => "PopUntil",
+      TransitionKind::PopTo => "PopTo",
+      TransitionKind::PopToMark => "PopToMark",
+      TransitionKind::PushKey => "PushKey",
+      TransitionKind::RemoveId => "RemoveId",
+      TransitionKind::Swap => "Swap",
+      TransitionKind::PopNAndPush => "PopNAndPush",
+      TransitionKind::PopAll => "PopAll",
+      TransitionKind::Reset => "Reset",
+      TransitionKind::ReplaceAll => "ReplaceAll",
+      TransitionKind::PopAllAndPush => "PopAllAndPush",
+      TransitionKind::InsertAt => "InsertAt",
+      TransitionKind::RemoveAt => "RemoveAt",
+      TransitionKind::SwapAt => "SwapAt",
+      TransitionKind::PopBelow => "PopBelow",
+      TransitionKind::Sequence => "Sequence",
+      TransitionKind::If => "If",
+      TransitionKind::FromFn => "FromFn",
+      TransitionKind::PushWith => "PushWith",
+      TransitionKind::PopNAndPushWith => "PopNAndPushWith",
+      TransitionKind::PopWith => "PopWith",
+      TransitionKind::Defer => "Defer",
+    }
+  }
+}
+
+impl<T> Transition<T> {
+  /// Get which variant of [`Transition`] this is.
+  pub fn kind(&self) -> TransitionKind {
+    match self {
+      Transition::None => TransitionKind::None,
+      Transition::Push(..) => TransitionKind::Push,
+      Transition::Pop => TransitionKind::Pop,
+      Transition::PushMany(..) => TransitionKind::PushMany,
+      Transition::PopN(..) => TransitionKind::PopN,
+      Transition::PopUntil(..) => TransitionKind::PopUntil,
+      Transition::PopTo(..) => TransitionKind::PopTo,
+      Transition::PopToMark(..) => TransitionKind::PopToMark,
+      Transition::PushKey(..) => TransitionKind::PushKey,
+      Transition::RemoveId(..) => TransitionKind::RemoveId,
+      Transition::Swap(..) => TransitionKind::Swap,
+      Transition::PopNAndPush(..) => TransitionKind::PopNAndPush,
+      Transition::PopAll => TransitionKind::PopAll,
+      Transition::Reset(..) => TransitionKind::Reset,
+      Transition::ReplaceAll(..) => TransitionKind::ReplaceAll,
+      Transition::PopAllAndPush(..) => TransitionKind::PopAllAndPush,
+      Transition::InsertAt(..) => TransitionKind::InsertAt,
+      Transition::RemoveAt(..) => TransitionKind::RemoveAt,
+      Transition::SwapAt(..) => TransitionKind::SwapAt,
+      Transition::PopBelow(..) => TransitionKind::PopBelow,
+      Transition::Sequence(..) => TransitionKind::Sequence,
+      Transition::If { .. } => TransitionKind::If,
+      Transition::FromFn(..) => TransitionKind::FromFn,
+      Transition::PushWith(..) => TransitionKind::PushWith,
+      Transition::PopNAndPushWith(..) => TransitionKind::PopNAndPushWith,
+      Transition::PopWith(..) => TransitionKind::PopWith,
+      Transition::Defer(..) => TransitionKind::Defer,
+    }
+  }
+}
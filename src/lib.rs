@@ -1,28 +1,229 @@
 #![doc = include_str!("../README.md")]
 
-use std::{fmt::Display, num::NonZeroUsize};
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+mod audit;
+mod builder;
+mod checkpoint;
+mod cursor;
+mod debug_stack;
+mod display;
+mod drained;
+mod dry_run;
+mod event;
+mod graph;
+mod guard;
+mod history;
+#[cfg(feature = "egui")]
+mod inspector;
+mod intercept;
+mod invariant;
+mod iter;
+mod kind;
+#[cfg(feature = "log")]
+mod logging;
+mod mail;
+#[cfg(feature = "metrics")]
+mod metered;
+mod modal;
+mod naming;
+mod observer;
+mod registry;
+mod replay;
+mod requests;
+mod runner;
+mod snapshot;
+mod state;
+mod stats;
+#[cfg(feature = "time")]
+mod timed;
+#[cfg(feature = "time")]
+mod timing;
+#[cfg(feature = "tracing")]
+mod trace;
+mod undo;
+mod view;
+
+pub use audit::{AuditEntry, AuditTrail};
+pub use builder::TransitionBuilder;
+pub use cursor::StackCursor;
+pub use debug_stack::DebugStack;
+pub use display::StackDisplay;
+pub use drained::Drained;
+pub use dry_run::TransitionShape;
+pub use event::{EventResponse, HandleEvent};
+/// Implement [`State`] on an enum of states, by handing every method to whichever variant it is.
+///
+/// Every variant needs to hold exactly one state, like `Screen::Title(TitleScreen)`.
+/// Anything else is a compile error. The transitions from [`State::update`] are converted with
+/// [`Transition::map`], so returning a [`Transition::FromFn`] panics, naming the variant.
+#[cfg(feature = "derive")]
+pub use gerrymander_derive::State;
+pub use graph::TransitionGraph;
+pub use guard::Guard;
+pub use history::HistoryEntry;
+#[cfg(feature = "egui")]
+pub use inspector::StackInspector;
+pub use intercept::{Interceptor, Verdict};
+pub use invariant::{Invariant, OnViolation};
+pub use iter::{BottomUp, Pairs, TopDown};
+pub use kind::TransitionKind;
+pub use mail::{Mailbox, Receive, Recipient};
+pub use modal::{ModalHandle, ModalStatus};
+pub use observer::{Observer, TransitionEvent};
+pub use replay::{Recorder, ReplayError, Script, ScriptStep};
+pub use requests::TransitionRequests;
+pub use runner::Runner;
+pub use snapshot::StackSnapshot;
+pub use state::State;
+pub use stats::{KindStats, StateStats};
+#[cfg(feature = "time")]
+pub use timing::StateTimes;
+pub use undo::UndoableStateMachine;
+pub use view::StackView;
+
+use std::{
+  any::Any,
+  borrow::Cow,
+  collections::{HashMap, VecDeque},
+  fmt::Display,
+  num::NonZeroUsize,
+  rc::Rc,
+};
 
 /// Wrapper for a stack of states.
 ///
 /// The stack will never be empty.
+///
+/// Every state gets a [`StateId`] when it goes onto the stack. Only the states themselves are
+/// serialized; a deserialized machine hands out new ids, and starts with no marks, deferred
+/// transitions, or guards.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", serde(try_from = "SerializedStateMachine<T>"))]
 pub struct StateMachine<T> {
   stack: Vec<T>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  ids: Ids,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  marks: HashMap<Cow<'static, str>, StateId>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  checkpoints: HashMap<Cow<'static, str>, Vec<T>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  deferred: TransitionQueue<T>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  guards: Vec<Rc<dyn Guard<T>>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  observers: Vec<Rc<dyn Observer<T>>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  history: Option<history::History>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  protected: Vec<StateId>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  frozen: bool,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  modals: Vec<(StateId, Rc<dyn modal::ModalSlot>)>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  factories: HashMap<Cow<'static, str>, registry::Factory<T>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  namer: Option<naming::Namer<T>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  invariants: Vec<Rc<dyn Invariant<T>>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  on_violation: OnViolation,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  interceptor: Option<Box<dyn Interceptor<T>>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  held: intercept::Held<T>,
+  /// An empty `Vec` with room in it, for the states the next transition removes.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  spare: Vec<T>,
+  #[cfg(feature = "time")]
+  #[cfg_attr(feature = "serde", serde(skip))]
+  expiries: Vec<(StateId, std::time::Duration)>,
+  #[cfg(feature = "tracing")]
+  #[cfg_attr(feature = "serde", serde(skip))]
+  span: Option<(StateId, tracing::Span)>,
+  #[cfg(feature = "metrics")]
+  #[cfg_attr(feature = "serde", serde(skip))]
+  metered: bool,
+  #[cfg(feature = "metrics")]
+  #[cfg_attr(feature = "serde", serde(skip))]
+  active_since: Option<metered::ActiveSince>,
 }
 
 impl<T> StateMachine<T> {
   /// Create a new `StateMachine` with the given state on top.
   pub fn new(initial: T) -> Self {
-    Self {
-      stack: vec![initial],
-    }
+    Self::new_many(vec![initial])
+  }
+
+  /// Create a new `StateMachine` with the given state on top, and room for `capacity` states
+  /// before it needs to reallocate.
+  pub fn with_capacity(initial: T, capacity: usize) -> Self {
+    let mut stack = Vec::with_capacity(capacity.max(1));
+    stack.push(initial);
+    let mut machine = Self::new_many(stack);
+    machine.ids.ids.reserve(capacity.saturating_sub(1));
+    machine
   }
 
   /// Create a new `StateMachine` with the given states on top. The last element of the vec
   /// will be the topmost state.
+  ///
+  /// ## Panics
+  ///
+  /// If `stack` is empty. Use [`StateMachine::try_new_many`] if it might be.
   pub fn new_many(stack: Vec<T>) -> Self {
-    Self { stack }
+    match Self::try_new_many(stack) {
+      Ok(machine) => machine,
+      Err(err) => panic!("{}", err),
+    }
+  }
+
+  /// Create a new `StateMachine` with the given states on top, or fail if there aren't any.
+  /// The last element of the vec will be the topmost state.
+  pub fn try_new_many(stack: Vec<T>) -> Result<Self, EmptyStackError> {
+    if stack.is_empty() {
+      return Err(EmptyStackError);
+    }
+    let mut ids = Ids::default();
+    ids.sync(stack.len());
+    Ok(Self {
+      stack,
+      ids,
+      marks: HashMap::new(),
+      checkpoints: HashMap::new(),
+      deferred: TransitionQueue::new(),
+      guards: Vec::new(),
+      observers: Vec::new(),
+      history: None,
+      protected: Vec::new(),
+      frozen: false,
+      modals: Vec::new(),
+      factories: HashMap::new(),
+      namer: None,
+      invariants: Vec::new(),
+      on_violation: OnViolation::Panic,
+      interceptor: None,
+      held: VecDeque::new(),
+      spare: Vec::new(),
+      #[cfg(feature = "time")]
+      expiries: Vec::new(),
+      #[cfg(feature = "tracing")]
+      span: None,
+      #[cfg(feature = "metrics")]
+      metered: false,
+      #[cfg(feature = "metrics")]
+      active_since: None,
+    })
+  }
+
+  /// Create a new `StateMachine` out of the states from an iterator, or fail if there aren't any.
+  /// The last state will be the topmost one.
+  pub fn try_from_iter(
+    states: impl IntoIterator<Item = T>,
+  ) -> Result<Self, EmptyStackError> {
+    Self::try_new_many(states.into_iter().collect())
   }
 
   /// Get the last element of the stack, aka the active state.
@@ -35,6 +236,112 @@ impl<T> StateMachine<T> {
     self.stack.last_mut().unwrap()
   }
 
+  /// Put a new state in place of the active state, and return the old one.
+  ///
+  /// Unlike [`Transition::Swap`], this doesn't touch the rest of the stack at all,
+  /// so the active state keeps its id and marks, and guards aren't asked.
+  pub fn replace_active(&mut self, state: T) -> T {
+    std::mem::replace(self.active_mut(), state)
+  }
+
+  /// Get the state directly under the active state, if there is one.
+  pub fn parent(&self) -> Option<&T> {
+    self.get_from_top(1)
+  }
+
+  /// Mutably get the state directly under the active state, if there is one.
+  pub fn parent_mut(&mut self) -> Option<&mut T> {
+    self.get_from_top_mut(1)
+  }
+
+  /// Mutably borrow the active state and the state directly under it at the same time,
+  /// so the active state can hand things down.
+  pub fn active_and_parent_mut(&mut self) -> (&mut T, Option<&mut T>) {
+    let (active, under) = self.stack.split_last_mut().unwrap();
+    (active, under.last_mut())
+  }
+
+  /// Check if any state on the stack matches the predicate.
+  pub fn contains(&self, pred: impl FnMut(&T) -> bool) -> bool {
+    self.stack.iter().any(pred)
+  }
+
+  /// Find the index of the topmost state matching the predicate, counting up from the bottom.
+  pub fn position(&self, pred: impl FnMut(&T) -> bool) -> Option<usize> {
+    self.stack.iter().rposition(pred)
+  }
+
+  /// Find how far down from the top the topmost state matching the predicate is,
+  /// so the active state is at depth 0.
+  pub fn depth_of(&self, pred: impl FnMut(&T) -> bool) -> Option<usize> {
+    self.stack.iter().rev().position(pred)
+  }
+
+  /// Go down the stack from the top, and return the first thing the function returns `Some` for.
+  pub fn find_map<U>(&self, f: impl FnMut(&T) -> Option<U>) -> Option<U> {
+    self.stack.iter().rev().find_map(f)
+  }
+
+  /// Keep only the states the predicate returns `true` for.
+  ///
+  /// If that would remove every state, nothing is removed and this returns an error.
+  pub fn retain(
+    &mut self,
+    mut pred: impl FnMut(&T) -> bool,
+  ) -> Result<(), TransitionError> {
+    self.retain_mut(|state| pred(state))
+  }
+
+  /// Keep only the states the predicate returns `true` for, letting it modify them along the way.
+  ///
+  /// If that would remove every state, nothing is removed and this returns an error.
+  /// Any changes the predicate made are kept.
+  ///
+  /// This isn't a transition, so guards, observers, the history, and invariants don't hear about
+  /// it.
+  pub fn retain_mut(
+    &mut self,
+    pred: impl FnMut(&mut T) -> bool,
+  ) -> Result<(), TransitionError> {
+    self.check_writable()?;
+    let keep: Vec<bool> = self.stack.iter_mut().map(pred).collect();
+    if !keep.contains(&true) {
+      return Err(TransitionError::PoppedTooMany {
+        popcnt: keep.len(),
+        available: keep.len() - 1,
+      });
+    }
+    self.apply_tracked(|tracked| {
+      let removes_protected = tracked.protected.iter().any(|id| {
+        tracked
+          .ids
+          .position_of(*id)
+          .is_some_and(|index| !keep[index])
+      });
+      if removes_protected {
+        return Err(TransitionError::Protected);
+      }
+      let mut flags = keep.iter();
+      tracked.stack.retain(|_| *flags.next().unwrap());
+      let mut flags = keep.iter();
+      tracked.ids.ids.retain(|_| *flags.next().unwrap());
+      Ok(())
+    })
+  }
+
+  /// Get the state `depth` states down from the top, so `get_from_top(0)` is the active state.
+  pub fn get_from_top(&self, depth: usize) -> Option<&T> {
+    let index = self.stack.len().checked_sub(depth + 1)?;
+    self.stack.get(index)
+  }
+
+  /// Mutably get the state `depth` states down from the top, so `get_from_top_mut(0)` is the active
+  /// state.
+  pub fn get_from_top_mut(&mut self, depth: usize) -> Option<&mut T> {
+    let index = self.stack.len().checked_sub(depth + 1)?;
+    self.stack.get_mut(index)
+  }
+
   /// Get the last element of the stack and all elements under it.
   pub fn split_last(&self) -> (&[T], &T) {
     let (under, last) = self.stack.split_last().unwrap();
@@ -48,11 +355,624 @@ impl<T> StateMachine<T> {
   }
 
   /// Apply the given transition. See [`Transition::apply`] for more detail.
+  ///
+  /// Every [`Guard`] on the machine gets to check the transition first. If the machine is
+  /// [frozen](StateMachine::freeze), this fails with [`TransitionError::Locked`].
   pub fn apply(
     &mut self,
     transition: Transition<T>,
   ) -> Result<TransitionOutcome<T>, TransitionError> {
-    transition.apply(&mut self.stack)
+    let (outcome, popped) = self.apply_popping_with(transition)?;
+    if let Some((id, answer)) = popped {
+      self.answer_modal(id, answer);
+    }
+    Ok(outcome)
+  }
+
+  /// Apply the given transition, and if it's a [`Transition::PopWith`], hand back its value
+  /// along with the id of the state it popped, so the caller can decide where it goes.
+  fn apply_popping_with(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<(TransitionOutcome<T>, Option<modal::PoppedWith>), TransitionError>
+  {
+    let Some(transition) = self.intercept(transition, false) else {
+      return Ok((TransitionOutcome::Deferred, None));
+    };
+    if !self.is_watched() {
+      return self.apply_popping_with_unobserved(transition);
+    }
+    let kind = transition.kind();
+    let depth_before = self.stack.len();
+    let res = self.apply_popping_with_unobserved(transition);
+    let result = match &res {
+      Ok((outcome, _)) => Ok(outcome),
+      Err(err) => Err(err.clone()),
+    };
+    self.report(kind, depth_before, result);
+    res
+  }
+
+  /// Tell the history, observers, and everything else that's watching about a transition
+  /// that was just applied.
+  fn report(
+    &mut self,
+    kind: TransitionKind,
+    depth_before: usize,
+    result: Result<&TransitionOutcome<T>, TransitionError>,
+  ) {
+    if let Ok(outcome) = result {
+      self.record(kind, outcome);
+    }
+    #[cfg(feature = "tracing")]
+    self.trace(kind, depth_before, result.clone());
+    #[cfg(feature = "log")]
+    self.log(kind, depth_before, result.clone());
+    #[cfg(feature = "metrics")]
+    if self.metered {
+      self.meter(kind, result.is_err());
+    }
+    self.notify_observers(kind, depth_before, result);
+  }
+
+  /// Whether anything wants to know about applied transitions, so they can't take shortcuts.
+  fn is_watched(&self) -> bool {
+    #[cfg(feature = "tracing")]
+    if Self::is_traced() {
+      return true;
+    }
+    #[cfg(feature = "log")]
+    if Self::is_logged() {
+      return true;
+    }
+    #[cfg(feature = "metrics")]
+    if self.metered {
+      return true;
+    }
+    !self.observers.is_empty()
+      || self.history.is_some()
+      || self.checks_invariants()
+      || self.interceptor.is_some()
+  }
+
+  fn apply_popping_with_unobserved(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<(TransitionOutcome<T>, Option<modal::PoppedWith>), TransitionError>
+  {
+    self.check_writable()?;
+    self.check_guards(&transition)?;
+    let (transition, answer) = match transition {
+      Transition::PopWith(answer) => (Transition::Pop, Some(answer)),
+      transition => (transition, None),
+    };
+    self.ids.sync(self.stack.len());
+    let popped = self.active_id();
+    let checking = self.checks_invariants();
+    let old_ids = if checking {
+      self.ids.ids.clone()
+    } else {
+      Vec::new()
+    };
+    let old_deferred = self.deferred.transitions.len();
+    let mut outcome = self.apply_tracked(|stack| {
+      stack.apply_protected(|stack| transition.apply_to(stack))
+    })?;
+    if checking {
+      outcome = self.check_invariants(outcome, old_ids, old_deferred)?;
+    }
+    Ok((outcome, answer.map(|answer| (popped, answer))))
+  }
+
+  /// Push a new active state. This is the same as applying [`Transition::Push`].
+  pub fn push(&mut self, state: T) -> Result<(), TransitionError> {
+    match self.apply(Transition::Push(state))? {
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => Ok(()),
+    }
+  }
+
+  /// Push all of these states on top, in order, with the last one becoming active. This is the
+  /// same as applying [`Transition::PopNAndPush`] without popping anything, so unlike [`Extend`],
+  /// guards and observers get a say, and it fails if the machine is frozen.
+  pub fn push_many(
+    &mut self,
+    states: impl IntoIterator<Item = T>,
+  ) -> Result<(), TransitionError> {
+    let states = states.into_iter().collect();
+    match self.apply(Transition::PopNAndPush(0, states))? {
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => Ok(()),
+    }
+  }
+
+  /// Pop the active state and return it. This is the same as applying [`Transition::Pop`],
+  /// but without putting the popped state in a `Vec`.
+  pub fn pop(&mut self) -> Result<T, TransitionError> {
+    if self.is_watched() {
+      // the observers and history need an outcome to look at
+      return match self.apply(Transition::Pop)? {
+        TransitionOutcome::Revealed(mut removed) => Ok(removed.pop().unwrap()),
+        TransitionOutcome::Deferred => Err(TransitionError::Held),
+        _ => unreachable!("popping always reveals"),
+      };
+    }
+    self.check_writable()?;
+    self.check_guards(&Transition::Pop)?;
+    let len = self.stack.len();
+    if len == 1 {
+      return Err(TransitionError::PoppedTooMany {
+        popcnt: 1,
+        available: 0,
+      });
+    }
+    if self.protected.contains(&self.active_id()) {
+      return Err(TransitionError::Protected);
+    }
+    self.apply_tracked(|stack| Ok(stack.remove(len - 1)))
+  }
+
+  /// Replace the active state and return the old one. This is the same as applying
+  /// [`Transition::Swap`].
+  pub fn swap(&mut self, state: T) -> Result<T, TransitionError> {
+    match self.apply(Transition::Swap(state))? {
+      TransitionOutcome::SwappedIn(mut removed, _) => {
+        Ok(removed.pop().unwrap())
+      }
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => unreachable!("swapping always swaps"),
+    }
+  }
+
+  /// Clear the whole stack and start again from `initial`, returning all the old states, bottommost
+  /// first.
+  ///
+  /// This is the same as applying [`Transition::Reset`], so it only fails if a guard rejects it.
+  pub fn reset(&mut self, initial: T) -> Result<Vec<T>, TransitionError> {
+    match self.apply(Transition::Reset(initial))? {
+      TransitionOutcome::SwappedIn(removed, _) => Ok(removed),
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => unreachable!("resetting always swaps"),
+    }
+  }
+
+  /// Remove every state above the one at `index`, counting up from the bottom, and return them,
+  /// bottommost first.
+  ///
+  /// This is the same as applying [`Transition::PopN`], so the state at `index` becomes active.
+  pub fn drain_above(
+    &mut self,
+    index: usize,
+  ) -> Result<Vec<T>, TransitionError> {
+    let len = self.stack.len();
+    if index >= len {
+      return Err(TransitionError::OutOfBounds { index, len });
+    }
+    match self.apply(Transition::PopN(len - 1 - index))? {
+      TransitionOutcome::Revealed(removed) => Ok(removed),
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => unreachable!("popping always reveals"),
+    }
+  }
+
+  /// Keep only the bottom `count` states, and return the rest, bottommost first.
+  ///
+  /// This is the same as applying [`Transition::PopN`], so it fails if `count` is 0.
+  /// If there are `count` states or fewer already, nothing happens.
+  pub fn truncate_to_bottom(
+    &mut self,
+    count: usize,
+  ) -> Result<Vec<T>, TransitionError> {
+    let pop_count = self.stack.len().saturating_sub(count);
+    match self.apply(Transition::PopN(pop_count))? {
+      TransitionOutcome::Revealed(removed) => Ok(removed),
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => unreachable!("popping always reveals"),
+    }
+  }
+
+  /// Take the top `count` states off into a machine of their own.
+  ///
+  /// This is the same as applying [`Transition::PopN`], so it fails if it would take every state.
+  /// The new machine hands out its own ids, and doesn't have any of this one's marks,
+  /// deferred transitions, or guards.
+  pub fn split_off_top(
+    &mut self,
+    count: NonZeroUsize,
+  ) -> Result<StateMachine<T>, TransitionError> {
+    match self.apply(Transition::PopN(count.get()))? {
+      TransitionOutcome::Revealed(removed) => Ok(Self::new_many(removed)),
+      TransitionOutcome::Deferred => Err(TransitionError::Held),
+      _ => unreachable!("popping always reveals"),
+    }
+  }
+
+  /// Put all of another machine's states on top of this one's, with its active state becoming
+  /// this one's active state.
+  ///
+  /// This is the opposite of [`StateMachine::split_off_top`]. It goes through
+  /// [`StateMachine::push_many`], so it can fail the same ways. The states get new ids, and the
+  /// other machine's marks, deferred transitions and guards are dropped.
+  pub fn append(
+    &mut self,
+    other: StateMachine<T>,
+  ) -> Result<(), TransitionError> {
+    self.push_many(other.consume())
+  }
+
+  /// Convert every state into a different type of state, keeping them in the same order.
+  ///
+  /// Ids, marks, and protection carry over. Deferred transitions, guards, and checkpoints don't,
+  /// because they only know about the old type of state.
+  pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> StateMachine<U> {
+    let Ok(machine) =
+      self.try_map(|state| Ok::<_, std::convert::Infallible>(f(state)));
+    machine
+  }
+
+  /// Convert every state into a different type of state, like [`StateMachine::map`],
+  /// but stop at the first error and return it.
+  pub fn try_map<U, E>(
+    self,
+    f: impl FnMut(T) -> Result<U, E>,
+  ) -> Result<StateMachine<U>, E> {
+    let mut ids = self.ids;
+    ids.sync(self.stack.len());
+    Ok(StateMachine {
+      stack: self.stack.into_iter().map(f).collect::<Result<_, _>>()?,
+      ids,
+      marks: self.marks,
+      checkpoints: HashMap::new(),
+      deferred: TransitionQueue::with_coalescing(self.deferred.coalescing()),
+      guards: Vec::new(),
+      observers: Vec::new(),
+      history: self.history,
+      protected: self.protected,
+      frozen: self.frozen,
+      modals: self.modals,
+      factories: HashMap::new(),
+      namer: None,
+      invariants: Vec::new(),
+      on_violation: self.on_violation,
+      interceptor: None,
+      held: VecDeque::new(),
+      spare: Vec::new(),
+      #[cfg(feature = "time")]
+      expiries: self.expiries,
+      #[cfg(feature = "tracing")]
+      span: None,
+      #[cfg(feature = "metrics")]
+      metered: self.metered,
+      #[cfg(feature = "metrics")]
+      active_since: None,
+    })
+  }
+
+  /// Apply the given transition, and get the new active state along with the outcome.
+  pub fn apply_and_active(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<(TransitionOutcome<T>, &mut T), TransitionError> {
+    let outcome = self.apply(transition)?;
+    Ok((outcome, self.active_mut()))
+  }
+
+  /// Work out a transition from the active state, then apply it.
+  pub fn apply_with(
+    &mut self,
+    f: impl FnOnce(&T) -> Transition<T>,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    let transition = f(self.active());
+    self.apply(transition)
+  }
+
+  /// Apply the given transition, but hand it back if it fails. See
+  /// [`Transition::apply_recoverable`] for more detail.
+  pub fn apply_recoverable(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<TransitionOutcome<T>, RecoverableError<T>> {
+    if let Err(err) = transition.dry_run(self) {
+      return Err((err, Some(transition)));
+    }
+    self.apply(transition).map_err(|err| (err, None))
+  }
+
+  /// Pop N states and push these new ones, just like [`Transition::PopNAndPush`],
+  /// but without needing to collect the new states into a `Vec` first.
+  pub fn pop_n_and_push<I>(
+    &mut self,
+    count: usize,
+    states: I,
+  ) -> Result<TransitionOutcome<T>, TransitionError>
+  where
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+  {
+    self.check_writable()?;
+    if !self.guards.is_empty() || self.is_watched() {
+      // the guards, observers, and history need something to look at
+      let transition =
+        Transition::PopNAndPush(count, states.into_iter().collect());
+      return self.apply(transition);
+    }
+    self.apply_tracked(|stack| {
+      stack.apply_protected(|stack| pop_n_and_push(stack, count, states))
+    })
+  }
+
+  /// Freeze the machine, so anything that would change which states are on the stack fails with
+  /// [`TransitionError::Locked`] until it's [unfrozen](StateMachine::unfreeze).
+  ///
+  /// Transitions can still be [deferred](StateMachine::defer), and the states themselves can still
+  /// be changed through `&mut` access.
+  pub fn freeze(&mut self) {
+    self.frozen = true;
+  }
+
+  /// Unfreeze the machine, so transitions can be applied again.
+  pub fn unfreeze(&mut self) {
+    self.frozen = false;
+  }
+
+  /// Check if the machine is [frozen](StateMachine::freeze).
+  pub fn is_frozen(&self) -> bool {
+    self.frozen
+  }
+
+  fn check_writable(&self) -> Result<(), TransitionError> {
+    if self.frozen {
+      Err(TransitionError::Locked)
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Protect the bottom `count` states, so any transition that would remove them fails with
+  /// [`TransitionError::Protected`]. States can still be put underneath them.
+  ///
+  /// This replaces whatever was protected before, so `protect_bottom(0)` protects nothing.
+  pub fn protect_bottom(&mut self, count: usize) {
+    self.ids.sync(self.stack.len());
+    let count = count.min(self.stack.len());
+    self.protected = self.ids.ids[..count].to_vec();
+  }
+
+  /// Add a guard, which gets to check every transition before it's applied and reject it.
+  pub fn add_guard(&mut self, guard: impl Guard<T> + 'static) {
+    self.guards.push(Rc::new(guard));
+  }
+
+  /// Remove every guard.
+  pub fn clear_guards(&mut self) {
+    self.guards.clear();
+  }
+
+  fn check_guards(
+    &self,
+    transition: &Transition<T>,
+  ) -> Result<(), TransitionError> {
+    check_guards(&self.guards, &self.stack, transition)
+  }
+
+  /// Apply all of the transitions in order, all or nothing.
+  ///
+  /// If one fails, everything before it is rolled back, and this returns its index and error.
+  /// Otherwise, this returns the outcome of every transition. Guards check each transition against
+  /// the stack the ones before it left behind.
+  ///
+  /// The whole batch is [dry run](Transition::dry_run) before any of it is applied, so a batch
+  /// that's bound to fail doesn't run any closures. The dry run can't see past a transition it
+  /// can't predict, like [`Transition::FromFn`], or know what guards will say about states that
+  /// aren't on the stack yet. If one of those fails, the closures before it have already run
+  /// by the time the batch is rolled back.
+  ///
+  /// The batch counts as one [`Transition::Sequence`] to everything else. The interceptor sees it
+  /// as one, and if it holds it, this fails with [`TransitionError::Held`]. Invariants are
+  /// checked once at the end, and if they fail, the index is the last transition's.
+  /// Observers and the history are told about it once, as a [`TransitionKind::Sequence`].
+  pub fn apply_all(
+    &mut self,
+    transitions: Vec<Transition<T>>,
+  ) -> Result<Vec<TransitionOutcome<T>>, (usize, TransitionError)> {
+    let transitions =
+      match self.intercept(Transition::Sequence(transitions), false) {
+        Some(Transition::Sequence(transitions)) => transitions,
+        Some(transition) => vec![transition],
+        None => return Err((0, TransitionError::Held)),
+      };
+    let depth_before = self.stack.len();
+    let res = self.apply_all_unobserved(transitions);
+    if self.is_watched() {
+      let result = res.as_ref().map_err(|(_, err)| err.clone());
+      self.report(TransitionKind::Sequence, depth_before, result);
+    }
+    res.map(|outcome| match outcome {
+      TransitionOutcome::Sequence(outcomes) => outcomes,
+      _ => unreachable!("batches are sequences"),
+    })
+  }
+
+  fn apply_all_unobserved(
+    &mut self,
+    transitions: Vec<Transition<T>>,
+  ) -> Result<TransitionOutcome<T>, (usize, TransitionError)> {
+    self.check_writable().map_err(|err| (0, err))?;
+    self.dry_run_all(&transitions)?;
+    let last = transitions.len().saturating_sub(1);
+    self.ids.sync(self.stack.len());
+    let checking = self.checks_invariants();
+    let old_ids = if checking {
+      self.ids.ids.clone()
+    } else {
+      Vec::new()
+    };
+    let old_deferred = self.deferred.transitions.len();
+    let guards = self.guards.clone();
+    let outcomes = self.apply_tracked(|stack| {
+      let saved = stack.save();
+      let mut outcomes = Vec::with_capacity(transitions.len());
+      for (index, transition) in transitions.into_iter().enumerate() {
+        let res =
+          check_guards(&guards, stack.states(), &transition).and_then(|()| {
+            stack.apply_protected(|stack| transition.apply_to(stack))
+          });
+        match res {
+          Ok(outcome) => outcomes.push(outcome),
+          Err(err) => {
+            for outcome in outcomes.into_iter().rev() {
+              outcome.undo(stack);
+            }
+            stack.restore(saved);
+            return Err((index, err));
+          }
+        }
+      }
+      Ok(outcomes)
+    })?;
+    let outcome = TransitionOutcome::Sequence(outcomes);
+    if checking {
+      return self
+        .check_invariants(outcome, old_ids, old_deferred)
+        .map_err(|err| (last, err));
+    }
+    Ok(outcome)
+  }
+
+  /// Get the id of the active state.
+  pub fn active_id(&self) -> StateId {
+    *self.ids.ids.last().unwrap()
+  }
+
+  /// Get the id of the state at the given index, counting up from the bottom.
+  pub fn id_at(&self, index: usize) -> Option<StateId> {
+    self.ids.ids.get(index).copied()
+  }
+
+  /// Get the ids of every state, in the same order as [`StateMachine::get_stack`].
+  pub fn get_ids(&self) -> &[StateId] {
+    &self.ids.ids
+  }
+
+  /// Find the index of the state with the given id, counting up from the bottom,
+  /// or `None` if it's not on the stack anymore.
+  pub fn position_of(&self, id: StateId) -> Option<usize> {
+    self.ids.position_of(id)
+  }
+
+  /// Borrow the state with the given id, if it's still on the stack.
+  pub fn get_by_id(&self, id: StateId) -> Option<&T> {
+    self.position_of(id).map(|index| &self.stack[index])
+  }
+
+  /// Mutably borrow the state with the given id, if it's still on the stack.
+  pub fn get_by_id_mut(&mut self, id: StateId) -> Option<&mut T> {
+    self.position_of(id).map(|index| &mut self.stack[index])
+  }
+
+  /// Mark the active state with a label, so you can get back to it later with
+  /// [`Transition::PopToMark`].
+  ///
+  /// If the label was already on another state, it's moved to this one. Marks go away with their
+  /// state, so you don't need to clean them up.
+  pub fn mark(&mut self, label: impl Into<Cow<'static, str>>) {
+    let id = self.active_id();
+    self.marks.insert(label.into(), id);
+  }
+
+  /// Remove the mark with the given label.
+  pub fn unmark(&mut self, label: &str) {
+    self.marks.remove(label);
+  }
+
+  /// Get the id of the state marked with the given label, if it's still on the stack.
+  pub fn marked(&self, label: &str) -> Option<StateId> {
+    self
+      .marks
+      .get(label)
+      .copied()
+      .filter(|id| self.position_of(*id).is_some())
+  }
+
+  fn tracked(&mut self) -> Tracked<'_, T> {
+    // in case someone's been messing with `get_stack_direct`
+    self.ids.sync(self.stack.len());
+    // and forget about marks and times whose states are gone
+    #[cfg(feature = "time")]
+    self.ids.prune_times();
+    let ids = &self.ids;
+    self.marks.retain(|_, id| ids.position_of(*id).is_some());
+    self.protected.retain(|id| ids.position_of(*id).is_some());
+    self.modals.retain(|(id, _)| ids.position_of(*id).is_some());
+    Tracked {
+      stack: &mut self.stack,
+      ids: &mut self.ids,
+      marks: &self.marks,
+      protected: &self.protected,
+      factories: &self.factories,
+      spare: &mut self.spare,
+      deferred: Vec::new(),
+    }
+  }
+
+  /// Do something to the tracked stack, then put anything it deferred on the queue if it worked.
+  fn apply_tracked<R, E>(
+    &mut self,
+    f: impl FnOnce(&mut Tracked<'_, T>) -> Result<R, E>,
+  ) -> Result<R, E> {
+    let mut tracked = self.tracked();
+    let res = f(&mut tracked);
+    let deferred = tracked.deferred;
+    if res.is_ok() {
+      for transition in deferred {
+        self.deferred.push(transition);
+      }
+    }
+    res
+  }
+
+  /// Put a transition on the queue, to be applied the next time [`StateMachine::flush_deferred`]
+  /// is called.
+  pub fn defer(&mut self, transition: Transition<T>) {
+    self.deferred.push(transition);
+  }
+
+  /// Borrow the queue of deferred transitions.
+  pub fn get_deferred(&self) -> &TransitionQueue<T> {
+    &self.deferred
+  }
+
+  /// Mutably borrow the queue of deferred transitions.
+  pub fn get_deferred_mut(&mut self) -> &mut TransitionQueue<T> {
+    &mut self.deferred
+  }
+
+  /// Mutably iterate over the states, along with the queue of deferred transitions,
+  /// so the states can ask for transitions while you're iterating over them.
+  pub fn iter_mut_deferred(
+    &mut self,
+  ) -> (std::slice::IterMut<'_, T>, &mut TransitionQueue<T>) {
+    (self.stack.iter_mut(), &mut self.deferred)
+  }
+
+  /// Apply every deferred transition, in the order they were deferred.
+  ///
+  /// Each one is applied separately, so one failing doesn't stop the rest. Any transitions deferred
+  /// while flushing stay on the queue for next time.
+  ///
+  /// If the machine is [frozen](StateMachine::freeze), nothing is applied and the queue is left
+  /// alone.
+  pub fn flush_deferred(
+    &mut self,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>> {
+    if self.frozen {
+      return Vec::new();
+    }
+    let queue = std::mem::take(&mut self.deferred.transitions);
+    queue
+      .into_iter()
+      .map(|transition| self.apply(transition))
+      .collect()
   }
 
   /// Borrow the stack.
@@ -71,17 +991,23 @@ impl<T> StateMachine<T> {
   ///
   /// You MUST leave at least one element in the stack. Not doing so won't cause UB, but it will cause panics,
   /// so this method is marked `unsafe`.
+  ///
+  /// The [`StateId`]s don't follow along with any changes made through this. If you change the
+  /// length of the stack, ids are added or removed at the top the next time a transition is
+  /// applied.
   pub unsafe fn get_stack_direct(&mut self) -> &mut Vec<T> {
     &mut self.stack
   }
 
-  /// Iterate over the states from topmost (active) to bottommost.
-  pub fn iter(&self) -> std::slice::Iter<T> {
+  /// Iterate over the states from bottommost to topmost (active).
+  ///
+  /// This goes the same way as [`StateMachine::iter_bottom_up`].
+  pub fn iter(&self) -> std::slice::Iter<'_, T> {
     self.stack.iter()
   }
 
-  /// Mutably iterate over the states from topmost (active) to bottommost.
-  pub fn iter_mut(&mut self) -> std::slice::IterMut<T> {
+  /// Mutably iterate over the states from bottommost to topmost (active).
+  pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
     self.stack.iter_mut()
   }
 
@@ -95,6 +1021,23 @@ impl<T> StateMachine<T> {
     NonZeroUsize::new(self.stack.len()).unwrap()
   }
 
+  /// Get how many states the stack can hold before it needs to reallocate.
+  pub fn capacity(&self) -> usize {
+    self.stack.capacity()
+  }
+
+  /// Make room for at least `additional` more states without reallocating.
+  pub fn reserve(&mut self, additional: usize) {
+    self.stack.reserve(additional);
+    self.ids.ids.reserve(additional);
+  }
+
+  /// Shrink the stack's allocation down as close to the number of states as possible.
+  pub fn shrink_to_fit(&mut self) {
+    self.stack.shrink_to_fit();
+    self.ids.ids.shrink_to_fit();
+  }
+
   /// To make clippy stop yelling at me.
   #[doc(hidden)]
   pub fn is_empty(&self) -> bool {
@@ -102,7 +1045,53 @@ impl<T> StateMachine<T> {
   }
 }
 
-/// Iterate over the states from topmost to bottommost.
+/// Index into the stack, counting up from the bottom.
+impl<T> std::ops::Index<usize> for StateMachine<T> {
+  type Output = T;
+
+  fn index(&self, index: usize) -> &T {
+    &self.stack[index]
+  }
+}
+
+/// Index into the stack, counting up from the bottom.
+impl<T> std::ops::IndexMut<usize> for StateMachine<T> {
+  fn index_mut(&mut self, index: usize) -> &mut T {
+    &mut self.stack[index]
+  }
+}
+
+impl<T> TryFrom<Vec<T>> for StateMachine<T> {
+  type Error = EmptyStackError;
+
+  fn try_from(stack: Vec<T>) -> Result<Self, Self::Error> {
+    Self::try_new_many(stack)
+  }
+}
+
+/// Push all the states on top, with the last one becoming active, like [`Transition::PushMany`].
+///
+/// This can't fail, so it doesn't ask any guards about it. It isn't a transition either,
+/// so observers, the history, and invariants don't hear about it.
+///
+/// ## Panics
+///
+/// If the machine is [frozen](StateMachine::freeze). Use [`StateMachine::push_many`] to get an
+/// error instead.
+impl<T> Extend<T> for StateMachine<T> {
+  fn extend<I: IntoIterator<Item = T>>(&mut self, states: I) {
+    assert!(
+      !self.frozen,
+      "can't push states onto a frozen state machine"
+    );
+    let Ok(()) = self.apply_tracked(|stack| {
+      stack.push_all(states.into_iter());
+      Ok::<_, std::convert::Infallible>(())
+    });
+  }
+}
+
+/// Iterate over the states from bottommost to topmost (active).
 impl<T> IntoIterator for StateMachine<T> {
   type Item = T;
   type IntoIter = std::vec::IntoIter<T>;
@@ -112,86 +1101,1506 @@ impl<T> IntoIterator for StateMachine<T> {
   }
 }
 
-/// A transition between states.
-pub enum Transition<T> {
-  /// Don't do anything
-  None,
-  /// Push this state on top.
-  Push(T),
-  /// Pop the current state.
-  Pop,
-  /// Replace the current state with a new one.
-  Swap(T),
-  /// The most generic version: pop N states off the stack, then push these new ones.
-  /// The last element in the vec will be the new active state.
+/// Iterate over the states from bottommost to topmost (active), like [`StateMachine::iter`].
+impl<'a, T> IntoIterator for &'a StateMachine<T> {
+  type Item = &'a T;
+  type IntoIter = std::slice::Iter<'a, T>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.stack.iter()
+  }
+}
+
+/// Mutably iterate over the states from bottommost to topmost (active), like
+/// [`StateMachine::iter_mut`].
+impl<'a, T> IntoIterator for &'a mut StateMachine<T> {
+  type Item = &'a mut T;
+  type IntoIter = std::slice::IterMut<'a, T>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.stack.iter_mut()
+  }
+}
+
+/// Start with `T::default()` as the only state.
+impl<T: Default> Default for StateMachine<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: Default> StateMachine<T> {
+  /// Move the active state out, leaving `T::default()` in its place.
+  pub fn take_active(&mut self) -> T {
+    std::mem::take(self.active_mut())
+  }
+}
+
+impl<T: PartialEq + Clone> StateMachine<T> {
+  /// Work out the transition that turns this machine's stack into the `target` stack,
+  /// bottommost state first.
+  ///
+  /// States the two stacks have in common at the bottom are left alone, so this is the smallest
+  /// [`Transition::PopNAndPush`] that does the job. If the stacks are already the same,
+  /// this is [`Transition::None`]. Applying it fails if `target` is empty.
+  pub fn diff(&self, target: &[T]) -> Transition<T> {
+    let common = self
+      .stack
+      .iter()
+      .zip(target)
+      .take_while(|(a, b)| a == b)
+      .count();
+    if common == self.stack.len() && common == target.len() {
+      Transition::None
+    } else {
+      Transition::PopNAndPush(
+        self.stack.len() - common,
+        target[common..].to_vec(),
+      )
+    }
+  }
+}
+
+/// Cloning a state machine doesn't clone its deferred transitions, because transitions can have
+/// closures in them. The clone starts with nothing deferred, but coalesces them the same way.
+///
+/// Modal states in the clone won't answer the [`ModalHandle`]s from the original.
+///
+/// Guards, observers, and registered factories are shared between the original and the clone.
+impl<T: Clone> Clone for StateMachine<T> {
+  fn clone(&self) -> Self {
+    Self {
+      stack: self.stack.clone(),
+      ids: self.ids.clone(),
+      marks: self.marks.clone(),
+      checkpoints: self.checkpoints.clone(),
+      deferred: TransitionQueue::with_coalescing(self.deferred.coalescing()),
+      guards: self.guards.clone(),
+      observers: self.observers.clone(),
+      history: self.history.clone(),
+      protected: self.protected.clone(),
+      frozen: self.frozen,
+      modals: Vec::new(),
+      factories: self.factories.clone(),
+      namer: self.namer.clone(),
+      invariants: self.invariants.clone(),
+      on_violation: self.on_violation,
+      interceptor: None,
+      held: VecDeque::new(),
+      spare: Vec::new(),
+      #[cfg(feature = "time")]
+      expiries: self.expiries.clone(),
+      #[cfg(feature = "tracing")]
+      span: None,
+      #[cfg(feature = "metrics")]
+      metered: self.metered,
+      #[cfg(feature = "metrics")]
+      active_since: None,
+    }
+  }
+}
+
+/// Two state machines are equal if they have equal stacks. Ids, marks, deferred transitions
+/// and guards aren't compared.
+impl<T: PartialEq> PartialEq for StateMachine<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.stack == other.stack
+  }
+}
+
+impl<T: Eq> Eq for StateMachine<T> {}
+
+/// Only the stack is hashed, to match [`PartialEq`].
+impl<T: std::hash::Hash> std::hash::Hash for StateMachine<T> {
+  fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    self.stack.hash(state);
+  }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for StateMachine<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("StateMachine")
+      .field("stack", &self.stack)
+      .field("ids", &self.ids)
+      .field("marks", &self.marks)
+      .field("checkpoints", &self.checkpoints)
+      .field("deferred", &self.deferred)
+      .field("guards", &self.guards.len())
+      .field("observers", &self.observers.len())
+      .field("history", &self.history)
+      .field("protected", &self.protected)
+      .field("frozen", &self.frozen)
+      .field("modals", &self.modals.len())
+      .field("factories", &self.factories.keys().collect::<Vec<_>>())
+      .field("named", &self.namer.is_some())
+      .field("invariants", &self.invariants.len())
+      .field("on_violation", &self.on_violation)
+      .field("intercepted", &self.interceptor.is_some())
+      .field("held", &self.held)
+      .finish()
+  }
+}
+
+/// Transitions waiting to be applied to a state machine later.
+///
+/// See [`Transition::Defer`] and [`StateMachine::flush_deferred`].
+pub struct TransitionQueue<T> {
+  transitions: Vec<Transition<T>>,
+  coalesce: Coalesce,
+}
+
+impl<T> TransitionQueue<T> {
+  /// Create an empty queue that doesn't coalesce anything.
+  pub fn new() -> Self {
+    Self::with_coalescing(Coalesce::NONE)
+  }
+
+  /// Create an empty queue that coalesces transitions as they're pushed.
+  pub fn with_coalescing(coalesce: Coalesce) -> Self {
+    Self {
+      transitions: Vec::new(),
+      coalesce,
+    }
+  }
+
+  /// Get how this queue coalesces transitions.
+  pub fn coalescing(&self) -> Coalesce {
+    self.coalesce
+  }
+
+  /// Change how this queue coalesces transitions. This only affects transitions pushed from now on.
+  pub fn set_coalescing(&mut self, coalesce: Coalesce) {
+    self.coalesce = coalesce;
+  }
+
+  /// Add a transition to the end of the queue, coalescing it with the ones before it if
+  /// the queue is set up to.
+  pub fn push(&mut self, transition: Transition<T>) {
+    let mut pops = match transition {
+      Transition::Pop => 1,
+      Transition::PopN(count) if count > 0 => count,
+      transition => {
+        self.transitions.push(transition);
+        return;
+      }
+    };
+
+    if self.coalesce.cancel_push_pop {
+      while pops > 0
+        && matches!(self.transitions.last(), Some(Transition::Push(_)))
+      {
+        self.transitions.pop();
+        pops -= 1;
+      }
+    }
+    if pops == 0 {
+      return;
+    }
+
+    if self.coalesce.merge_pops {
+      if let Some(last) = self.transitions.last_mut() {
+        let merged = match last {
+          Transition::Pop => Some(1),
+          Transition::PopN(count) => Some(*count),
+          _ => None,
+        };
+        if let Some(count) = merged {
+          *last = Transition::PopN(count + pops);
+          return;
+        }
+      }
+    }
+
+    self.transitions.push(if pops == 1 {
+      Transition::Pop
+    } else {
+      Transition::PopN(pops)
+    });
+  }
+
+  /// Borrow the queued transitions, first to be applied first.
+  pub fn get_transitions(&self) -> &[Transition<T>] {
+    &self.transitions
+  }
+
+  /// Throw away every queued transition.
+  pub fn clear(&mut self) {
+    self.transitions.clear();
+  }
+
+  /// Get how many transitions are queued.
+  pub fn len(&self) -> usize {
+    self.transitions.len()
+  }
+
+  /// Check if nothing is queued.
+  pub fn is_empty(&self) -> bool {
+    self.transitions.is_empty()
+  }
+}
+
+/// How a [`TransitionQueue`] combines transitions as they're pushed onto it, so a frame's worth of
+/// requests collapses into as few stack edits as possible.
+///
+/// Coalescing changes what happens if the transitions would have failed. For example, merging two
+/// pops means that if the second one couldn't have happened, neither does the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Coalesce {
+  /// A pop right after a [`Transition::Push`] cancels it out, and neither is queued.
+  pub cancel_push_pop: bool,
+  /// Consecutive [`Transition::Pop`]s and [`Transition::PopN`]s are merged into one `PopN`.
+  pub merge_pops: bool,
+}
+
+impl Coalesce {
+  /// Don't coalesce anything.
+  pub const NONE: Coalesce = Coalesce {
+    cancel_push_pop: false,
+    merge_pops: false,
+  };
+  /// Coalesce everything that can be.
+  pub const ALL: Coalesce = Coalesce {
+    cancel_push_pop: true,
+    merge_pops: true,
+  };
+}
+
+impl<T> Default for TransitionQueue<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for TransitionQueue<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_list().entries(&self.transitions).finish()
+  }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SerializedStateMachine<T> {
+  stack: Vec<T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> TryFrom<SerializedStateMachine<T>> for StateMachine<T> {
+  type Error = EmptyStackError;
+
+  fn try_from(value: SerializedStateMachine<T>) -> Result<Self, Self::Error> {
+    Self::try_new_many(value.stack)
+  }
+}
+
+/// A handle to one specific state on a [`StateMachine`].
+///
+/// Each state gets a new id when it goes on the stack, and ids are never reused by the same
+/// machine, so an id always refers to the same state until it's removed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateId(u64);
+
+/// The ids of the states on a state machine.
+#[derive(Debug, Clone, Default)]
+struct Ids {
+  ids: Vec<StateId>,
+  next: u64,
+  #[cfg(feature = "time")]
+  times: HashMap<StateId, StateTimes>,
+}
+
+impl Ids {
+  fn fresh(&mut self) -> StateId {
+    let id = StateId(self.next);
+    self.next += 1;
+    #[cfg(feature = "time")]
+    self.times.insert(id, StateTimes::new());
+    id
+  }
+
+  /// Add or remove ids from the top to match the length of the stack.
+  fn sync(&mut self, len: usize) {
+    self.ids.truncate(len);
+    while self.ids.len() < len {
+      let id = self.fresh();
+      self.ids.push(id);
+    }
+  }
+
+  fn position_of(&self, id: StateId) -> Option<usize> {
+    self.ids.iter().rposition(|it| *it == id)
+  }
+}
+
+/// A transition between states.
+///
+/// With the `serde` feature, transitions can be serialized, except for the variants that hold
+/// closures or iterators. Trying to serialize one of those is an error.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Transition<T> {
+  /// Don't do anything
+  None,
+  /// Push this state on top.
+  Push(T),
+  /// Pop the current state.
+  Pop,
+  /// Push all of these states on top, in order. The last state yielded will be the new active
+  /// state.
+  ///
+  /// If the iterator is empty, nothing happens.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  PushMany(Box<dyn Iterator<Item = T>>),
+  /// Pop N states off the stack. At least one state must be left over.
+  PopN(usize),
+  /// Pop states until the predicate returns `true` for the active state,
+  /// or until only the bottommost state is left.
+  ///
+  /// If the active state already matches, nothing is popped.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  PopUntil(Box<dyn Fn(&T) -> bool>),
+  /// Pop states until the state with this id is the active one.
+  ///
+  /// Fails if there's no state with this id on the stack.
+  PopTo(StateId),
+  /// Pop states until the state marked with this label is the active one.
+  /// See [`StateMachine::mark`].
+  ///
+  /// Fails if there's no state with this mark on the stack.
+  PopToMark(Cow<'static, str>),
+  /// Push a new state made by the function registered with this key.
+  /// See [`StateMachine::register`].
+  ///
+  /// Fails if there's no function registered with this key.
+  PushKey(Cow<'static, str>),
+  /// Remove the state with this id from wherever it is in the stack.
+  ///
+  /// Fails if there's no state with this id on the stack, or if it's the only state.
+  RemoveId(StateId),
+  /// Replace the current state with a new one.
+  Swap(T),
+  /// The most generic version: pop N states off the stack, then push these new ones.
+  /// The last element in the vec will be the new active state.
   PopNAndPush(usize, Vec<T>),
+  /// Pop every state except the bottommost one.
+  PopAll,
+  /// Pop every state, including the bottommost one, and push this one in their place.
+  Reset(T),
+  /// Replace the entire stack with these states. The last element in the vec will be the new active
+  /// state.
+  ///
+  /// The vec must not be empty.
+  ReplaceAll(Vec<T>),
+  /// Pop every state, including the bottommost one, and push these in their place.
+  /// The last element in the vec will be the new active state.
+  ///
+  /// This is [`Transition::PopNAndPush`] with the length of the stack, so unlike
+  /// [`Transition::ReplaceAll`], the outcome is a [`TransitionOutcome::SwappedIn`].
+  /// The vec must not be empty.
+  PopAllAndPush(Vec<T>),
+  /// Insert a state at the given index, counting up from the bottom of the stack.
+  ///
+  /// Everything at or above that index gets shifted up by one. Inserting at the length of the stack
+  /// is the same as [`Transition::Push`].
+  InsertAt(usize, T),
+  /// Remove the state at the given index, counting up from the bottom of the stack.
+  ///
+  /// Removing the active state is the same as [`Transition::Pop`].
+  RemoveAt(usize),
+  /// Replace the state at the given depth with a new one, counting down from the top of the stack.
+  ///
+  /// Depth 0 is the active state, so `SwapAt(0, s)` is the same as [`Transition::Swap`].
+  SwapAt(usize, T),
+  /// Remove N states directly underneath the active state, leaving the active state where it is.
+  PopBelow(usize),
+  /// Apply each of these transitions in order, all or nothing.
+  ///
+  /// If any of them fails, the ones before it are rolled back and the stack is left as it was.
+  /// The sequence is [dry run](Transition::dry_run) first, so closures in it, like the ones in
+  /// [`Transition::PushWith`], only run if it can work. The dry run can't see past a
+  /// [`Transition::FromFn`] or some conditionals, though, so if one of those leads to a failure,
+  /// the closures before it will have run.
+  Sequence(Vec<Transition<T>>),
+  /// Check the condition against the active state, then apply one transition or the other
+  /// depending on the result.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  If {
+    /// The condition to check the active state with.
+    cond: Box<dyn Fn(&T) -> bool>,
+    /// The transition to apply if the condition is `true`.
+    then: Box<Transition<T>>,
+    /// The transition to apply if the condition is `false`.
+    otherwise: Box<Transition<T>>,
+  },
+  /// Look at the stack when the transition is applied, and decide what to do then.
+  ///
+  /// The outcome is the outcome of whatever transition the function returns.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  FromFn(TransitionFn<T>),
+  /// Like [`Transition::Push`], but the state is only constructed when the transition is applied.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  PushWith(Box<dyn FnOnce() -> T>),
+  /// Like [`Transition::PopNAndPush`], but the new states are only constructed once the transition
+  /// is known to be possible.
+  ///
+  /// If it turns out the function returns no states and the pop would empty the stack,
+  /// this still fails, but the stack is not modified.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  PopNAndPushWith(usize, Box<dyn FnOnce() -> Vec<T>>),
+  /// Like [`Transition::Pop`], but hand a value to whoever was waiting on the popped state.
+  ///
+  /// If the popped state was pushed with [`StateMachine::push_modal`] and the value is the right
+  /// type, it goes to the [`ModalHandle`]. Otherwise, [`StateMachine::apply_with_hooks`] passes it
+  /// to the [`State::on_result`] of the state that becomes active. Everywhere else, including
+  /// inside a [`Transition::Sequence`], this is just a pop and the value is dropped.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  PopWith(Box<dyn Any>),
+  /// Don't apply this transition now; put it on the state machine's queue to be applied
+  /// later, with [`StateMachine::flush_deferred`].
+  ///
+  /// A bare `Vec` has no queue to put it on, so this fails there.
+  Defer(Box<Transition<T>>),
 }
 
+impl<T: std::fmt::Debug> std::fmt::Debug for Transition<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // Closures can't be printed, so they just show up as `..`
+    let opaque = format_args!("..");
+    match self {
+      Transition::None => write!(f, "None"),
+      Transition::Push(s) => f.debug_tuple("Push").field(s).finish(),
+      Transition::Pop => write!(f, "Pop"),
+      Transition::PushMany(_) => {
+        f.debug_tuple("PushMany").field(&opaque).finish()
+      }
+      Transition::PopN(count) => f.debug_tuple("PopN").field(count).finish(),
+      Transition::PopUntil(_) => {
+        f.debug_tuple("PopUntil").field(&opaque).finish()
+      }
+      Transition::PopTo(id) => f.debug_tuple("PopTo").field(id).finish(),
+      Transition::PopToMark(label) => {
+        f.debug_tuple("PopToMark").field(label).finish()
+      }
+      Transition::PushKey(key) => f.debug_tuple("PushKey").field(key).finish(),
+      Transition::RemoveId(id) => f.debug_tuple("RemoveId").field(id).finish(),
+      Transition::Swap(s) => f.debug_tuple("Swap").field(s).finish(),
+      Transition::PopNAndPush(count, states) => f
+        .debug_tuple("PopNAndPush")
+        .field(count)
+        .field(states)
+        .finish(),
+      Transition::PopAll => write!(f, "PopAll"),
+      Transition::Reset(s) => f.debug_tuple("Reset").field(s).finish(),
+      Transition::ReplaceAll(states) => {
+        f.debug_tuple("ReplaceAll").field(states).finish()
+      }
+      Transition::PopAllAndPush(states) => {
+        f.debug_tuple("PopAllAndPush").field(states).finish()
+      }
+      Transition::InsertAt(index, s) => {
+        f.debug_tuple("InsertAt").field(index).field(s).finish()
+      }
+      Transition::RemoveAt(index) => {
+        f.debug_tuple("RemoveAt").field(index).finish()
+      }
+      Transition::SwapAt(depth, s) => {
+        f.debug_tuple("SwapAt").field(depth).field(s).finish()
+      }
+      Transition::PopBelow(count) => {
+        f.debug_tuple("PopBelow").field(count).finish()
+      }
+      Transition::Sequence(transitions) => {
+        f.debug_tuple("Sequence").field(transitions).finish()
+      }
+      Transition::If {
+        cond: _,
+        then,
+        otherwise,
+      } => f
+        .debug_struct("If")
+        .field("cond", &opaque)
+        .field("then", then)
+        .field("otherwise", otherwise)
+        .finish(),
+      Transition::FromFn(_) => f.debug_tuple("FromFn").field(&opaque).finish(),
+      Transition::PushWith(_) => {
+        f.debug_tuple("PushWith").field(&opaque).finish()
+      }
+      Transition::PopNAndPushWith(count, _) => f
+        .debug_tuple("PopNAndPushWith")
+        .field(count)
+        .field(&opaque)
+        .finish(),
+      Transition::PopWith(_) => {
+        f.debug_tuple("PopWith").field(&opaque).finish()
+      }
+      Transition::Defer(transition) => {
+        f.debug_tuple("Defer").field(transition).finish()
+      }
+    }
+  }
+}
+
+impl<T: 'static> Transition<T> {
+  /// Convert this into a transition on a different type of state. This is handy if each of your
+  /// screens has its own type, and they all get wrapped up in one big enum on the state machine.
+  ///
+  /// `lift` converts the states. `lower` lets the conditions in [`Transition::PopUntil`] and
+  /// [`Transition::If`] look at the new type of state; conditions never match states it returns
+  /// `None` for.
+  ///
+  /// [`Transition::FromFn`] needs to see the whole stack as the old type of state, so it can't be
+  /// converted. If there's one anywhere in this transition, the transition is handed back unchanged
+  /// in the `Err`.
+  pub fn map<U: 'static>(
+    self,
+    lift: impl Fn(T) -> U + 'static,
+    lower: impl Fn(&U) -> Option<&T> + 'static,
+  ) -> Result<Transition<U>, Self> {
+    if self.has_from_fn() {
+      return Err(self);
+    }
+    let lift: Rc<dyn Fn(T) -> U> = Rc::new(lift);
+    let lower: Lower<T, U> = Rc::new(lower);
+    Ok(self.map_inner(&lift, &lower))
+  }
+
+  fn has_from_fn(&self) -> bool {
+    match self {
+      Transition::FromFn(_) => true,
+      Transition::Sequence(transitions) => {
+        transitions.iter().any(Transition::has_from_fn)
+      }
+      Transition::If {
+        then, otherwise, ..
+      } => then.has_from_fn() || otherwise.has_from_fn(),
+      Transition::Defer(transition) => transition.has_from_fn(),
+      _ => false,
+    }
+  }
+
+  fn map_inner<U: 'static>(
+    self,
+    lift: &Rc<dyn Fn(T) -> U>,
+    lower: &Lower<T, U>,
+  ) -> Transition<U> {
+    let lift_vec = |states: Vec<T>| states.into_iter().map(&**lift).collect();
+    let lower_pred =
+      |pred: Box<dyn Fn(&T) -> bool>| -> Box<dyn Fn(&U) -> bool> {
+        let lower = lower.clone();
+        Box::new(move |state| lower(state).is_some_and(&pred))
+      };
+    match self {
+      Transition::None => Transition::None,
+      Transition::Push(s) => Transition::Push(lift(s)),
+      Transition::Pop => Transition::Pop,
+      Transition::PushMany(states) => {
+        let lift = lift.clone();
+        Transition::PushMany(Box::new(states.map(move |s| lift(s))))
+      }
+      Transition::PopN(count) => Transition::PopN(count),
+      Transition::PopUntil(pred) => Transition::PopUntil(lower_pred(pred)),
+      Transition::PopTo(id) => Transition::PopTo(id),
+      Transition::PopToMark(label) => Transition::PopToMark(label),
+      Transition::PushKey(key) => Transition::PushKey(key),
+      Transition::RemoveId(id) => Transition::RemoveId(id),
+      Transition::Swap(s) => Transition::Swap(lift(s)),
+      Transition::PopNAndPush(count, states) => {
+        Transition::PopNAndPush(count, lift_vec(states))
+      }
+      Transition::PopAll => Transition::PopAll,
+      Transition::Reset(s) => Transition::Reset(lift(s)),
+      Transition::ReplaceAll(states) => {
+        Transition::ReplaceAll(lift_vec(states))
+      }
+      Transition::PopAllAndPush(states) => {
+        Transition::PopAllAndPush(lift_vec(states))
+      }
+      Transition::InsertAt(index, s) => Transition::InsertAt(index, lift(s)),
+      Transition::RemoveAt(index) => Transition::RemoveAt(index),
+      Transition::SwapAt(depth, s) => Transition::SwapAt(depth, lift(s)),
+      Transition::PopBelow(count) => Transition::PopBelow(count),
+      Transition::Sequence(transitions) => Transition::Sequence(
+        transitions
+          .into_iter()
+          .map(|transition| transition.map_inner(lift, lower))
+          .collect(),
+      ),
+      Transition::If {
+        cond,
+        then,
+        otherwise,
+      } => Transition::If {
+        cond: lower_pred(cond),
+        then: Box::new(then.map_inner(lift, lower)),
+        otherwise: Box::new(otherwise.map_inner(lift, lower)),
+      },
+      Transition::FromFn(_) => unreachable!("checked by has_from_fn"),
+      Transition::PushWith(f) => {
+        let lift = lift.clone();
+        Transition::PushWith(Box::new(move || lift(f())))
+      }
+      Transition::PopNAndPushWith(count, f) => {
+        let lift = lift.clone();
+        Transition::PopNAndPushWith(
+          count,
+          Box::new(move || f().into_iter().map(&*lift).collect()),
+        )
+      }
+      Transition::PopWith(value) => Transition::PopWith(value),
+      Transition::Defer(transition) => {
+        Transition::Defer(Box::new(transition.map_inner(lift, lower)))
+      }
+    }
+  }
+}
+
+/// How [`Transition::map`] looks at new states as old ones.
+type Lower<T, U> = Rc<dyn Fn(&U) -> Option<&T>>;
+
+/// A function that decides on a transition by looking at the stack. See [`Transition::FromFn`].
+pub type TransitionFn<T> = Box<dyn FnOnce(&[T]) -> Transition<T>>;
+
 impl<T> Transition<T> {
+  /// Start building a transition out of pops and pushes. See [`TransitionBuilder`].
+  pub fn build() -> TransitionBuilder<T> {
+    TransitionBuilder::new()
+  }
+
+  /// Convenience constructor for [`Transition::PushMany`], so you don't have to box the iterator
+  /// yourself.
+  pub fn push_many<I>(states: I) -> Self
+  where
+    I: IntoIterator<Item = T>,
+    I::IntoIter: 'static,
+  {
+    Transition::PushMany(Box::new(states.into_iter()))
+  }
+
+  /// Convenience constructor for [`Transition::PopUntil`], so you don't have to box the closure
+  /// yourself.
+  pub fn pop_until(pred: impl Fn(&T) -> bool + 'static) -> Self {
+    Transition::PopUntil(Box::new(pred))
+  }
+
+  /// Convenience constructor for [`Transition::PopToMark`].
+  pub fn pop_to_mark(label: impl Into<Cow<'static, str>>) -> Self {
+    Transition::PopToMark(label.into())
+  }
+
+  /// Convenience constructor for [`Transition::PushKey`].
+  pub fn push_key(key: impl Into<Cow<'static, str>>) -> Self {
+    Transition::PushKey(key.into())
+  }
+
+  /// Convenience constructor for [`Transition::If`], so you don't have to box everything yourself.
+  pub fn if_else(
+    cond: impl Fn(&T) -> bool + 'static,
+    then: Transition<T>,
+    otherwise: Transition<T>,
+  ) -> Self {
+    Transition::If {
+      cond: Box::new(cond),
+      then: Box::new(then),
+      otherwise: Box::new(otherwise),
+    }
+  }
+
+  /// Convenience constructor for [`Transition::FromFn`], so you don't have to box the closure
+  /// yourself.
+  pub fn from_fn(f: impl FnOnce(&[T]) -> Transition<T> + 'static) -> Self {
+    Transition::FromFn(Box::new(f))
+  }
+
+  /// Convenience constructor for [`Transition::PushWith`], so you don't have to box the closure
+  /// yourself.
+  pub fn push_with(f: impl FnOnce() -> T + 'static) -> Self {
+    Transition::PushWith(Box::new(f))
+  }
+
+  /// Convenience constructor for [`Transition::PopNAndPushWith`], so you don't have to box the
+  /// closure yourself.
+  pub fn pop_n_and_push_with(
+    count: usize,
+    f: impl FnOnce() -> Vec<T> + 'static,
+  ) -> Self {
+    Transition::PopNAndPushWith(count, Box::new(f))
+  }
+
+  /// Convenience constructor for [`Transition::PopWith`], so you don't have to box the value
+  /// yourself.
+  pub fn pop_with(value: impl Any) -> Self {
+    Transition::PopWith(Box::new(value))
+  }
+
   /// Apply the transition to the given stack.
   ///
   /// If an error is returned, the stack will not be modified.
+  ///
+  /// A bare `Vec` doesn't keep track of [`StateId`]s, marks, or registered factories, so
+  /// [`Transition::PopTo`], [`Transition::PopToMark`], [`Transition::RemoveId`] and
+  /// [`Transition::PushKey`] will always fail here.
+  /// Use [`StateMachine::apply`] for those.
   pub fn apply(
     self,
     stack: &mut Vec<T>,
   ) -> Result<TransitionOutcome<T>, TransitionError> {
-    let (pop_count, mut to_push) = match self {
+    self.apply_to(stack)
+  }
+
+  /// Apply the transition to the given stack, like [`Transition::apply`], but hand the transition
+  /// back if it fails, so any states in it aren't lost.
+  ///
+  /// The transition is checked with a dry run first. If there's no telling whether it'll work
+  /// without running one of its closures, it's applied anyway, and if it fails after that
+  /// there's nothing to hand back.
+  pub fn apply_recoverable(
+    self,
+    stack: &mut Vec<T>,
+  ) -> Result<TransitionOutcome<T>, RecoverableError<T>> {
+    if let Err(err) = self.dry_run_on(stack, None) {
+      return Err((err, Some(self)));
+    }
+    self.apply(stack).map_err(|err| (err, None))
+  }
+
+  fn apply_to<S: Stack<T>>(
+    self,
+    stack: &mut S,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    let (pop_count, to_push) = match self {
       Transition::None => return Ok(TransitionOutcome::None),
-      Transition::Push(s) => (0, vec![s]),
-      Transition::Pop => (1, vec![]),
-      Transition::Swap(s) => (1, vec![s]),
+      Transition::Defer(transition) => {
+        stack.defer(*transition)?;
+        return Ok(TransitionOutcome::Deferred);
+      }
+      Transition::Sequence(transitions) => {
+        // find out if it's going to fail before running any closures in it
+        dry_run::dry_run_each(&transitions, stack.states(), stack.tracking())
+          .map_err(|(_, err)| err)?;
+        let saved = stack.save();
+        let mut log = Vec::with_capacity(transitions.len());
+        for transition in transitions {
+          if let Err(err) = transition.apply_logged(stack, &mut log) {
+            for outcome in log.into_iter().rev() {
+              outcome.undo(stack);
+            }
+            stack.restore(saved);
+            return Err(err);
+          }
+        }
+        return Ok(TransitionOutcome::Sequence(log));
+      }
+      Transition::FromFn(f) => return f(stack.states()).apply_to(stack),
+      Transition::If {
+        cond,
+        then,
+        otherwise,
+      } => {
+        let took_then = cond(stack.states().last().unwrap());
+        let transition = if took_then { then } else { otherwise };
+        return Ok(TransitionOutcome::Conditional(
+          took_then,
+          Box::new(transition.apply_to(stack)?),
+        ));
+      }
+      Transition::Push(s) => return Ok(push(stack, s)),
+      Transition::PushMany(states) => {
+        let len = stack.len();
+        stack.push_all(states);
+        return Ok(if stack.len() == len {
+          TransitionOutcome::None
+        } else {
+          TransitionOutcome::Pushed(stack.len() - len - 1)
+        });
+      }
+      Transition::Pop | Transition::PopWith(_) => {
+        if stack.len() == 1 {
+          return Err(TransitionError::PoppedTooMany {
+            popcnt: 1,
+            available: 0,
+          });
+        }
+        let mut removed = stack.spare();
+        removed.push(stack.pop());
+        return Ok(TransitionOutcome::Revealed(removed));
+      }
+      Transition::PopN(count) => (count, vec![]),
+      Transition::PopUntil(pred) => {
+        let states = stack.states();
+        let count = states.iter().rev().take_while(|s| !pred(s)).count();
+        (count.min(states.len() - 1), vec![])
+      }
+      Transition::PopTo(id) => match stack.position_of(id) {
+        Some(index) => (stack.len() - 1 - index, vec![]),
+        None => return Err(TransitionError::NoSuchState(id)),
+      },
+      Transition::PopToMark(label) => {
+        match stack.marked(&label).and_then(|id| stack.position_of(id)) {
+          Some(index) => (stack.len() - 1 - index, vec![]),
+          None => return Err(TransitionError::NoSuchMark),
+        }
+      }
+      Transition::PushKey(key) => match stack.construct(&key) {
+        Some(state) => return Ok(push(stack, state)),
+        None => return Err(TransitionError::NoSuchKey),
+      },
+      Transition::Swap(s) | Transition::SwapAt(0, s) => {
+        let mut removed = stack.spare();
+        removed.push(stack.swap_top(s));
+        return Ok(TransitionOutcome::SwappedIn(removed, 0));
+      }
       Transition::PopNAndPush(count, states) => (count, states),
+      Transition::PushWith(f) => return Ok(push(stack, f())),
+      Transition::PopNAndPushWith(count, f) => {
+        // Check what we can before making anything.
+        // If nothing gets pushed, the check below will catch it.
+        if count > stack.len() {
+          return Err(TransitionError::PoppedTooMany {
+            popcnt: count,
+            available: stack.len() - 1,
+          });
+        }
+        (count, f())
+      }
+      Transition::PopAll => (stack.len() - 1, vec![]),
+      Transition::Reset(s) => (stack.len(), vec![s]),
+      Transition::PopAllAndPush(states) => {
+        if states.is_empty() {
+          return Err(TransitionError::EmptyReplacement);
+        }
+        (stack.len(), states)
+      }
+      Transition::ReplaceAll(states) => {
+        if states.is_empty() {
+          return Err(TransitionError::EmptyReplacement);
+        }
+        return Ok(TransitionOutcome::Replaced(stack.replace(states)));
+      }
+      Transition::InsertAt(index, s) => {
+        let len = stack.len();
+        if index > len {
+          return Err(TransitionError::OutOfBounds { index, len });
+        }
+        stack.insert(index, s);
+        return Ok(if index == len {
+          TransitionOutcome::Pushed(0)
+        } else {
+          TransitionOutcome::Inserted(index)
+        });
+      }
+      Transition::RemoveAt(index) => return remove_at(stack, index),
+      Transition::SwapAt(depth, s) => {
+        let len = stack.len();
+        if depth >= len {
+          return Err(TransitionError::OutOfBounds { index: depth, len });
+        }
+        let index = len - 1 - depth;
+        let old = stack.remove(index);
+        stack.insert(index, s);
+        return Ok(TransitionOutcome::SwappedAt(index, old));
+      }
+      Transition::PopBelow(count) => {
+        let available = stack.len() - 1;
+        if count > available {
+          return Err(TransitionError::PoppedTooMany {
+            popcnt: count,
+            available,
+          });
+        }
+        let index = available - count;
+        let mut removed = stack.spare();
+        removed.extend((0..count).map(|_| stack.remove(index)));
+        return Ok(TransitionOutcome::RemovedBelow(removed));
+      }
+      Transition::RemoveId(id) => match stack.position_of(id) {
+        Some(index) => return remove_at(stack, index),
+        None => return Err(TransitionError::NoSuchState(id)),
+      },
     };
 
-    // We need to always leave at least one thing on top
-    let allowed_popcnt = if to_push.is_empty() {
-      stack.len() - 1
-    } else {
-      stack.len()
-    };
-    if pop_count > allowed_popcnt {
-      Err(TransitionError::PoppedTooMany {
-        popcnt: pop_count,
-        available: allowed_popcnt,
-      })?
+    pop_n_and_push(stack, pop_count, to_push)
+  }
+
+  /// Apply the transition, recording its outcome.
+  ///
+  /// Sequences and conditionals are flattened into the log.
+  fn apply_logged<S: Stack<T>>(
+    self,
+    stack: &mut S,
+    log: &mut Vec<TransitionOutcome<T>>,
+  ) -> Result<(), TransitionError> {
+    match self {
+      Transition::Sequence(transitions) => transitions
+        .into_iter()
+        .try_for_each(|transition| transition.apply_logged(stack, log)),
+      Transition::If {
+        cond,
+        then,
+        otherwise,
+      } => {
+        if cond(stack.states().last().unwrap()) {
+          then.apply_logged(stack, log)
+        } else {
+          otherwise.apply_logged(stack, log)
+        }
+      }
+      Transition::FromFn(f) => f(stack.states()).apply_logged(stack, log),
+      transition => {
+        log.push(transition.apply_to(stack)?);
+        Ok(())
+      }
     }
+  }
+}
 
-    let len = stack.len();
-    let removed: Vec<T> = stack.drain(len - pop_count..).collect();
+fn check_guards<T>(
+  guards: &[Rc<dyn Guard<T>>],
+  stack: &[T],
+  transition: &Transition<T>,
+) -> Result<(), TransitionError> {
+  for guard in guards {
+    guard
+      .check(stack, transition)
+      .map_err(|reason| TransitionError::Rejected(reason.into()))?;
+  }
+  Ok(())
+}
 
-    Ok(if to_push.is_empty() {
-      TransitionOutcome::Revealed(removed)
-    } else {
-      let len = to_push.len();
-      stack.append(&mut to_push);
-      if removed.is_empty() {
-        TransitionOutcome::Pushed
-      } else {
-        TransitionOutcome::SwappedIn(removed, len - 1)
-      }
+/// Pop `pop_count` states off the stack, then push all of `to_push` on top.
+///
+/// Nearly every transition boils down to this.
+fn pop_n_and_push<T, S, I>(
+  stack: &mut S,
+  pop_count: usize,
+  to_push: I,
+) -> Result<TransitionOutcome<T>, TransitionError>
+where
+  S: Stack<T>,
+  I: IntoIterator<Item = T>,
+  I::IntoIter: ExactSizeIterator,
+{
+  let to_push = to_push.into_iter();
+
+  // We need to always leave at least one thing on top
+  let allowed_popcnt = if to_push.len() == 0 {
+    stack.len() - 1
+  } else {
+    stack.len()
+  };
+  if pop_count > allowed_popcnt {
+    Err(TransitionError::PoppedTooMany {
+      popcnt: pop_count,
+      available: allowed_popcnt,
+    })?
+  }
+
+  let removed = stack.drain_from(stack.len() - pop_count);
+
+  let len = stack.len();
+  stack.push_all(to_push);
+  let pushed = stack.len() - len;
+  Ok(if pushed == 0 {
+    TransitionOutcome::Revealed(removed)
+  } else if removed.is_empty() {
+    TransitionOutcome::Pushed(pushed - 1)
+  } else {
+    TransitionOutcome::SwappedIn(removed, pushed - 1)
+  })
+}
+
+/// Push one state on top, without the `Vec` that [`pop_n_and_push`] would need.
+fn push<T, S: Stack<T>>(stack: &mut S, state: T) -> TransitionOutcome<T> {
+  stack.push(state);
+  TransitionOutcome::Pushed(0)
+}
+
+/// Remove the state at the given index, without emptying the stack.
+fn remove_at<T, S: Stack<T>>(
+  stack: &mut S,
+  index: usize,
+) -> Result<TransitionOutcome<T>, TransitionError> {
+  let len = stack.len();
+  if index >= len {
+    return Err(TransitionError::OutOfBounds { index, len });
+  }
+  if len == 1 {
+    return Err(TransitionError::PoppedTooMany {
+      popcnt: 1,
+      available: 0,
+    });
+  }
+  let removed = stack.remove(index);
+  Ok(if index == len - 1 {
+    let mut spare = stack.spare();
+    spare.push(removed);
+    TransitionOutcome::Revealed(spare)
+  } else {
+    TransitionOutcome::Removed(index, removed)
+  })
+}
+
+/// Everything a transition needs to be able to do to a stack.
+///
+/// This lets the same transition code work on a bare `Vec`, and on a [`StateMachine`],
+/// which has to keep its [`StateId`]s lined up with its states.
+trait Stack<T> {
+  /// Any bookkeeping that needs to be put back if a sequence is rolled back.
+  type Saved;
+
+  fn states(&self) -> &[T];
+  /// Get everything else there is to know about the stack, for dry runs.
+  fn tracking(&self) -> Option<dry_run::Tracking<'_, T>>;
+  fn save(&self) -> Self::Saved;
+  fn restore(&mut self, saved: Self::Saved);
+  fn position_of(&self, id: StateId) -> Option<usize>;
+  fn marked(&self, label: &str) -> Option<StateId>;
+  fn construct(&self, key: &str) -> Option<T>;
+  fn drain_from(&mut self, index: usize) -> Vec<T>;
+  /// Get an empty `Vec` to put removed states in, which might already have room.
+  fn spare(&mut self) -> Vec<T>;
+  fn push_all<I: Iterator<Item = T>>(&mut self, states: I);
+  fn push(&mut self, state: T);
+  /// Pop the top state. The stack must have more than one state.
+  fn pop(&mut self) -> T;
+  /// Put a new state in place of the top one, and return the old one.
+  fn swap_top(&mut self, state: T) -> T;
+  fn insert(&mut self, index: usize, state: T);
+  fn remove(&mut self, index: usize) -> T;
+  fn replace(&mut self, states: Vec<T>) -> Vec<T>;
+  fn defer(&mut self, transition: Transition<T>)
+    -> Result<(), TransitionError>;
+
+  fn len(&self) -> usize {
+    self.states().len()
+  }
+}
+
+impl<T> Stack<T> for Vec<T> {
+  type Saved = ();
+
+  fn states(&self) -> &[T] {
+    self
+  }
+
+  fn tracking(&self) -> Option<dry_run::Tracking<'_, T>> {
+    None
+  }
+
+  fn save(&self) {}
+
+  fn restore(&mut self, _: ()) {}
+
+  fn position_of(&self, _: StateId) -> Option<usize> {
+    None
+  }
+
+  fn marked(&self, _: &str) -> Option<StateId> {
+    None
+  }
+
+  fn construct(&self, _: &str) -> Option<T> {
+    None
+  }
+
+  fn drain_from(&mut self, index: usize) -> Vec<T> {
+    self.drain(index..).collect()
+  }
+
+  fn spare(&mut self) -> Vec<T> {
+    Vec::new()
+  }
+
+  fn push_all<I: Iterator<Item = T>>(&mut self, states: I) {
+    self.extend(states)
+  }
+
+  fn push(&mut self, state: T) {
+    Vec::push(self, state)
+  }
+
+  fn pop(&mut self) -> T {
+    Vec::pop(self).unwrap()
+  }
+
+  fn swap_top(&mut self, state: T) -> T {
+    std::mem::replace(self.last_mut().unwrap(), state)
+  }
+
+  fn insert(&mut self, index: usize, state: T) {
+    Vec::insert(self, index, state)
+  }
+
+  fn remove(&mut self, index: usize) -> T {
+    Vec::remove(self, index)
+  }
+
+  fn replace(&mut self, states: Vec<T>) -> Vec<T> {
+    std::mem::replace(self, states)
+  }
+
+  fn defer(&mut self, _: Transition<T>) -> Result<(), TransitionError> {
+    Err(TransitionError::NowhereToDefer)
+  }
+}
+
+/// A [`StateMachine`]'s stack, with the ids of each state alongside it.
+struct Tracked<'a, T> {
+  stack: &'a mut Vec<T>,
+  ids: &'a mut Ids,
+  marks: &'a HashMap<Cow<'static, str>, StateId>,
+  protected: &'a [StateId],
+  factories: &'a HashMap<Cow<'static, str>, registry::Factory<T>>,
+  spare: &'a mut Vec<T>,
+  /// Transitions deferred while applying, to go on the queue for real if everything works out.
+  deferred: Vec<Transition<T>>,
+}
+
+impl<T> Tracked<'_, T> {
+  /// Do something to the stack, and roll it back if it removed a protected state.
+  fn apply_protected(
+    &mut self,
+    f: impl FnOnce(&mut Self) -> Result<TransitionOutcome<T>, TransitionError>,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    if self.protected.is_empty() {
+      return f(self);
+    }
+    let saved = self.save();
+    let outcome = f(self)?;
+    if self.lost_protected() {
+      outcome.undo(self);
+      self.restore(saved);
+      return Err(TransitionError::Protected);
+    }
+    Ok(outcome)
+  }
+
+  fn lost_protected(&self) -> bool {
+    let ids = &self.ids;
+    self
+      .protected
+      .iter()
+      .any(|id| ids.position_of(*id).is_none())
+  }
+}
+
+impl<T> Stack<T> for Tracked<'_, T> {
+  type Saved = (Vec<StateId>, usize);
+
+  fn states(&self) -> &[T] {
+    self.stack
+  }
+
+  fn tracking(&self) -> Option<dry_run::Tracking<'_, T>> {
+    Some(dry_run::Tracking {
+      ids: self.ids,
+      marks: self.marks,
+      protected: self.protected,
+      factories: self.factories,
     })
   }
+
+  fn save(&self) -> Self::Saved {
+    (self.ids.ids.clone(), self.deferred.len())
+  }
+
+  fn restore(&mut self, (ids, deferred_len): Self::Saved) {
+    self.ids.ids = ids;
+    self.deferred.truncate(deferred_len);
+  }
+
+  fn position_of(&self, id: StateId) -> Option<usize> {
+    self.ids.position_of(id)
+  }
+
+  fn marked(&self, label: &str) -> Option<StateId> {
+    self.marks.get(label).copied()
+  }
+
+  fn construct(&self, key: &str) -> Option<T> {
+    self.factories.get(key).map(|factory| factory())
+  }
+
+  fn drain_from(&mut self, index: usize) -> Vec<T> {
+    self.ids.ids.truncate(index);
+    let mut removed = self.spare();
+    removed.extend(self.stack.drain(index..));
+    removed
+  }
+
+  fn spare(&mut self) -> Vec<T> {
+    std::mem::take(self.spare)
+  }
+
+  fn push_all<I: Iterator<Item = T>>(&mut self, states: I) {
+    self.stack.extend(states);
+    self.ids.sync(self.stack.len());
+  }
+
+  fn push(&mut self, state: T) {
+    self.stack.push(state);
+    let id = self.ids.fresh();
+    self.ids.ids.push(id);
+  }
+
+  fn pop(&mut self) -> T {
+    self.ids.ids.pop();
+    self.stack.pop().unwrap()
+  }
+
+  fn swap_top(&mut self, state: T) -> T {
+    // the new state is a different state, so it gets a new id
+    let id = self.ids.fresh();
+    *self.ids.ids.last_mut().unwrap() = id;
+    std::mem::replace(self.stack.last_mut().unwrap(), state)
+  }
+
+  fn insert(&mut self, index: usize, state: T) {
+    self.stack.insert(index, state);
+    let id = self.ids.fresh();
+    self.ids.ids.insert(index, id);
+  }
+
+  fn remove(&mut self, index: usize) -> T {
+    self.ids.ids.remove(index);
+    self.stack.remove(index)
+  }
+
+  fn replace(&mut self, states: Vec<T>) -> Vec<T> {
+    self.ids.ids.clear();
+    self.ids.sync(states.len());
+    std::mem::replace(self.stack, states)
+  }
+
+  fn defer(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<(), TransitionError> {
+    self.deferred.push(transition);
+    Ok(())
+  }
 }
 
 /// What happened to the state stack after applying a transition.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TransitionOutcome<T> {
   /// Nothing happened!
   None,
-  /// A state was newly pushed to the stack.
-  Pushed,
+  /// The new state got pushed on top of the stack, along with N things below it.
+  Pushed(usize),
   /// The top state on the stack was revealed after things were removed from on top of it.
   /// The `Vec` has all the states that used to be on top, with the last element being the previous
   /// top of the stack.
   Revealed(Vec<T>),
   /// Things were removed from the stack, and then the new state got pushed on top along with N things below it.
   SwappedIn(Vec<T>, usize),
+  /// The entire stack was replaced. The `Vec` is the old stack, bottommost state first.
+  Replaced(Vec<T>),
+  /// A state was inserted underneath the active state, at the given index.
+  Inserted(usize),
+  /// A state underneath the active state was removed from the given index.
+  Removed(usize, T),
+  /// A state underneath the active state at the given index was replaced. This has the old state.
+  SwappedAt(usize, T),
+  /// States directly underneath the active state were removed, leaving the active state in place.
+  /// The `Vec` has the removed states, with the last element being the one that was right under
+  /// the active state.
+  RemovedBelow(Vec<T>),
+  /// A [`Transition::Sequence`] was applied. This has the outcome of every transition in it, in
+  /// order. Nested sequences are flattened into this one, and so are conditionals; only the outcome
+  /// of the branch that ran is included.
+  Sequence(Vec<TransitionOutcome<T>>),
+  /// The transition was put on the queue with [`Transition::Defer`], and nothing happened yet.
+  Deferred,
+  /// A [`Transition::If`] was applied. The `bool` is `true` if the `then` branch ran,
+  /// and this has the outcome of whichever branch it was.
+  Conditional(bool, Box<TransitionOutcome<T>>),
   // MTF
   // FTM
 }
 
+impl<T> TransitionOutcome<T> {
+  /// Convert the states in this outcome into a different type of state.
+  pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> TransitionOutcome<U> {
+    self.map_inner(&mut f)
+  }
+
+  fn map_inner<U>(self, f: &mut impl FnMut(T) -> U) -> TransitionOutcome<U> {
+    let mut map_vec =
+      |states: Vec<T>| states.into_iter().map(&mut *f).collect();
+    match self {
+      TransitionOutcome::None => TransitionOutcome::None,
+      TransitionOutcome::Pushed(under) => TransitionOutcome::Pushed(under),
+      TransitionOutcome::Revealed(removed) => {
+        TransitionOutcome::Revealed(map_vec(removed))
+      }
+      TransitionOutcome::SwappedIn(removed, under) => {
+        TransitionOutcome::SwappedIn(map_vec(removed), under)
+      }
+      TransitionOutcome::Replaced(old) => {
+        TransitionOutcome::Replaced(map_vec(old))
+      }
+      TransitionOutcome::Inserted(index) => TransitionOutcome::Inserted(index),
+      TransitionOutcome::Removed(index, state) => {
+        TransitionOutcome::Removed(index, f(state))
+      }
+      TransitionOutcome::SwappedAt(index, state) => {
+        TransitionOutcome::SwappedAt(index, f(state))
+      }
+      TransitionOutcome::RemovedBelow(removed) => {
+        TransitionOutcome::RemovedBelow(map_vec(removed))
+      }
+      TransitionOutcome::Sequence(outcomes) => TransitionOutcome::Sequence(
+        outcomes
+          .into_iter()
+          .map(|outcome| outcome.map_inner(f))
+          .collect(),
+      ),
+      TransitionOutcome::Deferred => TransitionOutcome::Deferred,
+      TransitionOutcome::Conditional(took_then, outcome) => {
+        TransitionOutcome::Conditional(
+          took_then,
+          Box::new(outcome.map_inner(f)),
+        )
+      }
+    }
+  }
+
+  /// Get the transition that undoes the transition this is the outcome of, putting the stack back
+  /// how it was right before it. This hands the removed states back to the stack.
+  ///
+  /// This is only guaranteed to work if nothing else has happened to the stack since. States that
+  /// get put back are new states as far as the state machine is concerned, so they get new
+  /// [`StateId`]s. Deferred transitions stay deferred.
+  pub fn invert(self) -> Transition<T>
+  where
+    T: 'static,
+  {
+    match self {
+      TransitionOutcome::None | TransitionOutcome::Deferred => Transition::None,
+      TransitionOutcome::Pushed(under) => Transition::PopN(under + 1),
+      TransitionOutcome::Revealed(removed) if removed.is_empty() => {
+        Transition::None
+      }
+      TransitionOutcome::Revealed(removed) => {
+        Transition::PopNAndPush(0, removed)
+      }
+      TransitionOutcome::SwappedIn(removed, under) => {
+        Transition::PopNAndPush(under + 1, removed)
+      }
+      TransitionOutcome::Replaced(old) => Transition::ReplaceAll(old),
+      TransitionOutcome::Inserted(index) => Transition::RemoveAt(index),
+      TransitionOutcome::Removed(index, state) => {
+        Transition::InsertAt(index, state)
+      }
+      TransitionOutcome::SwappedAt(index, state) => Transition::Sequence(vec![
+        Transition::RemoveAt(index),
+        Transition::InsertAt(index, state),
+      ]),
+      TransitionOutcome::RemovedBelow(removed) => {
+        // they go back under the active state, wherever that is by then
+        Transition::from_fn(move |stack| {
+          let index = stack.len() - 1;
+          Transition::Sequence(
+            removed
+              .into_iter()
+              .enumerate()
+              .map(|(i, state)| Transition::InsertAt(index + i, state))
+              .collect(),
+          )
+        })
+      }
+      TransitionOutcome::Sequence(outcomes) => Transition::Sequence(
+        outcomes.into_iter().rev().map(Self::invert).collect(),
+      ),
+      TransitionOutcome::Conditional(_, outcome) => outcome.invert(),
+    }
+  }
+
+  /// Put the stack back the way it was before the transition that caused this outcome.
+  ///
+  /// Stack bookkeeping, like ids and deferred transitions, is put back by [`Stack::restore`].
+  fn undo<S: Stack<T>>(self, stack: &mut S) {
+    self.revert(stack);
+  }
+
+  /// Undo this outcome, and get the outcome of undoing it. Reverting that redoes it.
+  ///
+  /// Deferred transitions stay on the queue.
+  fn revert<S: Stack<T>>(self, stack: &mut S) -> TransitionOutcome<T> {
+    let len = stack.len();
+    match self {
+      // rolling back the queue is handled by `Stack::restore`
+      TransitionOutcome::None | TransitionOutcome::Deferred => {
+        TransitionOutcome::None
+      }
+      TransitionOutcome::Pushed(under) => {
+        TransitionOutcome::Revealed(stack.drain_from(len - 1 - under))
+      }
+      TransitionOutcome::Revealed(removed) => match removed.len() {
+        0 => TransitionOutcome::None,
+        count => {
+          stack.push_all(removed.into_iter());
+          TransitionOutcome::Pushed(count - 1)
+        }
+      },
+      TransitionOutcome::SwappedIn(removed, under) => {
+        let pushed = stack.drain_from(len - 1 - under);
+        match removed.len() {
+          0 => TransitionOutcome::Revealed(pushed),
+          count => {
+            stack.push_all(removed.into_iter());
+            TransitionOutcome::SwappedIn(pushed, count - 1)
+          }
+        }
+      }
+      TransitionOutcome::Replaced(old) => {
+        TransitionOutcome::Replaced(stack.replace(old))
+      }
+      TransitionOutcome::Inserted(index) => {
+        TransitionOutcome::Removed(index, stack.remove(index))
+      }
+      TransitionOutcome::Removed(index, state) => {
+        stack.insert(index, state);
+        TransitionOutcome::Inserted(index)
+      }
+      TransitionOutcome::SwappedAt(index, state) => {
+        let old = stack.remove(index);
+        stack.insert(index, state);
+        TransitionOutcome::SwappedAt(index, old)
+      }
+      TransitionOutcome::RemovedBelow(removed) => {
+        let index = len - 1;
+        let inserted = (index..).zip(removed).map(|(index, state)| {
+          stack.insert(index, state);
+          TransitionOutcome::Inserted(index)
+        });
+        TransitionOutcome::Sequence(inserted.collect())
+      }
+      TransitionOutcome::Sequence(outcomes) => TransitionOutcome::Sequence(
+        outcomes
+          .into_iter()
+          .rev()
+          .map(|outcome| outcome.revert(stack))
+          .collect(),
+      ),
+      TransitionOutcome::Conditional(took_then, outcome) => {
+        TransitionOutcome::Conditional(
+          took_then,
+          Box::new(outcome.revert(stack)),
+        )
+      }
+    }
+  }
+}
+
+/// The error from [`Transition::apply_recoverable`] and [`StateMachine::apply_recoverable`],
+/// along with the transition that failed if it's still around.
+pub type RecoverableError<T> = (TransitionError, Option<Transition<T>>);
+
 /// Something went wrong when applying a transition.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransitionError {
   /// Tried to pop too many things off the stack.
   PoppedTooMany {
@@ -200,9 +2609,48 @@ pub enum TransitionError {
     /// How many states you were *allowed* to pop.
     ///
     /// If you are pushing more onto the stack, this is the length of the state machine.
-    /// Otherwise, this is the length minus 1.
+    /// Otherwise, or if it isn't known yet, like with [`Transition::PopNAndPushWith`] before its
+    /// closure runs, this is the length minus 1.
     available: usize,
   },
+  /// Tried to replace the stack with nothing.
+  EmptyReplacement,
+  /// Tried to touch a state at an index past the end of the stack.
+  OutOfBounds {
+    /// The index you asked for.
+    ///
+    /// For transitions that count down from the top, like [`Transition::SwapAt`], this is the
+    /// depth.
+    index: usize,
+    /// How many states were in the stack.
+    len: usize,
+  },
+  /// Tried to find a state by its id, but it's not on the stack anymore.
+  NoSuchState(StateId),
+  /// Tried to find a state by its mark, but no state on the stack has it.
+  NoSuchMark,
+  /// Tried to push a state with [`Transition::PushKey`], but nothing is registered with that key.
+  NoSuchKey,
+  /// Tried to [roll back](StateMachine::rollback_to) to a checkpoint that doesn't exist.
+  NoSuchCheckpoint,
+  /// Tried to defer a transition on a bare `Vec`, which has no queue to put it on.
+  NowhereToDefer,
+  /// Tried to change the stack while the machine was [frozen](StateMachine::freeze).
+  Locked,
+  /// Tried to remove a state that was protected with [`StateMachine::protect_bottom`].
+  Protected,
+  /// A [`Guard`] on the state machine rejected the transition, for this reason.
+  Rejected(Cow<'static, str>),
+  /// A [`State::on_enter`] or [`State::on_exit`] hook failed for this reason, so the transition
+  /// was undone. See [`StateMachine::apply_with_hooks`].
+  HookFailed(Cow<'static, str>),
+  /// An [`Invariant`] failed after the transition, so it was undone.
+  /// See [`StateMachine::on_violation`].
+  InvariantViolated,
+  /// The [`Interceptor`] held the transition, so a helper like [`StateMachine::pop`] couldn't
+  /// hand back what it removed, or one like [`StateMachine::push_modal`] couldn't attach anything
+  /// to the new state. The transition is still held, and applies when it's released.
+  Held,
 }
 
 impl Display for TransitionError {
@@ -213,8 +2661,61 @@ impl Display for TransitionError {
         "Tried to pop {} states, but could only pop {}",
         popcnt, available
       ),
+      TransitionError::EmptyReplacement => {
+        write!(f, "Tried to replace the stack with no states")
+      }
+      TransitionError::OutOfBounds { index, len } => write!(
+        f,
+        "Tried to access index {}, but the stack only has {} states",
+        index, len
+      ),
+      TransitionError::NoSuchState(id) => {
+        write!(f, "There's no state with id {} on the stack", id.0)
+      }
+      TransitionError::NoSuchMark => {
+        write!(f, "There's no state with that mark on the stack")
+      }
+      TransitionError::NoSuchKey => {
+        write!(f, "There's nothing registered with that key")
+      }
+      TransitionError::NoSuchCheckpoint => {
+        write!(f, "There's no checkpoint with that label")
+      }
+      TransitionError::NowhereToDefer => {
+        write!(f, "Tried to defer a transition with no queue to put it on")
+      }
+      TransitionError::Locked => {
+        write!(f, "Tried to change a frozen state machine")
+      }
+      TransitionError::Protected => {
+        write!(f, "Tried to remove a protected state")
+      }
+      TransitionError::Rejected(reason) => {
+        write!(f, "The transition was rejected: {}", reason)
+      }
+      TransitionError::HookFailed(reason) => {
+        write!(f, "A state's hook failed: {}", reason)
+      }
+      TransitionError::InvariantViolated => {
+        write!(f, "The transition broke one of the stack's invariants")
+      }
+      TransitionError::Held => {
+        write!(f, "The transition was held by an interceptor")
+      }
     }
   }
 }
 
 impl std::error::Error for TransitionError {}
+
+/// Tried to make a [`StateMachine`] with no states in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EmptyStackError;
+
+impl Display for EmptyStackError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "A state machine needs at least one state")
+  }
+}
+
+impl std::error::Error for EmptyStackError {}
@@ -0,0 +1,59 @@
+use crate::{StateMachine, TransitionError, TransitionKind, TransitionOutcome};
+
+impl<T> StateMachine<T> {
+  /// Whether the logger wants any of the records from [`StateMachine::log`]. Failures are the
+  /// loudest, at warn level, so if those aren't wanted, nothing is.
+  pub(crate) fn is_logged() -> bool {
+    log::log_enabled!(log::Level::Warn)
+  }
+
+  /// Log a transition that was just applied.
+  ///
+  /// Successful transitions are logged at debug level, with the whole stack at trace level,
+  /// and failed ones at warn level. States are shown with their [names](StateMachine::name_states),
+  /// or their ids if the machine can't name them.
+  pub(crate) fn log(
+    &self,
+    kind: TransitionKind,
+    depth_before: usize,
+    result: Result<&TransitionOutcome<T>, TransitionError>,
+  ) {
+    let depth = self.stack.len();
+    match result {
+      Ok(outcome) => {
+        log::debug!(
+          "applied {:?}: popped {}, depth {} -> {}, active {}",
+          kind,
+          outcome.removed_count(),
+          depth_before,
+          depth,
+          self.render(depth - 1)
+        );
+        if log::log_enabled!(log::Level::Trace) {
+          let stack: Vec<_> =
+            (0..depth).map(|index| self.render(index)).collect();
+          log::trace!("stack is now [{}]", stack.join(", "));
+        }
+      }
+      Err(err) => {
+        log::warn!(
+          "{:?} failed at depth {}, active {}: {}",
+          kind,
+          depth,
+          self.render(depth - 1),
+          err
+        );
+      }
+    }
+  }
+
+  /// Show the state at the given index in a log message.
+  fn render(&self, index: usize) -> String {
+    self
+      .name_at(index)
+      .unwrap_or_else(|| match self.id_at(index) {
+        Some(id) => format!("#{}", id.0),
+        None => String::from("?"),
+      })
+  }
+}
@@ -0,0 +1,115 @@
+use crate::{StateId, StateMachine};
+
+/// Who a message posted to a [`Mailbox`] is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Recipient {
+  /// The state directly under the sender.
+  Parent,
+  /// The bottommost state.
+  Bottom,
+  /// The state with this id.
+  Id(StateId),
+  /// Every state apart from the sender.
+  Broadcast,
+}
+
+/// Messages states have posted to each other, waiting to be delivered with
+/// [`StateMachine::deliver`].
+///
+/// Nothing on the machine needs this, so you only need one if your states talk to each other.
+/// Keep it somewhere states can get at, like in the context passed to [`StateMachine::tick`],
+/// and deliver it before the next tick.
+#[derive(Debug)]
+pub struct Mailbox<M> {
+  letters: Vec<Letter<M>>,
+}
+
+#[derive(Debug)]
+struct Letter<M> {
+  from: StateId,
+  to: Recipient,
+  message: M,
+}
+
+impl<M> Mailbox<M> {
+  /// Create an empty mailbox.
+  pub fn new() -> Self {
+    Self {
+      letters: Vec::new(),
+    }
+  }
+
+  /// Post a message from the state with the id `from`.
+  ///
+  /// The recipient is worked out when the message is delivered, so [`Recipient::Parent`] is
+  /// whatever's under the sender then.
+  pub fn post(&mut self, from: StateId, to: Recipient, message: M) {
+    self.letters.push(Letter { from, to, message });
+  }
+
+  /// Get how many messages are waiting to be delivered.
+  pub fn len(&self) -> usize {
+    self.letters.len()
+  }
+
+  /// Check whether there are no messages waiting to be delivered.
+  pub fn is_empty(&self) -> bool {
+    self.letters.is_empty()
+  }
+
+  /// Throw away every waiting message.
+  pub fn clear(&mut self) {
+    self.letters.clear();
+  }
+}
+
+impl<M> Default for Mailbox<M> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Something that can go on a [`StateMachine`] and get messages of type `M` from other states.
+/// See [`Mailbox`].
+pub trait Receive<M> {
+  /// Read a message from the state with the id `from`.
+  fn receive(&mut self, from: StateId, message: &M);
+}
+
+impl<T> StateMachine<T> {
+  /// Deliver every message in the mailbox, in the order they were posted, and empty it.
+  ///
+  /// Messages whose recipient isn't on the stack anymore are dropped, and so are messages
+  /// to [`Recipient::Parent`] whose sender is gone or is the bottommost state.
+  /// This returns how many times [`Receive::receive`] was called.
+  pub fn deliver<M>(&mut self, mailbox: &mut Mailbox<M>) -> usize
+  where
+    T: Receive<M>,
+  {
+    self.ids.sync(self.stack.len());
+    let mut delivered = 0;
+    for Letter { from, to, message } in mailbox.letters.drain(..) {
+      let index = match to {
+        Recipient::Parent => self
+          .position_of(from)
+          .and_then(|index| index.checked_sub(1)),
+        Recipient::Bottom => Some(0),
+        Recipient::Id(id) => self.position_of(id),
+        Recipient::Broadcast => {
+          for (state, id) in self.stack.iter_mut().zip(&self.ids.ids) {
+            if *id != from {
+              state.receive(from, &message);
+              delivered += 1;
+            }
+          }
+          continue;
+        }
+      };
+      if let Some(index) = index {
+        self.stack[index].receive(from, &message);
+        delivered += 1;
+      }
+    }
+    delivered
+  }
+}
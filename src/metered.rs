@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+use crate::{StateId, StateMachine, TransitionKind};
+
+/// When the active state became active, and what it's called.
+#[derive(Debug, Clone)]
+pub(crate) struct ActiveSince {
+  id: StateId,
+  at: Instant,
+  name: Option<String>,
+}
+
+impl<T> StateMachine<T> {
+  /// Start or stop publishing metrics for every transition applied to this machine. This is off
+  /// to begin with.
+  ///
+  /// - `gerrymander.transitions` counts successful transitions, labeled by `kind`.
+  /// - `gerrymander.transition_errors` counts failed ones, labeled by `kind`.
+  /// - `gerrymander.depth` is how many states are on the stack.
+  /// - `gerrymander.time_in_state` gets how many seconds a state was active for, each time it
+  ///   stops being active, labeled by `state` if the machine can
+  ///   [name states](StateMachine::name_states). The clock starts at the first transition after
+  ///   this is turned on, so the time before that isn't counted.
+  pub fn publish_metrics(&mut self, publish: bool) {
+    self.metered = publish;
+    self.active_since = None;
+  }
+
+  /// Publish metrics for a transition that was just applied.
+  pub(crate) fn meter(&mut self, kind: TransitionKind, failed: bool) {
+    if failed {
+      metrics::counter!("gerrymander.transition_errors", "kind" => kind.name())
+        .increment(1);
+      return;
+    }
+    metrics::counter!("gerrymander.transitions", "kind" => kind.name())
+      .increment(1);
+    metrics::gauge!("gerrymander.depth").set(self.stack.len() as f64);
+
+    let id = self.active_id();
+    if matches!(&self.active_since, Some(since) if since.id == id) {
+      return;
+    }
+    let now = Instant::now();
+    if let Some(since) = self.active_since.take() {
+      let seconds = now.duration_since(since.at).as_secs_f64();
+      match since.name {
+        Some(name) => {
+          metrics::histogram!("gerrymander.time_in_state", "state" => name)
+            .record(seconds)
+        }
+        None => {
+          metrics::histogram!("gerrymander.time_in_state").record(seconds)
+        }
+      }
+    }
+    self.active_since = Some(ActiveSince {
+      id,
+      at: now,
+      name: self.active_name(),
+    });
+  }
+}
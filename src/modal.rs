@@ -0,0 +1,96 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{StateId, StateMachine, TransitionError};
+
+/// A way to get the answer back from a state pushed with [`StateMachine::push_modal`].
+///
+/// The modal state answers by popping itself with
+/// [`Transition::PopWith`](crate::Transition::PopWith), with a value of type `R`.
+#[derive(Debug)]
+pub struct ModalHandle<R> {
+  id: StateId,
+  answer: Rc<RefCell<Option<R>>>,
+}
+
+/// What a [`ModalHandle`] found when it was polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModalStatus<R> {
+  /// The modal state is still on the stack, and hasn't answered yet.
+  Open,
+  /// The modal state popped itself with this answer.
+  Answered(R),
+  /// The modal state is gone, and there's no answer to take. Either it came off the stack
+  /// some other way, or its answer was already taken.
+  Closed,
+}
+
+impl<R> ModalHandle<R> {
+  /// Get the id of the modal state.
+  pub fn id(&self) -> StateId {
+    self.id
+  }
+
+  /// Check whether the modal state has answered yet, taking the answer if it has.
+  ///
+  /// The machine has to be the one the modal state was pushed onto.
+  pub fn poll<T>(&self, machine: &StateMachine<T>) -> ModalStatus<R> {
+    match self.answer.borrow_mut().take() {
+      Some(answer) => ModalStatus::Answered(answer),
+      None if machine.position_of(self.id).is_some() => ModalStatus::Open,
+      None => ModalStatus::Closed,
+    }
+  }
+}
+
+/// A value a state popped itself with, on its way to whoever wants it.
+pub(crate) type Answer = Box<dyn Any>;
+
+/// The id of a state that popped itself with an [`Answer`], and the answer.
+pub(crate) type PoppedWith = (StateId, Answer);
+
+/// Somewhere to put a modal state's answer, with its type erased.
+pub(crate) trait ModalSlot {
+  /// Put the answer in the slot, or hand it back if it's the wrong type.
+  fn fill(&self, answer: Answer) -> Result<(), Answer>;
+}
+
+impl<R: 'static> ModalSlot for RefCell<Option<R>> {
+  fn fill(&self, answer: Answer) -> Result<(), Answer> {
+    *self.borrow_mut() = Some(*answer.downcast()?);
+    Ok(())
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Push a new active state, and get a handle that gets its answer once it pops itself
+  /// with [`Transition::PopWith`](crate::Transition::PopWith).
+  ///
+  /// This is the "open a dialog and wait for what the player picked" pattern. The answer has
+  /// to be an `R`; if the modal state pops with anything else, the handle never sees it.
+  pub fn push_modal<R: 'static>(
+    &mut self,
+    state: T,
+  ) -> Result<ModalHandle<R>, TransitionError> {
+    self.push(state)?;
+    let answer = Rc::new(RefCell::new(None));
+    let id = self.active_id();
+    self.modals.push((id, answer.clone()));
+    Ok(ModalHandle { id, answer })
+  }
+
+  /// Give an answer from the popped state with the given id to its [`ModalHandle`], if it has one.
+  ///
+  /// If nobody took the answer, it's handed back.
+  pub(crate) fn answer_modal(
+    &mut self,
+    id: StateId,
+    answer: Answer,
+  ) -> Option<Answer> {
+    let Some(index) = self.modals.iter().position(|(modal, _)| *modal == id)
+    else {
+      return Some(answer);
+    };
+    let (_, slot) = self.modals.swap_remove(index);
+    slot.fill(answer).err()
+  }
+}
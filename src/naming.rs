@@ -0,0 +1,47 @@
+use std::rc::Rc;
+
+use crate::StateMachine;
+
+/// Something that turns a state into a name that people can read.
+pub(crate) type Namer<T> = Rc<dyn Fn(&T) -> String>;
+
+impl<T> StateMachine<T> {
+  /// Give the machine a way to name its states, so the diagnostics it emits can say which
+  /// states they're about, even if the states aren't [`Debug`](std::fmt::Debug).
+  ///
+  /// With the `tracing` feature, the name goes on every transition's event and on the active
+  /// state's span. With the `log` feature, states are logged with their names instead of their
+  /// ids. With the `metrics` feature, the time spent in each state is labeled with its name.
+  ///
+  /// The namer is shared with clones of the machine, like guards are.
+  pub fn name_states(&mut self, namer: impl Fn(&T) -> String + 'static) {
+    self.namer = Some(Rc::new(namer));
+  }
+
+  /// Name states with their [`Debug`](std::fmt::Debug) output. See [`StateMachine::name_states`].
+  pub fn name_states_with_debug(&mut self)
+  where
+    T: std::fmt::Debug + 'static,
+  {
+    self.name_states(|state| format!("{:?}", state));
+  }
+
+  /// Stop naming states.
+  pub fn clear_state_names(&mut self) {
+    self.namer = None;
+  }
+
+  /// Get the name of the state at the given index, counting up from the bottom.
+  ///
+  /// This is `None` if there's no state there, or the machine hasn't been told
+  /// how to [name states](StateMachine::name_states).
+  pub fn name_at(&self, index: usize) -> Option<String> {
+    let namer = self.namer.as_ref()?;
+    self.stack.get(index).map(|state| namer(state))
+  }
+
+  /// Get the name of the active state. See [`StateMachine::name_at`].
+  pub fn active_name(&self) -> Option<String> {
+    self.name_at(self.stack.len() - 1)
+  }
+}
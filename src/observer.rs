@@ -0,0 +1,90 @@
+use std::rc::Rc;
+
+use crate::{
+  StateId, StateMachine, TransitionError, TransitionKind, TransitionOutcome,
+};
+
+/// What an [`Observer`] gets told about a transition that was applied to a [`StateMachine`].
+#[derive(Debug)]
+pub struct TransitionEvent<'a, T> {
+  /// Which transition it was.
+  pub kind: TransitionKind,
+  /// What happened, or why nothing did.
+  pub result: Result<&'a TransitionOutcome<T>, TransitionError>,
+  /// How many states were on the stack before.
+  pub depth_before: usize,
+  /// How many states are on the stack now.
+  pub depth_after: usize,
+  /// The active state now.
+  pub active: &'a T,
+  /// The whole stack now, bottommost first.
+  pub stack: &'a [T],
+  /// The ids of the states on the stack now, in the same order.
+  pub ids: &'a [StateId],
+}
+
+/// Something that wants to know every time a transition is applied to a [`StateMachine`],
+/// whether it works or not, like a logger or the thing that plays sound effects.
+///
+/// Observers are told after [`StateMachine::apply`] and the methods built on it, including
+/// [`StateMachine::apply_with_hooks`] and [`StateMachine::pop`]. They can't change anything.
+/// [`StateMachine::apply_all`] tells them once for the whole batch.
+///
+/// Changes that aren't transitions aren't seen: [`StateMachine::retain_mut`], [`Extend`],
+/// [`StateMachine::cursor`], and undoing and redoing with an
+/// [`UndoableStateMachine`](crate::UndoableStateMachine).
+///
+/// Observers only hear about transitions, so they don't know what was on the stack before the
+/// first one. Observers that keep track of the stack, like [`StateStats`](crate::StateStats),
+/// have a `start` method to look at it first.
+///
+/// This is implemented for closures with the same signature as [`Observer::observe`].
+pub trait Observer<T> {
+  /// Look at what just happened.
+  fn observe(&self, event: &TransitionEvent<'_, T>);
+}
+
+impl<T, F> Observer<T> for F
+where
+  F: Fn(&TransitionEvent<'_, T>),
+{
+  fn observe(&self, event: &TransitionEvent<'_, T>) {
+    self(event)
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Add an observer, which gets told about every transition applied from now on.
+  ///
+  /// The machine keeps the observer, so to look at what it saw, keep a clone. The observers in
+  /// this crate, [`TransitionGraph`](crate::TransitionGraph), [`AuditTrail`](crate::AuditTrail)
+  /// and [`StateStats`](crate::StateStats), share everything between their clones.
+  pub fn add_observer(&mut self, observer: impl Observer<T> + 'static) {
+    self.observers.push(Rc::new(observer));
+  }
+
+  /// Remove every observer.
+  pub fn clear_observers(&mut self) {
+    self.observers.clear();
+  }
+
+  pub(crate) fn notify_observers(
+    &self,
+    kind: TransitionKind,
+    depth_before: usize,
+    result: Result<&TransitionOutcome<T>, TransitionError>,
+  ) {
+    let event = TransitionEvent {
+      kind,
+      result,
+      depth_before,
+      depth_after: self.stack.len(),
+      active: self.active(),
+      stack: &self.stack,
+      ids: &self.ids.ids,
+    };
+    for observer in &self.observers {
+      observer.observe(&event);
+    }
+  }
+}
@@ -0,0 +1,38 @@
+use std::{borrow::Cow, rc::Rc};
+
+use crate::StateMachine;
+
+/// Makes a new state for [`Transition::PushKey`](crate::Transition::PushKey).
+pub(crate) type Factory<T> = Rc<dyn Fn() -> T>;
+
+impl<T> StateMachine<T> {
+  /// Register a function that makes a new state, so
+  /// [`Transition::PushKey`](crate::Transition::PushKey) with this key can push one.
+  ///
+  /// This means whatever asks for a screen doesn't need to know how to make it, and lets you
+  /// list screens by name in data. If the key already had a function, it's replaced.
+  ///
+  /// If you'd rather use your own enum for keys, give it a way to turn into a `&'static str`.
+  pub fn register(
+    &mut self,
+    key: impl Into<Cow<'static, str>>,
+    factory: impl Fn() -> T + 'static,
+  ) {
+    self.factories.insert(key.into(), Rc::new(factory));
+  }
+
+  /// Remove the function registered with this key.
+  pub fn unregister(&mut self, key: &str) {
+    self.factories.remove(key);
+  }
+
+  /// Check whether there's a function registered with this key.
+  pub fn is_registered(&self, key: &str) -> bool {
+    self.factories.contains_key(key)
+  }
+
+  /// Make a new state with the function registered with this key, without pushing it.
+  pub fn construct(&self, key: &str) -> Option<T> {
+    self.factories.get(key).map(|factory| factory())
+  }
+}
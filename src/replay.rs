@@ -0,0 +1,359 @@
+use std::borrow::Cow;
+
+use crate::{
+  StateId, StateMachine, Transition, TransitionError, TransitionOutcome,
+  TransitionShape,
+};
+
+/// Every transition applied through a [`Recorder`], so they can be [replayed](Script::replay)
+/// later.
+///
+/// Along with the steps, a script has the stack the recording started from, with the ids and
+/// marks it had, so transitions like [`Transition::RemoveId`] and [`Transition::PopToMark`] find
+/// the same states when they're replayed. The machine's other settings, like guards, protected
+/// states and registered factories, aren't part of it, so put them back with
+/// [`Script::replay_with`].
+///
+/// With the `serde` feature, a script can be saved to a single file and loaded back to replay
+/// later. Everything a [`Recorder`] writes down can be serialized.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct Script<T> {
+  initial: Vec<T>,
+  ids: Vec<StateId>,
+  next_id: u64,
+  marks: Vec<(Cow<'static, str>, StateId)>,
+  steps: Vec<ScriptStep<T>>,
+}
+
+/// One transition in a [`Script`], and what happened when it was recorded.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct ScriptStep<T> {
+  /// The transition to apply.
+  pub transition: Transition<T>,
+  /// What applying it did, or `None` if it shouldn't be checked.
+  pub expected: Option<Result<TransitionShape, TransitionError>>,
+  /// The ids the stack should have afterwards, and the next id to hand out, for when the
+  /// transition gives the states different ids than they had when it was recorded.
+  ///
+  /// A [`Recorder`] sets this when it writes a transition down as the stack it left behind.
+  pub ids: Option<(Vec<StateId>, u64)>,
+}
+
+/// A replayed transition didn't do what it did when it was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+  /// The script's starting stack is empty, or its ids don't fit it.
+  ///
+  /// Scripts from a [`Recorder`] never have this problem, but ones loaded from elsewhere might.
+  BadStart,
+  /// The step at this index has [ids](ScriptStep::ids) that don't fit the stack it left behind.
+  ///
+  /// Like [`ReplayError::BadStart`], this only happens with scripts from elsewhere.
+  BadIds(usize),
+  /// The step at this index did something different.
+  Mismatch {
+    /// The index of the step in the script.
+    step: usize,
+    /// What the step did when it was recorded.
+    expected: Result<TransitionShape, TransitionError>,
+    /// What it did this time.
+    actual: Result<TransitionShape, TransitionError>,
+  },
+}
+
+impl std::fmt::Display for ReplayError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ReplayError::BadStart => {
+        write!(f, "The script's starting stack is empty or malformed")
+      }
+      ReplayError::BadIds(step) => {
+        write!(f, "Step {} has ids that don't fit the stack", step)
+      }
+      ReplayError::Mismatch {
+        step,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "Step {} should have done {:?}, but did {:?}",
+        step, expected, actual
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl<T> Script<T> {
+  /// Borrow the states the recording started from, bottommost first.
+  pub fn initial(&self) -> &[T] {
+    &self.initial
+  }
+
+  /// Borrow the recorded steps, first to be applied first.
+  pub fn steps(&self) -> &[ScriptStep<T>] {
+    &self.steps
+  }
+
+  /// Make a new machine with the stack the recording started from, and
+  /// [replay](StateMachine::replay) every step on it, checking each one does what it did
+  /// when it was recorded.
+  pub fn replay(self) -> Result<StateMachine<T>, ReplayError> {
+    self.replay_with(|_| {})
+  }
+
+  /// Like [`Script::replay`], but let `setup` configure the machine before any steps are
+  /// replayed, with the guards, factories, protected states and so on that the recorded
+  /// machine had.
+  pub fn replay_with(
+    self,
+    setup: impl FnOnce(&mut StateMachine<T>),
+  ) -> Result<StateMachine<T>, ReplayError> {
+    let (mut machine, steps) = self.start()?;
+    setup(&mut machine);
+    machine.replay(steps)?;
+    Ok(machine)
+  }
+
+  /// Make a new machine with the stack the recording started from, and apply every step
+  /// on it without checking what they do.
+  ///
+  /// This only fails if the script can't be started. Steps that fail are skipped over.
+  pub fn run(self) -> Result<StateMachine<T>, ReplayError> {
+    self.run_with(|_| {})
+  }
+
+  /// Like [`Script::run`], but let `setup` configure the machine first, like
+  /// [`Script::replay_with`].
+  pub fn run_with(
+    self,
+    setup: impl FnOnce(&mut StateMachine<T>),
+  ) -> Result<StateMachine<T>, ReplayError> {
+    let (mut machine, steps) = self.start()?;
+    setup(&mut machine);
+    for step in steps {
+      let applied = machine.apply(step.transition).is_ok();
+      if let Some((ids, next)) = step.ids.filter(|_| applied) {
+        machine.restore_ids(ids, next);
+      }
+    }
+    Ok(machine)
+  }
+
+  /// Make the machine the recording started from, with its ids and marks.
+  fn start(self) -> Result<(StateMachine<T>, Vec<ScriptStep<T>>), ReplayError> {
+    let mut machine = StateMachine::try_new_many(self.initial)
+      .map_err(|_| ReplayError::BadStart)?;
+    if !machine.restore_ids(self.ids, self.next_id) {
+      return Err(ReplayError::BadStart);
+    }
+    machine.marks.extend(self.marks);
+    Ok((machine, self.steps))
+  }
+}
+
+/// A [`StateMachine`] that writes down every transition applied to it in a [`Script`].
+///
+/// This is for reproducing bugs: record a session, save the script, and replay it later to
+/// get back to the same stack in the same way.
+#[derive(Debug)]
+pub struct Recorder<T> {
+  machine: StateMachine<T>,
+  script: Script<T>,
+}
+
+impl<T: Clone> Recorder<T> {
+  /// Start recording the machine from the stack it has now, along with its ids and marks.
+  pub fn new(mut machine: StateMachine<T>) -> Self {
+    machine.ids.sync(machine.stack.len());
+    let mut marks: Vec<_> = machine
+      .marks
+      .iter()
+      .filter(|(_, id)| machine.position_of(**id).is_some())
+      .map(|(label, id)| (label.clone(), *id))
+      .collect();
+    marks.sort();
+    let script = Script {
+      initial: machine.stack.clone(),
+      ids: machine.ids.ids.clone(),
+      next_id: machine.ids.next,
+      marks,
+      steps: Vec::new(),
+    };
+    Self { machine, script }
+  }
+
+  /// Apply the transition, like [`StateMachine::apply`], and write it down along with what it did.
+  ///
+  /// Transitions with closures in them can't be copied, so they're written down as the
+  /// [`Transition::PopNAndPush`] that does the same thing to the stack, along with the new ids,
+  /// so later steps find the same states on replay. The value from a [`Transition::PopWith`]
+  /// can't be copied either, so it isn't replayed. If they fail, there's nothing to write down.
+  pub fn apply(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    let copy = transition.try_clone();
+    self.machine.ids.sync(self.machine.stack.len());
+    let ids_before = self.machine.ids.ids.clone();
+    let res = self.machine.apply(transition);
+    let step = match copy {
+      Some(transition) => Some(ScriptStep {
+        transition,
+        expected: Some(
+          res
+            .as_ref()
+            .map(TransitionOutcome::shape)
+            .map_err(|err| err.clone()),
+        ),
+        ids: None,
+      }),
+      None => res.is_ok().then(|| self.equivalent_step(&ids_before)),
+    };
+    self.script.steps.extend(step);
+    res
+  }
+
+  /// Work out the smallest [`Transition::PopNAndPush`] that gets from a stack with these ids
+  /// to the machine's stack now.
+  fn equivalent_step(&self, ids_before: &[StateId]) -> ScriptStep<T> {
+    let ids = self.machine.get_ids();
+    let kept = ids_before
+      .iter()
+      .zip(ids)
+      .take_while(|(before, after)| before == after)
+      .count();
+    let popped = ids_before.len() - kept;
+    let pushed = self.machine.get_stack()[kept..].to_vec();
+    let shape = match (popped, pushed.len()) {
+      (popped, 0) => TransitionShape::Revealed(popped),
+      (0, pushed) => TransitionShape::Pushed(pushed - 1),
+      (popped, pushed) => TransitionShape::SwappedIn(popped, pushed - 1),
+    };
+    ScriptStep {
+      transition: Transition::PopNAndPush(popped, pushed),
+      expected: Some(Ok(shape)),
+      ids: Some((ids.to_vec(), self.machine.ids.next)),
+    }
+  }
+}
+
+impl<T> Recorder<T> {
+  /// Borrow the state machine.
+  pub fn machine(&self) -> &StateMachine<T> {
+    &self.machine
+  }
+
+  /// Borrow what's been recorded so far.
+  pub fn script(&self) -> &Script<T> {
+    &self.script
+  }
+
+  /// Stop recording, and get the machine and the script back.
+  pub fn finish(self) -> (StateMachine<T>, Script<T>) {
+    (self.machine, self.script)
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Apply every step in order, checking each one does what it did when it was recorded.
+  ///
+  /// This stops at the first step that does something different. Steps before it stay applied.
+  pub fn replay(
+    &mut self,
+    steps: impl IntoIterator<Item = ScriptStep<T>>,
+  ) -> Result<(), ReplayError> {
+    for (
+      step,
+      ScriptStep {
+        transition,
+        expected,
+        ids,
+      },
+    ) in steps.into_iter().enumerate()
+    {
+      let actual = self.apply(transition).map(|outcome| outcome.shape());
+      if let Some(expected) = expected {
+        if actual != expected {
+          return Err(ReplayError::Mismatch {
+            step,
+            expected,
+            actual,
+          });
+        }
+      }
+      if let Some((ids, next)) = ids.filter(|_| actual.is_ok()) {
+        if !self.restore_ids(ids, next) {
+          return Err(ReplayError::BadIds(step));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Give the states these ids, and hand out `next` after them, if there's one for each state,
+  /// no two are the same, and they're all below `next`.
+  fn restore_ids(&mut self, ids: Vec<StateId>, next: u64) -> bool {
+    let mut sorted = ids.clone();
+    sorted.sort();
+    if ids.len() != self.stack.len()
+      || sorted.windows(2).any(|pair| pair[0] == pair[1])
+      || sorted.last().is_some_and(|highest| highest.0 >= next)
+    {
+      return false;
+    }
+    self.ids.ids = ids;
+    self.ids.next = next;
+    true
+  }
+}
+
+impl<T: Clone> Transition<T> {
+  /// Copy this transition, unless there's something in it that can't be copied,
+  /// like a closure, an iterator, or a [`Transition::PopWith`] value.
+  pub fn try_clone(&self) -> Option<Self> {
+    Some(match self {
+      Transition::None => Transition::None,
+      Transition::Push(s) => Transition::Push(s.clone()),
+      Transition::Pop => Transition::Pop,
+      Transition::PopN(count) => Transition::PopN(*count),
+      Transition::PopTo(id) => Transition::PopTo(*id),
+      Transition::PopToMark(label) => Transition::PopToMark(label.clone()),
+      Transition::PushKey(key) => Transition::PushKey(key.clone()),
+      Transition::RemoveId(id) => Transition::RemoveId(*id),
+      Transition::Swap(s) => Transition::Swap(s.clone()),
+      Transition::PopNAndPush(count, states) => {
+        Transition::PopNAndPush(*count, states.clone())
+      }
+      Transition::PopAll => Transition::PopAll,
+      Transition::Reset(s) => Transition::Reset(s.clone()),
+      Transition::ReplaceAll(states) => Transition::ReplaceAll(states.clone()),
+      Transition::PopAllAndPush(states) => {
+        Transition::PopAllAndPush(states.clone())
+      }
+      Transition::InsertAt(index, s) => Transition::InsertAt(*index, s.clone()),
+      Transition::RemoveAt(index) => Transition::RemoveAt(*index),
+      Transition::SwapAt(depth, s) => Transition::SwapAt(*depth, s.clone()),
+      Transition::PopBelow(count) => Transition::PopBelow(*count),
+      Transition::Sequence(transitions) => Transition::Sequence(
+        transitions
+          .iter()
+          .map(Transition::try_clone)
+          .collect::<Option<_>>()?,
+      ),
+      Transition::Defer(transition) => {
+        Transition::Defer(Box::new(transition.try_clone()?))
+      }
+      Transition::PushMany(_)
+      | Transition::PopUntil(_)
+      | Transition::If { .. }
+      | Transition::FromFn(_)
+      | Transition::PushWith(_)
+      | Transition::PopNAndPushWith(..)
+      | Transition::PopWith(_) => return None,
+    })
+  }
+}
@@ -0,0 +1,76 @@
+use crate::{StateMachine, Transition, TransitionError, TransitionOutcome};
+
+/// Transitions that states asked for while the machine was busy, to be applied once it isn't.
+///
+/// While you're going through the states with [`StateMachine::for_each_requesting`], the machine is
+/// borrowed, so nothing can call [`StateMachine::apply`]. Instead, each state gets one of these to
+/// ask for transitions with, and they're all applied afterwards in the order they were asked for.
+#[derive(Debug)]
+pub struct TransitionRequests<T> {
+  requests: Vec<Transition<T>>,
+}
+
+impl<T> TransitionRequests<T> {
+  /// Create an empty set of requests.
+  pub fn new() -> Self {
+    Self {
+      requests: Vec::new(),
+    }
+  }
+
+  /// Ask for a transition to be applied. It goes after every transition asked for before it.
+  pub fn request(&mut self, transition: Transition<T>) {
+    self.requests.push(transition);
+  }
+
+  /// Borrow the requested transitions, first to be applied first.
+  pub fn get_requests(&self) -> &[Transition<T>] {
+    &self.requests
+  }
+
+  /// Get how many transitions have been requested.
+  pub fn len(&self) -> usize {
+    self.requests.len()
+  }
+
+  /// Check whether no transitions have been requested.
+  pub fn is_empty(&self) -> bool {
+    self.requests.is_empty()
+  }
+}
+
+impl<T> Default for TransitionRequests<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Call the function on every state, from topmost (active) to bottommost, and then apply every
+  /// transition they requested with [`StateMachine::apply_requests`].
+  pub fn for_each_requesting(
+    &mut self,
+    mut f: impl FnMut(&mut T, &mut TransitionRequests<T>),
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>> {
+    let mut requests = TransitionRequests::new();
+    for state in self.stack.iter_mut().rev() {
+      f(state, &mut requests);
+    }
+    self.apply_requests(requests)
+  }
+
+  /// Apply every requested transition, in the order they were requested.
+  ///
+  /// Each one is applied separately, so one failing doesn't stop the rest. This returns
+  /// the result of each one.
+  pub fn apply_requests(
+    &mut self,
+    requests: TransitionRequests<T>,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>> {
+    requests
+      .requests
+      .into_iter()
+      .map(|transition| self.apply(transition))
+      .collect()
+  }
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use crate::{State, StateMachine, TransitionError, TransitionOutcome};
+
+/// A main loop helper that owns a [`StateMachine`] and ticks it at a fixed timestep.
+///
+/// Each frame, tell it how much time passed with [`Runner::frame`]. It calls [`StateMachine::tick`]
+/// once for every whole timestep that's built up, and then lets you draw the visible states with
+/// how far it is into the next timestep, so you can interpolate between updates.
+///
+/// It doesn't read the clock itself, so it works with whatever timer your framework gives you.
+#[derive(Debug)]
+pub struct Runner<T> {
+  machine: StateMachine<T>,
+  timestep: Duration,
+  accumulated: Duration,
+  max_steps: usize,
+}
+
+impl<T> Runner<T> {
+  /// Create a new runner that ticks the machine once every `timestep`.
+  ///
+  /// By default, it catches up on at most 8 timesteps in one frame, and drops the rest of the time.
+  /// Change this with [`Runner::max_steps`].
+  ///
+  /// ## Panics
+  ///
+  /// If `timestep` is zero.
+  pub fn new(machine: StateMachine<T>, timestep: Duration) -> Self {
+    assert!(!timestep.is_zero(), "A runner's timestep can't be zero");
+    Self {
+      machine,
+      timestep,
+      accumulated: Duration::ZERO,
+      max_steps: 8,
+    }
+  }
+
+  /// Set the most timesteps to catch up on in one frame, so a long stall doesn't make the next
+  /// frame take even longer. Any time past that is dropped.
+  pub fn max_steps(mut self, max_steps: usize) -> Self {
+    self.max_steps = max_steps;
+    self
+  }
+
+  /// Get how long each timestep is.
+  pub fn timestep(&self) -> Duration {
+    self.timestep
+  }
+
+  /// Get how far the runner is into the next timestep, from `0.0` to just under `1.0`.
+  pub fn alpha(&self) -> f32 {
+    self.accumulated.as_secs_f32() / self.timestep.as_secs_f32()
+  }
+
+  /// Borrow the state machine.
+  pub fn machine(&self) -> &StateMachine<T> {
+    &self.machine
+  }
+
+  /// Mutably borrow the state machine.
+  pub fn machine_mut(&mut self) -> &mut StateMachine<T> {
+    &mut self.machine
+  }
+
+  /// Take the state machine back out of the runner.
+  pub fn into_machine(self) -> StateMachine<T> {
+    self.machine
+  }
+
+  /// Add the elapsed time, and [tick](StateMachine::tick) the machine once for every whole timestep
+  /// that's built up.
+  ///
+  /// This returns the result of each tick.
+  pub fn advance<Ctx>(
+    &mut self,
+    elapsed: Duration,
+    ctx: &mut Ctx,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>>
+  where
+    T: State<Ctx>,
+  {
+    self.accumulated += elapsed;
+    let mut results = Vec::new();
+    while self.accumulated >= self.timestep {
+      if results.len() == self.max_steps {
+        self.accumulated = Duration::ZERO;
+        break;
+      }
+      self.accumulated -= self.timestep;
+      results.push(self.machine.tick(ctx));
+    }
+    results
+  }
+
+  /// [Advance](Runner::advance) the runner, and then call `draw` on every
+  /// [visible](StateMachine::iter_visible) state, bottommost first, with the
+  /// [alpha](Runner::alpha).
+  pub fn frame<Ctx>(
+    &mut self,
+    elapsed: Duration,
+    ctx: &mut Ctx,
+    mut draw: impl FnMut(&T, f32),
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>>
+  where
+    T: State<Ctx>,
+  {
+    let results = self.advance(elapsed, ctx);
+    let alpha = self.alpha();
+    for state in self.machine.iter_visible::<Ctx>() {
+      draw(state, alpha);
+    }
+    results
+  }
+}
@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+
+use crate::{StateMachine, Transition, TransitionError, TransitionOutcome};
+
+/// A copy of a [`StateMachine`]'s stack and marks, from [`StateMachine::snapshot`].
+///
+/// Unlike cloning the whole machine, this only has the states and which of them are marked,
+/// so it's easy to keep around, compare, and (with the `serde` feature) save to disk.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackSnapshot<T> {
+  states: Vec<T>,
+  marks: Vec<(Cow<'static, str>, usize)>,
+}
+
+impl<T> StackSnapshot<T> {
+  /// Borrow the states in the snapshot, bottommost first.
+  pub fn states(&self) -> &[T] {
+    &self.states
+  }
+
+  /// Get the index of the state with this mark in the snapshot, counting up from the bottom.
+  pub fn marked(&self, label: &str) -> Option<usize> {
+    self
+      .marks
+      .iter()
+      .find(|(mark, _)| mark == label)
+      .map(|(_, index)| *index)
+  }
+
+  /// Take the states out of the snapshot, bottommost first.
+  pub fn into_states(self) -> Vec<T> {
+    self.states
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Copy the stack, along with which states are marked.
+  pub fn snapshot(&self) -> StackSnapshot<T>
+  where
+    T: Clone,
+  {
+    let mut marks: Vec<_> = self
+      .marks
+      .iter()
+      .filter_map(|(label, id)| Some((label.clone(), self.position_of(*id)?)))
+      .collect();
+    marks.sort();
+    StackSnapshot {
+      states: self.stack.clone(),
+      marks,
+    }
+  }
+
+  /// Replace the stack with the states in the snapshot, and mark them like they were marked
+  /// when it was taken. Marks that aren't in the snapshot are gone afterwards.
+  ///
+  /// This applies [`Transition::ReplaceAll`], so guards and protected states get a say. Snapshots
+  /// can come from anywhere, so this checks it first: it fails with
+  /// [`TransitionError::EmptyReplacement`] if it has no states, and
+  /// [`TransitionError::OutOfBounds`] if a mark is past the end of the stack. If it fails, nothing
+  /// changes.
+  ///
+  /// If an [`Interceptor`](crate::Interceptor) holds the replacement, this returns
+  /// [`TransitionOutcome::Deferred`] without touching the marks, and releasing it later
+  /// replaces the stack without marking anything.
+  pub fn restore(
+    &mut self,
+    snapshot: StackSnapshot<T>,
+  ) -> Result<TransitionOutcome<T>, TransitionError> {
+    let len = snapshot.states.len();
+    if let Some((_, index)) =
+      snapshot.marks.iter().find(|(_, index)| *index >= len)
+    {
+      return Err(TransitionError::OutOfBounds { index: *index, len });
+    }
+    let outcome = self.apply(Transition::ReplaceAll(snapshot.states))?;
+    if matches!(outcome, TransitionOutcome::Deferred) {
+      // the interceptor held it, so the stack hasn't changed yet
+      return Ok(outcome);
+    }
+    self.marks.clear();
+    for (label, index) in snapshot.marks {
+      self.marks.insert(label, self.ids.ids[index]);
+    }
+    Ok(outcome)
+  }
+}
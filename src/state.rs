@@ -0,0 +1,331 @@
+use std::{any::Any, borrow::Cow};
+
+use crate::{
+  StateId, StateMachine, Transition, TransitionError, TransitionOutcome,
+};
+
+/// Something that can go on a [`StateMachine`] and wants to know when things happen to it.
+///
+/// Every hook does nothing by default, so you only need to write the ones you care about.
+/// They're only called by [`StateMachine::apply_with_hooks`] and [`StateMachine::tick`].
+///
+/// `Ctx` is whatever the states need from the rest of the program while updating, like assets or
+/// input. It's handed to every hook by the methods that call them.
+pub trait State<Ctx = ()> {
+  /// Called on the active state by [`StateMachine::tick`], to update it and work out what happens
+  /// next.
+  ///
+  /// By default, nothing happens.
+  fn update(&mut self, ctx: &mut Ctx) -> Transition<Self>
+  where
+    Self: Sized,
+  {
+    let _ = ctx;
+    Transition::None
+  }
+
+  /// Whether the states under this one should be drawn too, because this one doesn't cover them up.
+  /// See [`StateMachine::iter_visible`].
+  ///
+  /// By default, states are opaque, so this is `false`.
+  fn draw_previous(&self) -> bool {
+    false
+  }
+
+  /// Whether the states under this one should keep updating while it's on top of them.
+  /// See [`StateMachine::tick_all`].
+  ///
+  /// By default, states block everything under them, so this is `false`.
+  fn update_previous(&self) -> bool {
+    false
+  }
+
+  /// Called when this state goes onto the stack.
+  ///
+  /// If this fails, the transition that pushed it is undone, and the reason ends up in
+  /// [`TransitionError::HookFailed`]. See [`StateMachine::apply_with_hooks`].
+  fn on_enter(&mut self, ctx: &mut Ctx) -> Result<(), Cow<'static, str>> {
+    let _ = ctx;
+    Ok(())
+  }
+
+  /// Called when this state comes off the stack.
+  ///
+  /// If this fails, the transition that removed it is undone, and the reason ends up in
+  /// [`TransitionError::HookFailed`]. See [`StateMachine::apply_with_hooks`].
+  fn on_exit(&mut self, ctx: &mut Ctx) -> Result<(), Cow<'static, str>> {
+    let _ = ctx;
+    Ok(())
+  }
+
+  /// Called when this state was the active state, and something was put on top of it.
+  fn on_pause(&mut self, ctx: &mut Ctx) {
+    let _ = ctx;
+  }
+
+  /// Called when this state becomes the active state again because the state on top of it
+  /// was popped with [`Transition::PopWith`], with the value it was given.
+  ///
+  /// This is called just before [`State::on_resume`]. By default, the value is dropped.
+  fn on_result(&mut self, result: Box<dyn Any>, ctx: &mut Ctx) {
+    let _ = (result, ctx);
+  }
+
+  /// Called when this state becomes the active state again, after whatever was on top of it came
+  /// off.
+  fn on_resume(&mut self, ctx: &mut Ctx) {
+    let _ = ctx;
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Call [`State::update`] on the active state, and apply the transition it returns with
+  /// [`StateMachine::apply_with_hooks`].
+  ///
+  /// With the `tracing` feature, this happens inside the
+  /// [active state's span](StateMachine::active_span).
+  pub fn tick<Ctx>(
+    &mut self,
+    ctx: &mut Ctx,
+  ) -> Result<TransitionOutcome<T>, TransitionError>
+  where
+    T: State<Ctx>,
+  {
+    #[cfg(feature = "tracing")]
+    let span = self.active_span();
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    let transition = self.active_mut().update(ctx);
+    self.apply_with_hooks(transition, ctx)
+  }
+
+  /// Iterate over the states that can be seen, from bottommost to topmost (active),
+  /// so they can be drawn in order.
+  ///
+  /// This starts at the topmost state that doesn't [draw the previous state](State::draw_previous),
+  /// or the bottom of the stack if they all do.
+  pub fn iter_visible<Ctx>(&self) -> std::slice::Iter<'_, T>
+  where
+    T: State<Ctx>,
+  {
+    let start = self
+      .stack
+      .iter()
+      .rposition(|state| !state.draw_previous())
+      .unwrap_or(0);
+    self.stack[start..].iter()
+  }
+
+  /// Mutably iterate over the states that should be updated, from topmost (active) to bottommost.
+  ///
+  /// This goes down from the active state until it reaches one that doesn't
+  /// [update the previous state](State::update_previous), and stops after that one.
+  pub fn iter_updating_mut<Ctx>(
+    &mut self,
+  ) -> std::iter::Rev<std::slice::IterMut<'_, T>>
+  where
+    T: State<Ctx>,
+  {
+    let start = self
+      .stack
+      .iter()
+      .rposition(|state| !state.update_previous())
+      .unwrap_or(0);
+    self.stack[start..].iter_mut().rev()
+  }
+
+  /// Call [`State::update`] on every state from [`StateMachine::iter_updating_mut`], topmost first,
+  /// and then apply the transitions they return in that order with
+  /// [`StateMachine::apply_with_hooks`].
+  ///
+  /// Each transition is applied separately, so one failing doesn't stop the rest. This returns
+  /// the result of each one.
+  ///
+  /// Every transition is applied to the stack as it is by then, so a transition from a state
+  /// under the active one still works from the top of the stack. If an earlier transition
+  /// removed the state a transition came from, it's dropped, and its result is
+  /// [`TransitionError::NoSuchState`].
+  pub fn tick_all<Ctx>(
+    &mut self,
+    ctx: &mut Ctx,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>>
+  where
+    T: State<Ctx>,
+  {
+    self.ids.sync(self.stack.len());
+    let count = self.iter_updating_mut::<Ctx>().len();
+    let ids: Vec<_> = self.ids.ids.iter().rev().take(count).copied().collect();
+    let transitions: Vec<_> = self
+      .iter_updating_mut::<Ctx>()
+      .map(|state| state.update(ctx))
+      .collect();
+    transitions
+      .into_iter()
+      .zip(ids)
+      .map(|(transition, id)| match self.position_of(id) {
+        Some(_) => self.apply_with_hooks(transition, ctx),
+        None => Err(TransitionError::NoSuchState(id)),
+      })
+      .collect()
+  }
+
+  /// Apply the given transition, just like [`StateMachine::apply`], and then call the hooks on
+  /// every [`State`] it affected.
+  ///
+  /// The hooks are called in this order:
+  ///
+  /// 1. [`State::on_pause`] on the old active state, if it's still on the stack but isn't active
+  ///    anymore.
+  /// 2. [`State::on_exit`] on every state that was removed, topmost first.
+  /// 3. [`State::on_enter`] on every state that was added, bottommost first.
+  /// 4. [`State::on_result`] on the new active state, if the transition is a
+  ///    [`Transition::PopWith`] and no [`ModalHandle`](crate::ModalHandle) took the value.
+  /// 5. [`State::on_resume`] on the new active state, if it was already on the stack.
+  ///
+  /// Hooks are only called for what changed overall, so if a [`Transition::Sequence`] pushes a
+  /// state and then removes it again, it won't enter, but it will exit. If the transition fails, no
+  /// hooks are called.
+  ///
+  /// If an [`on_exit`](State::on_exit) or [`on_enter`](State::on_enter) hook fails, the transition
+  /// is undone, and this returns [`TransitionError::HookFailed`] with the stack just like it was.
+  /// To keep things balanced, the states that had already entered exit again, the states that had
+  /// already exited enter again, and the old active state resumes; whatever those hooks return
+  /// is ignored.
+  ///
+  /// Observers, the history and everything else watching the machine only hear about the
+  /// transition once the hooks have run, so if one fails, they're told it failed.
+  ///
+  /// The hooks only get `ctx`, not the machine, so the borrow checker stops them from changing the
+  /// machine while they run. To follow up with another transition, have the hook leave it in `ctx`
+  /// (a [`TransitionRequests`](crate::TransitionRequests) works well) and apply it afterwards.
+  pub fn apply_with_hooks<Ctx>(
+    &mut self,
+    transition: Transition<T>,
+    ctx: &mut Ctx,
+  ) -> Result<TransitionOutcome<T>, TransitionError>
+  where
+    T: State<Ctx>,
+  {
+    let Some(transition) = self.intercept(transition, true) else {
+      return Ok(TransitionOutcome::Deferred);
+    };
+    let kind = transition.kind();
+    let depth_before = self.stack.len();
+    self.ids.sync(self.stack.len());
+    let old_active = self.active_id();
+    let first_new = StateId(self.ids.next);
+    let old_ids = self.ids.ids.clone();
+    let old_deferred = self.deferred.transitions.len();
+
+    // nobody hears about it until the hooks have had their say
+    let (mut outcome, popped) =
+      match self.apply_popping_with_unobserved(transition) {
+        Ok(applied) => applied,
+        Err(err) => {
+          self.report(kind, depth_before, Err(err.clone()));
+          return Err(err);
+        }
+      };
+
+    let new_active = self.active_id();
+    let mut paused = false;
+    if new_active != old_active {
+      if let Some(old) = self.get_by_id_mut(old_active) {
+        old.on_pause(ctx);
+        paused = true;
+      }
+    }
+
+    let mut failed = None;
+    let mut exited = 0;
+    let mut removed = Vec::new();
+    outcome.removed_mut(&mut removed);
+    for state in removed.iter_mut().rev() {
+      match state.on_exit(ctx) {
+        Ok(()) => exited += 1,
+        Err(reason) => {
+          failed = Some(reason);
+          break;
+        }
+      }
+    }
+
+    let mut entered = 0;
+    if failed.is_none() {
+      for (state, id) in self.stack.iter_mut().zip(&self.ids.ids) {
+        if *id >= first_new {
+          match state.on_enter(ctx) {
+            Ok(()) => entered += 1,
+            Err(reason) => {
+              failed = Some(reason);
+              break;
+            }
+          }
+        }
+      }
+    }
+
+    if let Some(reason) = failed {
+      let new_states = self
+        .stack
+        .iter_mut()
+        .zip(&self.ids.ids)
+        .filter(|(_, id)| **id >= first_new)
+        .map(|(state, _)| state);
+      let entered: Vec<_> = new_states.take(entered).collect();
+      for state in entered.into_iter().rev() {
+        let _ = state.on_exit(ctx);
+      }
+      let skipped = removed.len() - exited;
+      for state in removed.into_iter().skip(skipped) {
+        let _ = state.on_enter(ctx);
+      }
+
+      outcome.undo(&mut self.stack);
+      self.ids.ids = old_ids;
+      self.deferred.transitions.truncate(old_deferred);
+      if paused {
+        self.active_mut().on_resume(ctx);
+      }
+      let err = TransitionError::HookFailed(reason);
+      self.report(kind, depth_before, Err(err.clone()));
+      return Err(err);
+    }
+
+    if let Some((id, answer)) = popped {
+      if let Some(result) = self.answer_modal(id, answer) {
+        self.active_mut().on_result(result, ctx);
+      }
+    }
+    if new_active != old_active && new_active < first_new {
+      self.active_mut().on_resume(ctx);
+    }
+    self.report(kind, depth_before, Ok(&outcome));
+
+    Ok(outcome)
+  }
+}
+
+impl<T> TransitionOutcome<T> {
+  /// Collect every state this outcome says was removed, in the order they were on the stack.
+  fn removed_mut<'a>(&'a mut self, out: &mut Vec<&'a mut T>) {
+    match self {
+      TransitionOutcome::Revealed(removed)
+      | TransitionOutcome::SwappedIn(removed, _)
+      | TransitionOutcome::Replaced(removed)
+      | TransitionOutcome::RemovedBelow(removed) => out.extend(removed),
+      TransitionOutcome::Removed(_, state)
+      | TransitionOutcome::SwappedAt(_, state) => out.push(state),
+      TransitionOutcome::Sequence(outcomes) => {
+        for outcome in outcomes {
+          outcome.removed_mut(out);
+        }
+      }
+      TransitionOutcome::Conditional(_, outcome) => outcome.removed_mut(out),
+      TransitionOutcome::None
+      | TransitionOutcome::Pushed(_)
+      | TransitionOutcome::Inserted(_)
+      | TransitionOutcome::Deferred => {}
+    }
+  }
+}
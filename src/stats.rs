@@ -0,0 +1,145 @@
+use std::{
+  cell::RefCell,
+  collections::{HashMap, HashSet},
+  hash::Hash,
+  rc::Rc,
+  time::{Duration, Instant},
+};
+
+use crate::{Observer, StateId, StateMachine, TransitionEvent, TransitionKind};
+
+/// How states of one kind have been coming and going. See [`StateStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct KindStats {
+  /// How many states of this kind went onto the stack.
+  pub pushes: usize,
+  /// How many states of this kind came off the stack, including by being swapped out.
+  pub pops: usize,
+  /// How many states of this kind were swapped out by [`Transition::Swap`](crate::Transition::Swap)
+  /// or [`Transition::SwapAt`](crate::Transition::SwapAt).
+  pub swaps: usize,
+  /// How long all the states of this kind that came off the stack were on it, added up.
+  pub total_lifetime: Duration,
+}
+
+impl KindStats {
+  /// Get how long states of this kind stay on the stack, on average, or `None` if none
+  /// have come off yet.
+  pub fn average_lifetime(&self) -> Option<Duration> {
+    let pops = u32::try_from(self.pops).ok().filter(|pops| *pops != 0)?;
+    Some(self.total_lifetime / pops)
+  }
+}
+
+/// An [`Observer`] that counts how states of each kind come and go, and how long they last,
+/// so you can see which screens people bounce off of.
+///
+/// States are grouped by a key function, like "which screen is this". Keep a clone to look at
+/// the counts; see [`StateMachine::add_observer`].
+pub struct StateStats<T, K> {
+  key: Rc<dyn Fn(&T) -> K>,
+  seen: Rc<RefCell<Seen<K>>>,
+}
+
+#[derive(Debug)]
+struct Seen<K> {
+  live: HashMap<StateId, (K, Instant)>,
+  kinds: HashMap<K, KindStats>,
+}
+
+impl<T, K: Eq + Hash + Clone> StateStats<T, K> {
+  /// Make empty stats, which group states with the given key function.
+  pub fn new(key: impl Fn(&T) -> K + 'static) -> Self {
+    Self {
+      key: Rc::new(key),
+      seen: Rc::new(RefCell::new(Seen {
+        live: HashMap::new(),
+        kinds: HashMap::new(),
+      })),
+    }
+  }
+
+  /// Count the states already on the machine as pushed, and start timing them now.
+  ///
+  /// Without this, the states that were there first are only counted once a transition happens,
+  /// and their lifetimes start then. The ones that transition removes are never counted at all.
+  pub fn start(&self, machine: &StateMachine<T>) {
+    let ids: Vec<_> = (0..machine.get_stack().len())
+      .filter_map(|index| machine.id_at(index))
+      .collect();
+    self.update(machine.get_stack(), &ids, false);
+  }
+
+  /// Get the stats for one kind of state.
+  pub fn get(&self, key: &K) -> KindStats {
+    let seen = self.seen.borrow();
+    seen.kinds.get(key).copied().unwrap_or_default()
+  }
+
+  /// Get the stats for every kind of state that's been seen, in no particular order.
+  pub fn all(&self) -> Vec<(K, KindStats)> {
+    let seen = self.seen.borrow();
+    seen
+      .kinds
+      .iter()
+      .map(|(key, stats)| (key.clone(), *stats))
+      .collect()
+  }
+
+  /// Forget every count, but keep track of the states on the stack.
+  pub fn clear(&self) {
+    self.seen.borrow_mut().kinds.clear();
+  }
+
+  fn update(&self, stack: &[T], ids: &[StateId], swapped: bool) {
+    let now = Instant::now();
+    let mut seen = self.seen.borrow_mut();
+    let Seen { live, kinds } = &mut *seen;
+
+    let on_stack: HashSet<_> = ids.iter().collect();
+    live.retain(|id, (key, pushed_at)| {
+      if on_stack.contains(id) {
+        return true;
+      }
+      let stats = kinds.entry(key.clone()).or_default();
+      stats.pops += 1;
+      stats.swaps += usize::from(swapped);
+      stats.total_lifetime += now.duration_since(*pushed_at);
+      false
+    });
+    for (state, id) in stack.iter().zip(ids) {
+      if !live.contains_key(id) {
+        let key = (self.key)(state);
+        kinds.entry(key.clone()).or_default().pushes += 1;
+        live.insert(*id, (key, now));
+      }
+    }
+  }
+}
+
+impl<T, K: Eq + Hash + Clone> Observer<T> for StateStats<T, K> {
+  fn observe(&self, event: &TransitionEvent<'_, T>) {
+    if event.result.is_ok() {
+      let swapped =
+        matches!(event.kind, TransitionKind::Swap | TransitionKind::SwapAt);
+      self.update(event.stack, event.ids, swapped);
+    }
+  }
+}
+
+impl<T, K> Clone for StateStats<T, K> {
+  fn clone(&self) -> Self {
+    Self {
+      key: self.key.clone(),
+      seen: self.seen.clone(),
+    }
+  }
+}
+
+impl<T, K: std::fmt::Debug> std::fmt::Debug for StateStats<T, K> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("StateStats")
+      .field("kinds", &self.seen.borrow().kinds)
+      .finish()
+  }
+}
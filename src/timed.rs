@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use crate::{
+  StateId, StateMachine, Transition, TransitionError, TransitionOutcome,
+};
+
+impl<T> StateMachine<T> {
+  /// Push a new active state that only stays on the stack for the given amount of time, and get its
+  /// id.
+  ///
+  /// The time only passes when you call [`StateMachine::tick_time`]. This is handy for splash
+  /// screens, toasts, and banners.
+  pub fn push_timed(
+    &mut self,
+    state: T,
+    lifetime: Duration,
+  ) -> Result<StateId, TransitionError> {
+    self.push(state)?;
+    let id = self.active_id();
+    self.expiries.push((id, lifetime));
+    Ok(id)
+  }
+
+  /// Make the state with the given id come off the stack after the given amount of time,
+  /// replacing whatever time it had left before. Nothing happens if it's not on the stack.
+  pub fn set_expiry(&mut self, id: StateId, lifetime: Duration) {
+    if self.position_of(id).is_none() {
+      return;
+    }
+    match self.expiries.iter_mut().find(|(timed, _)| *timed == id) {
+      Some((_, left)) => *left = lifetime,
+      None => self.expiries.push((id, lifetime)),
+    }
+  }
+
+  /// Make the state with the given id stay on the stack until it's removed some other way.
+  pub fn clear_expiry(&mut self, id: StateId) {
+    self.expiries.retain(|(timed, _)| *timed != id);
+  }
+
+  /// Get how much time the state with the given id has left, if it's on a timer.
+  pub fn time_left(&self, id: StateId) -> Option<Duration> {
+    self
+      .expiries
+      .iter()
+      .find(|(timed, _)| *timed == id && self.position_of(id).is_some())
+      .map(|(_, left)| *left)
+  }
+
+  /// Let the given amount of time pass for every timed state, and remove the ones whose time is up
+  /// with [`Transition::RemoveId`], topmost first.
+  ///
+  /// This returns the result of each removal. If one fails, like because it's the only state left,
+  /// the state stays on the stack and gets tried again on the next tick.
+  pub fn tick_time(
+    &mut self,
+    dt: Duration,
+  ) -> Vec<Result<TransitionOutcome<T>, TransitionError>> {
+    self.ids.sync(self.stack.len());
+    let ids = &self.ids;
+    self
+      .expiries
+      .retain(|(id, _)| ids.position_of(*id).is_some());
+    for (_, left) in &mut self.expiries {
+      *left = left.saturating_sub(dt);
+    }
+
+    let mut expired: Vec<_> = self
+      .expiries
+      .iter()
+      .filter(|(_, left)| left.is_zero())
+      .map(|(id, _)| *id)
+      .collect();
+    expired.sort_by_key(|id| std::cmp::Reverse(self.position_of(*id)));
+    expired
+      .into_iter()
+      .map(|id| {
+        let res = self.apply(Transition::RemoveId(id));
+        if res.is_ok() {
+          self.clear_expiry(id);
+        }
+        res
+      })
+      .collect()
+  }
+}
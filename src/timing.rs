@@ -0,0 +1,69 @@
+use std::{
+  collections::HashSet,
+  time::{Duration, Instant},
+};
+
+use crate::{Ids, StateId, StateMachine};
+
+/// How long a state has been on a [`StateMachine`], from [`StateMachine::times_at`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateTimes {
+  /// When the state went onto the stack.
+  pub pushed_at: Instant,
+  /// How much time has been [tracked](StateMachine::track_time) while it was the active state.
+  pub active: Duration,
+  /// How much time has been [tracked](StateMachine::track_time) while it was under other states.
+  pub paused: Duration,
+}
+
+impl StateTimes {
+  pub(crate) fn new() -> Self {
+    Self {
+      pushed_at: Instant::now(),
+      active: Duration::ZERO,
+      paused: Duration::ZERO,
+    }
+  }
+}
+
+impl Ids {
+  /// Forget the times of states that aren't on the stack anymore.
+  ///
+  /// This only looks if there are more times than states, so it's cheap to call often.
+  pub(crate) fn prune_times(&mut self) {
+    if self.times.len() > self.ids.len() {
+      let on_stack: HashSet<_> = self.ids.iter().collect();
+      self.times.retain(|id, _| on_stack.contains(id));
+    }
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Count a frame's worth of time. It's added to the active time of the active state,
+  /// and the paused time of every state under it.
+  ///
+  /// Call this once per frame with how long the frame took.
+  pub fn track_time(&mut self, dt: Duration) {
+    self.ids.sync(self.stack.len());
+    self.ids.prune_times();
+    let active = self.active_id();
+    for (id, times) in &mut self.ids.times {
+      if *id == active {
+        times.active += dt;
+      } else {
+        times.paused += dt;
+      }
+    }
+  }
+
+  /// Get how long the state at the given index, counting up from the bottom, has been on the stack.
+  pub fn times_at(&self, index: usize) -> Option<StateTimes> {
+    self.id_at(index).and_then(|id| self.times_of(id))
+  }
+
+  /// Get how long the state with the given id has been on the stack, if it still is.
+  pub fn times_of(&self, id: StateId) -> Option<StateTimes> {
+    self.position_of(id)?;
+    self.ids.times.get(&id).copied()
+  }
+}
@@ -0,0 +1,59 @@
+use crate::{StateMachine, TransitionError, TransitionKind, TransitionOutcome};
+
+impl<T> StateMachine<T> {
+  /// Get the span for the active state, which lasts for as long as it stays active.
+  ///
+  /// The span is called `state`, and has the state's id, and its name if the machine can
+  /// [name states](StateMachine::name_states). [`StateMachine::tick`] enters it while the active
+  /// state updates, and every transition's event is emitted inside it.
+  pub fn active_span(&mut self) -> tracing::Span {
+    let id = self.active_id();
+    if let Some((span_id, span)) = &self.span {
+      if *span_id == id {
+        return span.clone();
+      }
+    }
+    let name = self.active_name();
+    let span = tracing::debug_span!("state", id = id.0, name = name.as_deref());
+    self.span = Some((id, span.clone()));
+    span
+  }
+
+  /// Whether a subscriber wants any of the events from [`StateMachine::trace`]. Failures are the
+  /// loudest, at warn level, so if those aren't wanted, nothing is.
+  pub(crate) fn is_traced() -> bool {
+    tracing::enabled!(tracing::Level::WARN)
+  }
+
+  /// Emit an event for a transition that was just applied, and move the span along if the
+  /// active state changed.
+  pub(crate) fn trace(
+    &mut self,
+    kind: TransitionKind,
+    depth_before: usize,
+    result: Result<&TransitionOutcome<T>, TransitionError>,
+  ) {
+    let depth = self.stack.len();
+    let active = self.active_name();
+    let span = match &self.span {
+      Some((_, span)) => span.clone(),
+      None => tracing::Span::none(),
+    };
+    span.in_scope(|| match result {
+      Ok(outcome) => {
+        tracing::debug!(
+          ?kind,
+          popped = outcome.removed_count(),
+          depth_before,
+          depth,
+          active = active.as_deref(),
+          "applied transition"
+        );
+      }
+      Err(err) => {
+        tracing::warn!(?kind, %err, depth, "transition failed");
+      }
+    });
+    self.active_span();
+  }
+}
@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, num::NonZeroUsize};
+
+use crate::{StateMachine, Transition, TransitionError, TransitionOutcome};
+
+/// A [`StateMachine`] that remembers what each transition did, so it can be undone and redone.
+///
+/// Every outcome is kept, along with the states it removed, so undoing puts back exactly the states
+/// that were there before. The states come back with new [`StateId`](crate::StateId)s, though.
+///
+/// The machine can only be changed through [`UndoableStateMachine::apply`], so the history
+/// always matches the stack.
+#[derive(Debug)]
+pub struct UndoableStateMachine<T> {
+  machine: StateMachine<T>,
+  done: VecDeque<TransitionOutcome<T>>,
+  undone: Vec<TransitionOutcome<T>>,
+  capacity: NonZeroUsize,
+}
+
+impl<T> UndoableStateMachine<T> {
+  /// Wrap a state machine, remembering up to `capacity` transitions to undo.
+  /// Once there are more than that, the oldest are forgotten.
+  pub fn new(machine: StateMachine<T>, capacity: NonZeroUsize) -> Self {
+    Self {
+      machine,
+      done: VecDeque::new(),
+      undone: Vec::new(),
+      capacity,
+    }
+  }
+
+  /// Borrow the state machine.
+  pub fn machine(&self) -> &StateMachine<T> {
+    &self.machine
+  }
+
+  /// Take the state machine back out, forgetting the history.
+  pub fn into_machine(self) -> StateMachine<T> {
+    self.machine
+  }
+
+  /// Mutably borrow the active state. The states can be changed freely; it's only which states
+  /// are on the stack that the history keeps track of.
+  pub fn active_mut(&mut self) -> &mut T {
+    self.machine.active_mut()
+  }
+
+  /// Apply the given transition, like [`StateMachine::apply`], and remember it so it can be undone.
+  ///
+  /// This forgets everything that was undone, so it can't be redone anymore.
+  ///
+  /// There's no way to flush deferred transitions through this, so a [`Transition::Defer`]
+  /// anywhere in the transition fails with [`TransitionError::NowhereToDefer`]. If an
+  /// [interceptor](crate::Interceptor) holds the transition, this fails with
+  /// [`TransitionError::Held`]; nothing happened yet, so there's nothing to undo.
+  pub fn apply(
+    &mut self,
+    transition: Transition<T>,
+  ) -> Result<&TransitionOutcome<T>, TransitionError> {
+    if defers(&transition) {
+      return Err(TransitionError::NowhereToDefer);
+    }
+    let outcome = self.machine.apply(transition)?;
+    if let TransitionOutcome::Deferred = outcome {
+      return Err(TransitionError::Held);
+    }
+    self.undone.clear();
+    if self.done.len() == self.capacity.get() {
+      self.done.pop_front();
+    }
+    self.done.push_back(outcome);
+    Ok(self.done.back().unwrap())
+  }
+
+  /// Undo the last transition. This returns `false` if there was nothing to undo.
+  ///
+  /// Undoing doesn't go past the guards or protected states, since it only ever puts back
+  /// what was there before, but it does fail if the machine is [frozen](StateMachine::freeze).
+  /// It isn't a transition, so observers, the history, and invariants don't hear about it.
+  /// Neither does redoing.
+  pub fn undo(&mut self) -> Result<bool, TransitionError> {
+    self.machine.check_writable()?;
+    let Some(outcome) = self.done.pop_back() else {
+      return Ok(false);
+    };
+    let redo = self.machine.revert(outcome);
+    self.undone.push(redo);
+    Ok(true)
+  }
+
+  /// Redo the last transition that was undone. This returns `false` if there was nothing to redo.
+  pub fn redo(&mut self) -> Result<bool, TransitionError> {
+    self.machine.check_writable()?;
+    let Some(outcome) = self.undone.pop() else {
+      return Ok(false);
+    };
+    let undo = self.machine.revert(outcome);
+    if self.done.len() == self.capacity.get() {
+      self.done.pop_front();
+    }
+    self.done.push_back(undo);
+    Ok(true)
+  }
+
+  /// Check whether there's anything to undo.
+  pub fn can_undo(&self) -> bool {
+    !self.done.is_empty()
+  }
+
+  /// Check whether there's anything to redo.
+  pub fn can_redo(&self) -> bool {
+    !self.undone.is_empty()
+  }
+
+  /// Forget everything that could be undone or redone.
+  pub fn clear_history(&mut self) {
+    self.done.clear();
+    self.undone.clear();
+  }
+}
+
+/// Whether there's a [`Transition::Defer`] anywhere in the transition.
+fn defers<T>(transition: &Transition<T>) -> bool {
+  match transition {
+    Transition::Defer(_) => true,
+    Transition::Sequence(transitions) => transitions.iter().any(defers),
+    Transition::If {
+      then, otherwise, ..
+    } => defers(then) || defers(otherwise),
+    _ => false,
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Undo the outcome on the tracked stack, and get the outcome of undoing it.
+  fn revert(&mut self, outcome: TransitionOutcome<T>) -> TransitionOutcome<T> {
+    let Ok(inverse) = self.apply_tracked(|stack| {
+      Ok::<_, std::convert::Infallible>(outcome.revert(stack))
+    });
+    inverse
+  }
+}
@@ -0,0 +1,81 @@
+use std::num::NonZeroUsize;
+
+use crate::StateMachine;
+
+/// A read-only look at the stack of a [`StateMachine`], which you can get with
+/// [`StateMachine::as_view`].
+///
+/// Like the machine it came from, the stack it looks at is never empty.
+#[derive(Debug)]
+pub struct StackView<'a, T> {
+  stack: &'a [T],
+}
+
+impl<'a, T> StackView<'a, T> {
+  /// Get the last element of the stack, aka the active state.
+  pub fn active(&self) -> &'a T {
+    self.stack.last().unwrap()
+  }
+
+  /// Get the last element of the stack and all elements under it.
+  pub fn split_last(&self) -> (&'a [T], &'a T) {
+    let (last, under) = self.stack.split_last().unwrap();
+    (under, last)
+  }
+
+  /// Get the state directly under the active state, if there is one.
+  pub fn parent(&self) -> Option<&'a T> {
+    self.get_from_top(1)
+  }
+
+  /// Get the state `depth` states down from the top, so `get_from_top(0)` is the active state.
+  pub fn get_from_top(&self, depth: usize) -> Option<&'a T> {
+    let index = self.stack.len().checked_sub(depth + 1)?;
+    self.stack.get(index)
+  }
+
+  /// Borrow the stack.
+  pub fn get_stack(&self) -> &'a [T] {
+    self.stack
+  }
+
+  /// Iterate over the states, bottommost first.
+  pub fn iter(&self) -> std::slice::Iter<'a, T> {
+    self.stack.iter()
+  }
+
+  /// Get how many states are in the stack.
+  pub fn len(&self) -> NonZeroUsize {
+    NonZeroUsize::new(self.stack.len()).unwrap()
+  }
+
+  /// To make clippy stop yelling at me.
+  #[doc(hidden)]
+  pub fn is_empty(&self) -> bool {
+    false
+  }
+}
+
+impl<T> Clone for StackView<'_, T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T> Copy for StackView<'_, T> {}
+
+impl<'a, T> IntoIterator for StackView<'a, T> {
+  type Item = &'a T;
+  type IntoIter = std::slice::Iter<'a, T>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.stack.iter()
+  }
+}
+
+impl<T> StateMachine<T> {
+  /// Get a cheap read-only view of the stack, to hand to code that shouldn't be able to change it.
+  pub fn as_view(&self) -> StackView<'_, T> {
+    StackView { stack: &self.stack }
+  }
+}
@@ -0,0 +1,121 @@
+//! Checks for transitions that shouldn't allocate. This has its own allocator,
+//! so it's kept out of the main tests.
+
+// Tracking time keeps a map of every state's times, which does allocate.
+#![cfg(not(feature = "time"))]
+
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  cell::Cell,
+};
+
+use gerrymander::*;
+
+struct Counting;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Count how many times `f` allocates on this thread.
+fn allocations(f: impl FnOnce()) -> usize {
+  let before = ALLOCATIONS.with(Cell::get);
+  f();
+  ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn push_doesnt_allocate() {
+  let mut machine = StateMachine::with_capacity(0, 8);
+  assert_eq!(
+    allocations(|| {
+      machine.push(1).unwrap();
+      machine.apply(Transition::Push(2)).unwrap();
+    }),
+    0
+  );
+  assert_eq!(machine.get_stack(), &[0, 1, 2]);
+}
+
+#[test]
+fn pop_and_swap_only_allocate_their_outcome() {
+  let mut machine = StateMachine::with_capacity(0, 8);
+  machine.push(1).unwrap();
+  assert_eq!(
+    allocations(|| {
+      machine.pop().unwrap();
+    }),
+    0
+  );
+  machine.push(1).unwrap();
+  assert_eq!(
+    allocations(|| drop(machine.apply(Transition::Pop).unwrap())),
+    1
+  );
+  assert_eq!(
+    allocations(|| {
+      machine.swap(2).unwrap();
+    }),
+    1
+  );
+  let mut stack = vec![0, 1];
+  assert_eq!(
+    allocations(|| drop(Transition::Swap(3).apply(&mut stack).unwrap())),
+    1
+  );
+  assert_eq!(stack, [0, 3]);
+}
+
+#[test]
+fn draining_reuses_its_vec() {
+  let mut machine = StateMachine::with_capacity(0, 8);
+  machine.push(1).unwrap();
+  machine.apply_draining(Transition::Pop).unwrap();
+  assert_eq!(
+    allocations(|| {
+      for state in 1..4 {
+        machine.push(state).unwrap();
+        let removed: i32 =
+          machine.apply_draining(Transition::Pop).unwrap().sum();
+        assert_eq!(removed, state);
+        let removed: i32 = machine
+          .apply_draining(Transition::Swap(state))
+          .unwrap()
+          .sum();
+        assert!(removed < state);
+      }
+    }),
+    0
+  );
+}
+
+#[test]
+fn discarding_reuses_its_vec() {
+  let mut machine = StateMachine::with_capacity(0, 8);
+  machine.push(1).unwrap();
+  machine.apply_discard(Transition::PopN(1)).unwrap();
+  assert_eq!(
+    allocations(|| {
+      for state in 1..4 {
+        machine.push(state).unwrap();
+        machine.apply_discard(Transition::Swap(state)).unwrap();
+        machine.apply_discard(Transition::PopAll).unwrap();
+      }
+    }),
+    0
+  );
+}
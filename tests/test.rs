@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use gerrymander::*;
 
 #[test]
@@ -6,10 +8,10 @@ fn testing() {
   assert_eq!(*machine.active(), "bottom");
 
   let res = machine.apply(Transition::Push("1"));
-  assert_eq!(res, Ok(TransitionOutcome::Pushed));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(0)));
 
   let res = machine.apply(Transition::Push("2"));
-  assert_eq!(res, Ok(TransitionOutcome::Pushed));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(0)));
 
   let res = machine.apply(Transition::Swap("3"));
   assert_eq!(res, Ok(TransitionOutcome::SwappedIn(vec!["2"], 0)));
@@ -19,7 +21,7 @@ fn testing() {
 
   let res =
     machine.apply(Transition::PopNAndPush(0, vec!["10", "11", "12", "13"]));
-  assert_eq!(res, Ok(TransitionOutcome::Pushed));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(3)));
   assert_eq!(*machine.active(), "13");
 
   // notably, this transition would have left the machine empty
@@ -66,3 +68,2934 @@ fn serdeez_nuts() {
   let stack2 = unjsonified.consume();
   assert_eq!(stack1, stack2);
 }
+
+#[test]
+fn pop_all() {
+  let mut machine = StateMachine::new_many(vec!["menu", "game", "pause"]);
+  let res = machine.apply(Transition::PopAll);
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec!["game", "pause"])));
+  assert_eq!(machine.get_stack(), &["menu"]);
+
+  // popping everything off a single state is a no-op
+  let res = machine.apply(Transition::PopAll);
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec![])));
+
+  machine.apply(Transition::Push("game")).unwrap();
+  let res = machine.apply(Transition::Reset("title"));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::SwappedIn(vec!["menu", "game"], 0))
+  );
+  assert_eq!(machine.get_stack(), &["title"]);
+}
+
+#[test]
+fn pop_n() {
+  let mut machine = StateMachine::new_many(vec!["a", "b", "c", "d"]);
+  let res = machine.apply(Transition::PopN(2));
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec!["c", "d"])));
+  assert_eq!(machine.get_stack(), &["a", "b"]);
+
+  let res = machine.apply(Transition::PopN(2));
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 2,
+      available: 1
+    })
+  );
+  assert_eq!(machine.get_stack(), &["a", "b"]);
+}
+
+#[test]
+fn pop_until() {
+  let mut machine =
+    StateMachine::new_many(vec!["menu", "game", "inventory", "item"]);
+  let res = machine.apply(Transition::pop_until(|s| *s == "game"));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec!["inventory", "item"]))
+  );
+  assert_eq!(*machine.active(), "game");
+
+  // already there
+  let res = machine.apply(Transition::pop_until(|s| *s == "game"));
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec![])));
+
+  // never matches, so it stops at the bottom
+  let res = machine.apply(Transition::pop_until(|_| false));
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec!["game"])));
+  assert_eq!(machine.get_stack(), &["menu"]);
+}
+
+#[test]
+fn replace_all() {
+  let mut machine = StateMachine::new_many(vec!["menu", "game", "pause"]);
+  let res = machine.apply(Transition::ReplaceAll(vec!["world", "hud"]));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Replaced(vec!["menu", "game", "pause"]))
+  );
+  assert_eq!(machine.get_stack(), &["world", "hud"]);
+
+  let res = machine.apply(Transition::ReplaceAll(vec![]));
+  assert_eq!(res, Err(TransitionError::EmptyReplacement));
+  assert_eq!(machine.get_stack(), &["world", "hud"]);
+}
+
+#[test]
+fn insert_at() {
+  let mut machine = StateMachine::new_many(vec!["world", "dialog"]);
+  let res = machine.apply(Transition::InsertAt(1, "pause_guard"));
+  assert_eq!(res, Ok(TransitionOutcome::Inserted(1)));
+  assert_eq!(machine.get_stack(), &["world", "pause_guard", "dialog"]);
+
+  let res = machine.apply(Transition::InsertAt(3, "toast"));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(0)));
+  assert_eq!(*machine.active(), "toast");
+
+  let res = machine.apply(Transition::InsertAt(10, "nope"));
+  assert_eq!(res, Err(TransitionError::OutOfBounds { index: 10, len: 4 }));
+}
+
+#[test]
+fn remove_at() {
+  let mut machine = StateMachine::new_many(vec!["world", "guard", "dialog"]);
+  let res = machine.apply(Transition::RemoveAt(1));
+  assert_eq!(res, Ok(TransitionOutcome::Removed(1, "guard")));
+  assert_eq!(machine.get_stack(), &["world", "dialog"]);
+
+  let res = machine.apply(Transition::RemoveAt(1));
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec!["dialog"])));
+
+  let res = machine.apply(Transition::RemoveAt(5));
+  assert_eq!(res, Err(TransitionError::OutOfBounds { index: 5, len: 1 }));
+  let res = machine.apply(Transition::RemoveAt(0));
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 1,
+      available: 0
+    })
+  );
+  assert_eq!(machine.get_stack(), &["world"]);
+}
+
+#[test]
+fn push_many() {
+  let mut machine = StateMachine::new("world");
+  let res = machine.apply(Transition::push_many(["menu", "submenu"]));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(1)));
+  assert_eq!(machine.get_stack(), &["world", "menu", "submenu"]);
+
+  let res = machine.apply(Transition::push_many(std::iter::empty()));
+  assert_eq!(res, Ok(TransitionOutcome::None));
+  assert_eq!(machine.len().get(), 3);
+}
+
+#[test]
+fn sequence() {
+  let mut machine = StateMachine::new_many(vec!["world", "hud"]);
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Pop,
+    Transition::Push("inventory"),
+    Transition::Sequence(vec![Transition::InsertAt(1, "guard")]),
+  ]));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Sequence(vec![
+      TransitionOutcome::Revealed(vec!["hud"]),
+      TransitionOutcome::Pushed(0),
+      TransitionOutcome::Inserted(1),
+    ]))
+  );
+  assert_eq!(machine.get_stack(), &["world", "guard", "inventory"]);
+
+  // the last one fails, so everything gets rolled back
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Swap("map"),
+    Transition::RemoveAt(0),
+    Transition::Push("toast"),
+    Transition::PopN(10),
+  ]));
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 10,
+      available: 2
+    })
+  );
+  assert_eq!(machine.get_stack(), &["world", "guard", "inventory"]);
+
+  // and since it was never going to work, the closures in it don't run
+  let built = std::rc::Rc::new(std::cell::Cell::new(0));
+  let counter = built.clone();
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::PushWith(Box::new(move || {
+      counter.set(counter.get() + 1);
+      "toast"
+    })),
+    Transition::RemoveAt(10),
+  ]));
+  assert_eq!(res, Err(TransitionError::OutOfBounds { index: 10, len: 4 }));
+  assert_eq!(built.get(), 0);
+}
+
+#[test]
+fn conditional() {
+  let mut machine = StateMachine::new_many(vec!["world", "cutscene"]);
+  let pause = || {
+    Transition::if_else(
+      |s: &&str| *s == "cutscene",
+      Transition::None,
+      Transition::Push("pause"),
+    )
+  };
+
+  let res = machine.apply(pause());
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Conditional(
+      true,
+      Box::new(TransitionOutcome::None)
+    ))
+  );
+
+  machine.apply(Transition::Pop).unwrap();
+  let res = machine.apply(pause());
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Conditional(
+      false,
+      Box::new(TransitionOutcome::Pushed(0))
+    ))
+  );
+  assert_eq!(machine.get_stack(), &["world", "pause"]);
+
+  // inside of a sequence, the condition sees the stack as it is at that point
+  let res = machine.apply(Transition::Sequence(vec![Transition::Pop, pause()]));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Sequence(vec![
+      TransitionOutcome::Revealed(vec!["pause"]),
+      TransitionOutcome::Pushed(0),
+    ]))
+  );
+}
+
+#[test]
+fn from_fn() {
+  let mut machine = StateMachine::new_many(vec!["world", "menu", "submenu"]);
+  // pop back to the world, however deep we are
+  let to_world = || {
+    Transition::from_fn(|stack: &[&str]| {
+      let depth = stack.iter().rev().position(|s| *s == "world").unwrap();
+      Transition::PopN(depth)
+    })
+  };
+  let res = machine.apply(to_world());
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec!["menu", "submenu"]))
+  );
+
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Push("map"),
+    to_world(),
+  ]));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Sequence(vec![
+      TransitionOutcome::Pushed(0),
+      TransitionOutcome::Revealed(vec!["map"]),
+    ]))
+  );
+  assert_eq!(machine.get_stack(), &["world"]);
+}
+
+#[test]
+fn lazy_push() {
+  use std::{cell::Cell, rc::Rc};
+
+  let mut machine = StateMachine::new("world");
+  let res = machine.apply(Transition::push_with(|| "map"));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(0)));
+
+  let built = Rc::new(Cell::new(false));
+  let built2 = built.clone();
+  let res = machine.apply(Transition::pop_n_and_push_with(5, move || {
+    built2.set(true);
+    vec!["expensive"]
+  }));
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 5,
+      available: 1
+    })
+  );
+  assert!(!built.get());
+  assert_eq!(
+    Transition::pop_n_and_push_with(5, || vec!["expensive"]).dry_run(&machine),
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 5,
+      available: 1
+    })
+  );
+
+  let res =
+    machine.apply(Transition::pop_n_and_push_with(1, || vec!["a", "b"]));
+  assert_eq!(res, Ok(TransitionOutcome::SwappedIn(vec!["map"], 1)));
+  assert_eq!(machine.get_stack(), &["world", "a", "b"]);
+}
+
+#[test]
+fn pop_n_and_push_iter() {
+  let mut machine = StateMachine::new_many(vec!["world", "menu"]);
+  let res = machine.pop_n_and_push(1, ["map", "legend"]);
+  assert_eq!(res, Ok(TransitionOutcome::SwappedIn(vec!["menu"], 1)));
+  let res = machine.pop_n_and_push(0, ["a", "b"].into_iter().rev());
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(1)));
+  assert_eq!(machine.get_stack(), &["world", "map", "legend", "b", "a"]);
+
+  let res = machine.pop_n_and_push(5, []);
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 5,
+      available: 4
+    })
+  );
+}
+
+#[test]
+fn state_ids() {
+  let mut machine = StateMachine::new("world");
+  let world = machine.active_id();
+  machine.apply(Transition::Push("map")).unwrap();
+  let map = machine.active_id();
+  assert_ne!(world, map);
+  machine
+    .apply(Transition::push_many(["menu", "submenu"]))
+    .unwrap();
+  machine.apply(Transition::InsertAt(1, "guard")).unwrap();
+  assert_eq!(machine.position_of(map), Some(2));
+  assert_eq!(machine.get_by_id(map), Some(&"map"));
+
+  let res = machine.apply(Transition::PopTo(map));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec!["menu", "submenu"]))
+  );
+  assert_eq!(machine.active_id(), map);
+
+  let res = machine.apply(Transition::RemoveId(map));
+  assert_eq!(res, Ok(TransitionOutcome::Revealed(vec!["map"])));
+  assert_eq!(
+    machine.apply(Transition::PopTo(map)),
+    Err(TransitionError::NoSuchState(map))
+  );
+
+  // swapping in a state gives it a brand new id, even if it's in the same place
+  machine.apply(Transition::Swap("guard2")).unwrap();
+  assert_eq!(machine.get_ids().len(), 2);
+  assert_eq!(machine.id_at(0), Some(world));
+
+  // a failed sequence leaves the ids alone too
+  let before = machine.get_ids().to_vec();
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Pop,
+    Transition::Push("oops"),
+    Transition::PopTo(map),
+  ]));
+  assert_eq!(res, Err(TransitionError::NoSuchState(map)));
+  assert_eq!(machine.get_ids(), &before[..]);
+}
+
+#[test]
+fn marks() {
+  let mut machine = StateMachine::new_many(vec!["world", "pause"]);
+  machine.mark("pause_root");
+  machine
+    .apply(Transition::push_many(["settings", "audio"]))
+    .unwrap();
+
+  let res = machine.apply(Transition::pop_to_mark("pause_root"));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec!["settings", "audio"]))
+  );
+  assert_eq!(*machine.active(), "pause");
+
+  machine.apply(Transition::Pop).unwrap();
+  assert_eq!(machine.marked("pause_root"), None);
+  assert_eq!(
+    machine.apply(Transition::pop_to_mark("pause_root")),
+    Err(TransitionError::NoSuchMark)
+  );
+}
+
+#[test]
+fn swap_at() {
+  let mut machine = StateMachine::new_many(vec!["menu", "world", "dialog"]);
+  let dialog = machine.active_id();
+  let res = machine.apply(Transition::SwapAt(1, "other_world"));
+  assert_eq!(res, Ok(TransitionOutcome::SwappedAt(1, "world")));
+  assert_eq!(machine.get_stack(), &["menu", "other_world", "dialog"]);
+  assert_eq!(machine.active_id(), dialog);
+
+  let res = machine.apply(Transition::SwapAt(0, "other_dialog"));
+  assert_eq!(res, Ok(TransitionOutcome::SwappedIn(vec!["dialog"], 0)));
+
+  let res = machine.apply(Transition::SwapAt(3, "nope"));
+  assert_eq!(res, Err(TransitionError::OutOfBounds { index: 3, len: 3 }));
+}
+
+#[test]
+fn pop_below() {
+  let mut machine =
+    StateMachine::new_many(vec!["title", "level1", "level2", "shop"]);
+  let res = machine.apply(Transition::PopBelow(2));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::RemovedBelow(vec!["level1", "level2"]))
+  );
+  assert_eq!(machine.get_stack(), &["title", "shop"]);
+
+  let res = machine.apply(Transition::PopBelow(2));
+  assert_eq!(
+    res,
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 2,
+      available: 1
+    })
+  );
+
+  // rolled back in the right spot
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Push("dialog"),
+    Transition::PopBelow(2),
+    Transition::PopN(5),
+  ]));
+  assert!(res.is_err());
+  assert_eq!(machine.get_stack(), &["title", "shop"]);
+}
+
+#[test]
+fn deferred() {
+  let mut machine = StateMachine::new_many(vec![1, 2, 3]);
+  let (states, queue) = machine.iter_mut_deferred();
+  for state in states {
+    *state *= 10;
+    if *state == 20 {
+      queue.push(Transition::Push(40));
+    }
+  }
+  let res = machine.apply(Transition::Defer(Box::new(Transition::PopN(10))));
+  assert_eq!(res, Ok(TransitionOutcome::Deferred));
+  assert_eq!(machine.get_stack(), &[10, 20, 30]);
+  assert_eq!(machine.get_deferred().len(), 2);
+
+  let res = machine.flush_deferred();
+  assert_eq!(
+    res,
+    vec![
+      Ok(TransitionOutcome::Pushed(0)),
+      Err(TransitionError::PoppedTooMany {
+        popcnt: 10,
+        available: 3
+      })
+    ]
+  );
+  assert_eq!(machine.get_stack(), &[10, 20, 30, 40]);
+  assert!(machine.get_deferred().is_empty());
+
+  // rolling back a sequence takes it off the queue again
+  let res = machine.apply(Transition::Sequence(vec![
+    Transition::Defer(Box::new(Transition::Pop)),
+    Transition::PopN(10),
+  ]));
+  assert!(res.is_err());
+  assert!(machine.get_deferred().is_empty());
+
+  let mut raw = vec![1];
+  assert_eq!(
+    Transition::Defer(Box::new(Transition::Pop)).apply(&mut raw),
+    Err(TransitionError::NowhereToDefer)
+  );
+}
+
+#[test]
+fn invert() {
+  let transitions = vec![
+    Transition::Push("a"),
+    Transition::Pop,
+    Transition::PopNAndPush(1, vec!["b", "c"]),
+    Transition::ReplaceAll(vec!["x", "y", "z"]),
+    Transition::InsertAt(1, "i"),
+    Transition::RemoveAt(0),
+    Transition::SwapAt(1, "s"),
+    Transition::PopBelow(1),
+    Transition::Sequence(vec![Transition::Push("1"), Transition::PopN(2)]),
+    Transition::if_else(|_| true, Transition::PopAll, Transition::None),
+  ];
+
+  let mut machine = StateMachine::new_many(vec!["bottom", "top"]);
+  for transition in transitions {
+    let before = machine.get_stack().to_vec();
+    let outcome = machine.apply(transition).unwrap();
+    let after = machine.get_stack().to_vec();
+
+    machine.apply(outcome.invert()).unwrap();
+    assert_eq!(machine.get_stack(), &before[..]);
+    // and do it again so the next transition has something new to work on
+    machine = StateMachine::new_many(after);
+  }
+}
+
+#[test]
+fn map() {
+  #[derive(Debug, PartialEq)]
+  enum Screen {
+    Menu(&'static str),
+    World,
+  }
+  fn lower(screen: &Screen) -> Option<&&'static str> {
+    match screen {
+      Screen::Menu(menu) => Some(menu),
+      _ => None,
+    }
+  }
+
+  let mut machine = StateMachine::new(Screen::World);
+  let menus = Transition::Sequence(vec![
+    Transition::push_many(["main", "options"]),
+    Transition::Push("audio"),
+  ]);
+  let res = machine.apply(menus.map(Screen::Menu, lower).unwrap());
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Sequence(vec![
+      TransitionOutcome::Pushed(1),
+      TransitionOutcome::Pushed(0)
+    ]))
+  );
+
+  let back = Transition::pop_until(|menu: &&str| *menu == "main");
+  let res =
+    machine
+      .apply(back.map(Screen::Menu, lower).unwrap())
+      .map(|outcome| {
+        outcome.map(|screen| match screen {
+          Screen::Menu(menu) => menu,
+          Screen::World => "world",
+        })
+      });
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec!["options", "audio"]))
+  );
+  assert_eq!(*machine.active(), Screen::Menu("main"));
+
+  // the world isn't a menu, so it can't match
+  let res = machine.apply(
+    Transition::pop_until(|menu: &&str| *menu == "world")
+      .map(Screen::Menu, lower)
+      .unwrap(),
+  );
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::Revealed(vec![Screen::Menu("main")]))
+  );
+
+  let dynamic = Transition::from_fn(|_: &[&str]| Transition::None);
+  assert!(dynamic.map(Screen::Menu, lower).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_transitions() {
+  let transition = Transition::Sequence(vec![
+    Transition::PopN(2),
+    Transition::PopNAndPush(1, vec!["a".to_string(), "b".to_string()]),
+    Transition::pop_to_mark("root"),
+    Transition::Defer(Box::new(Transition::Push("c".to_string()))),
+  ]);
+  let json = serde_json::to_string(&transition).unwrap();
+  let transition: Transition<String> = serde_json::from_str(&json).unwrap();
+
+  let mut machine = StateMachine::new("root".to_string());
+  machine.mark("root");
+  machine
+    .apply(Transition::push_many(["x", "y", "z"].map(String::from)))
+    .unwrap();
+  let outcome = machine.apply(transition).unwrap();
+  assert_eq!(machine.get_stack(), &["root"]);
+
+  let json = serde_json::to_string(&outcome).unwrap();
+  let unjsonified: TransitionOutcome<String> =
+    serde_json::from_str(&json).unwrap();
+  assert_eq!(outcome, unjsonified);
+
+  let err = TransitionError::PoppedTooMany {
+    popcnt: 3,
+    available: 1,
+  };
+  let json = serde_json::to_string(&err).unwrap();
+  assert_eq!(serde_json::from_str::<TransitionError>(&json).unwrap(), err);
+
+  assert!(serde_json::to_string(&Transition::push_with(String::new)).is_err());
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_never_empty() {
+  use arbitrary::{Arbitrary, Unstructured};
+
+  // a little home-grown noise so the test is deterministic
+  let mut seed = 0x2545_f491_4f6c_dd1du64;
+  let bytes: Vec<u8> = (0..8192)
+    .map(|_| {
+      seed ^= seed << 13;
+      seed ^= seed >> 7;
+      seed ^= seed << 17;
+      seed as u8
+    })
+    .collect();
+
+  let mut u = Unstructured::new(&bytes);
+  let mut machine = StateMachine::<u8>::arbitrary(&mut u).unwrap();
+  while !u.is_empty() {
+    let transition = Transition::<u8>::arbitrary(&mut u).unwrap();
+    let before = machine.get_stack().to_vec();
+    if machine.apply(transition).is_err() {
+      assert_eq!(machine.get_stack(), &before[..]);
+    }
+    assert!(!machine.get_stack().is_empty());
+    machine.flush_deferred();
+  }
+}
+
+#[test]
+fn builder() {
+  let builder = Transition::build().pop(2).push("a").push("b");
+  assert_eq!(builder.net_change(), 0);
+  assert_eq!(builder.validate(2), Ok(()));
+  assert!(builder.validate(1).is_err());
+
+  let mut machine = StateMachine::new_many(vec!["x", "y", "z"]);
+  let res = machine.apply(builder.finish());
+  assert_eq!(res, Ok(TransitionOutcome::SwappedIn(vec!["y", "z"], 1)));
+  assert_eq!(machine.get_stack(), &["x", "a", "b"]);
+
+  // pushing then popping cancels out
+  let builder = Transition::build().push("c").push("d").pop(3);
+  assert_eq!(builder.pop_count(), 1);
+  assert!(builder.to_push().is_empty());
+  assert_eq!(builder.validate(0), Err(TransitionError::EmptyReplacement));
+  assert!(matches!(builder.finish(), Transition::Pop));
+  assert!(matches!(
+    Transition::<&str>::build().finish(),
+    Transition::None
+  ));
+}
+
+#[test]
+fn coalescing() {
+  let mut queue = TransitionQueue::with_coalescing(Coalesce::ALL);
+  queue.push(Transition::Pop);
+  queue.push(Transition::Push("a"));
+  queue.push(Transition::Push("b"));
+  queue.push(Transition::PopN(3));
+  queue.push(Transition::Pop);
+  assert!(matches!(queue.get_transitions(), [Transition::PopN(3)]));
+
+  queue.clear();
+  queue.set_coalescing(Coalesce {
+    cancel_push_pop: true,
+    merge_pops: false,
+  });
+  queue.push(Transition::Pop);
+  queue.push(Transition::Push("a"));
+  queue.push(Transition::PopN(2));
+  assert!(matches!(
+    queue.get_transitions(),
+    [Transition::Pop, Transition::Pop]
+  ));
+
+  let mut machine = StateMachine::new_many(vec!["world", "menu"]);
+  machine.get_deferred_mut().set_coalescing(Coalesce::ALL);
+  machine.defer(Transition::Push("popup"));
+  let res = machine.apply(Transition::Defer(Box::new(Transition::Pop)));
+  assert_eq!(res, Ok(TransitionOutcome::Deferred));
+  assert!(machine.get_deferred().is_empty());
+  assert_eq!(machine.clone().get_deferred().coalescing(), Coalesce::ALL);
+}
+
+#[test]
+fn pop_all_and_push() {
+  let mut machine = StateMachine::new_many(vec!["menu", "game"]);
+  let res = machine.apply(Transition::PopAllAndPush(vec!["level2", "intro"]));
+  assert_eq!(
+    res,
+    Ok(TransitionOutcome::SwappedIn(vec!["menu", "game"], 1))
+  );
+  assert_eq!(machine.get_stack(), &["level2", "intro"]);
+
+  let res = machine.apply(Transition::PopAllAndPush(vec![]));
+  assert_eq!(res, Err(TransitionError::EmptyReplacement));
+  assert_eq!(machine.get_stack(), &["level2", "intro"]);
+}
+
+#[test]
+fn guards() {
+  let mut machine = StateMachine::new_many(vec!["world", "cutscene"]);
+  machine.add_guard(|stack: &[&str], transition: &Transition<&str>| {
+    match (stack.last(), transition) {
+      (Some(&"cutscene"), Transition::Push("pause")) => {
+        Err("can't pause during a cutscene")
+      }
+      _ => Ok(()),
+    }
+  });
+
+  let res = machine.apply(Transition::Push("pause"));
+  assert_eq!(
+    res,
+    Err(TransitionError::Rejected(
+      "can't pause during a cutscene".into()
+    ))
+  );
+  assert_eq!(machine.get_stack(), &["world", "cutscene"]);
+
+  machine.apply(Transition::Pop).unwrap();
+  let res = machine.apply(Transition::Push("pause"));
+  assert_eq!(res, Ok(TransitionOutcome::Pushed(0)));
+
+  machine.clear_guards();
+  machine.apply(Transition::Swap("cutscene")).unwrap();
+  assert!(machine.apply(Transition::Push("pause")).is_ok());
+}
+
+#[test]
+fn dry_run() {
+  let mut machine = StateMachine::new_many(vec!["world", "inventory"]);
+  let world = machine.id_at(0).unwrap();
+
+  let checks = vec![
+    Transition::Pop,
+    Transition::PopN(2),
+    Transition::Swap("map"),
+    Transition::PopTo(world),
+    Transition::Sequence(vec![
+      Transition::Push("pause"),
+      Transition::PopTo(world),
+      Transition::if_else(
+        |s| *s == "world",
+        Transition::InsertAt(0, "title"),
+        Transition::None,
+      ),
+    ]),
+  ];
+  for transition in checks {
+    let shape = transition.dry_run(&machine);
+    let mut clone = machine.clone();
+    assert_eq!(shape, clone.apply(transition).map(|o| o.shape()));
+    assert_eq!(machine.get_stack(), &["world", "inventory"]);
+  }
+
+  assert_eq!(
+    Transition::Sequence(vec![Transition::Push("pause"), Transition::PopN(4)])
+      .dry_run(&machine),
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 4,
+      available: 2
+    })
+  );
+  assert_eq!(
+    Transition::from_fn(|_| Transition::Pop).dry_run(&machine),
+    Ok(TransitionShape::Unknown)
+  );
+
+  machine.add_guard(|_: &[&str], _: &Transition<&str>| Err("no"));
+  assert_eq!(
+    Transition::Pop.dry_run(&machine),
+    Err(TransitionError::Rejected("no".into()))
+  );
+}
+
+#[test]
+fn diff() {
+  let mut view = StateMachine::new_many(vec!["world", "inventory"]);
+  let authority = StateMachine::new_many(vec!["world", "map", "legend"]);
+
+  let transition = view.diff(authority.get_stack());
+  assert!(matches!(
+    &transition,
+    Transition::PopNAndPush(1, states) if states == &["map", "legend"]
+  ));
+  view.apply(transition).unwrap();
+  assert_eq!(view.get_stack(), authority.get_stack());
+
+  assert!(matches!(view.diff(authority.get_stack()), Transition::None));
+  assert!(matches!(
+    view.diff(&["world"]),
+    Transition::PopNAndPush(2, states) if states.is_empty()
+  ));
+}
+
+#[test]
+fn apply_recoverable() {
+  let mut machine = StateMachine::new("world");
+  let res = machine.apply_recoverable(Transition::PopNAndPush(
+    3,
+    vec![String::from("map")]
+      .into_iter()
+      .map(|_| "map")
+      .collect(),
+  ));
+  let Err((err, Some(Transition::PopNAndPush(3, states)))) = res else {
+    panic!("should have gotten the transition back");
+  };
+  assert_eq!(
+    err,
+    TransitionError::PoppedTooMany {
+      popcnt: 3,
+      available: 1
+    }
+  );
+  assert_eq!(states, vec!["map"]);
+  assert_eq!(machine.get_stack(), &["world"]);
+
+  let res = machine.apply_recoverable(Transition::from_fn(|_| Transition::Pop));
+  assert!(matches!(
+    res,
+    Err((TransitionError::PoppedTooMany { .. }, None))
+  ));
+
+  let mut stack = vec![1];
+  let res = Transition::Defer(Box::new(Transition::Push(2)))
+    .apply_recoverable(&mut stack);
+  assert!(matches!(
+    res,
+    Err((TransitionError::NowhereToDefer, Some(Transition::Defer(_))))
+  ));
+  let res = Transition::Swap(3).apply_recoverable(&mut stack);
+  assert_eq!(res.ok(), Some(TransitionOutcome::SwappedIn(vec![1], 0)));
+}
+
+#[test]
+fn apply_all() {
+  let mut machine = StateMachine::new_many(vec!["world", "inventory"]);
+  machine.mark("inventory");
+  machine.defer(Transition::Push("later"));
+
+  let res = machine.apply_all(vec![
+    Transition::Swap("map"),
+    Transition::Sequence(vec![Transition::Push("pause"), Transition::PopAll]),
+    Transition::InsertAt(0, "title"),
+    Transition::Defer(Box::new(Transition::Pop)),
+    Transition::PopN(5),
+  ]);
+  assert_eq!(
+    res,
+    Err((
+      4,
+      TransitionError::PoppedTooMany {
+        popcnt: 5,
+        available: 1
+      }
+    ))
+  );
+  assert_eq!(machine.get_stack(), &["world", "inventory"]);
+  assert_eq!(machine.marked("inventory"), machine.id_at(1));
+  assert_eq!(machine.get_deferred().len(), 1);
+
+  let res = machine.apply_all(vec![
+    Transition::Push("map"),
+    Transition::if_else(
+      |s| *s == "map",
+      Transition::Sequence(vec![Transition::Pop, Transition::Pop]),
+      Transition::None,
+    ),
+  ]);
+  assert_eq!(
+    res,
+    Ok(vec![
+      TransitionOutcome::Pushed(0),
+      TransitionOutcome::Conditional(
+        true,
+        Box::new(TransitionOutcome::Sequence(vec![
+          TransitionOutcome::Revealed(vec!["map"]),
+          TransitionOutcome::Revealed(vec!["inventory"]),
+        ]))
+      ),
+    ])
+  );
+  assert_eq!(machine.get_stack(), &["world"]);
+
+  // closures don't run if the batch is bound to fail
+  let built = std::rc::Rc::new(std::cell::Cell::new(0));
+  let counter = built.clone();
+  let res = machine.apply_all(vec![
+    Transition::PushWith(Box::new(move || {
+      counter.set(counter.get() + 1);
+      "shop"
+    })),
+    Transition::RemoveAt(3),
+  ]);
+  assert_eq!(
+    res,
+    Err((1, TransitionError::OutOfBounds { index: 3, len: 2 }))
+  );
+  assert_eq!(built.get(), 0);
+}
+
+#[test]
+fn apply_with() {
+  let mut machine = StateMachine::new(1);
+  for _ in 0..3 {
+    machine.apply_with(|n| Transition::Push(n * 2)).unwrap();
+  }
+  assert_eq!(machine.get_stack(), &[1, 2, 4, 8]);
+
+  let res = machine.apply_with(|n| Transition::PopN(*n));
+  assert!(res.is_err());
+  assert_eq!(machine.len().get(), 4);
+}
+
+#[test]
+fn push_pop_swap() {
+  let mut machine = StateMachine::new("world");
+  machine.push("inventory").unwrap();
+  assert_eq!(machine.swap("map"), Ok("inventory"));
+  assert_eq!(machine.pop(), Ok("map"));
+  assert_eq!(
+    machine.pop(),
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 1,
+      available: 0
+    })
+  );
+  assert_eq!(machine.get_stack(), &["world"]);
+}
+
+#[test]
+fn pop_by_value() {
+  let mut machine = StateMachine::new_many(vec!["world", "pause"]);
+  let pause = machine.active_id();
+  machine.add_guard(|stack: &[&str], transition: &Transition<&str>| {
+    match transition {
+      Transition::Pop if stack.len() == 1 => Err("nothing to pop"),
+      _ => Ok(()),
+    }
+  });
+
+  assert_eq!(machine.pop(), Ok("pause"));
+  assert_eq!(machine.position_of(pause), None);
+  assert_eq!(
+    machine.pop(),
+    Err(TransitionError::Rejected("nothing to pop".into()))
+  );
+}
+
+#[test]
+fn try_new_many() {
+  let machine = StateMachine::try_new_many(vec!["world"]).unwrap();
+  assert_eq!(machine.get_stack(), &["world"]);
+  assert_eq!(
+    StateMachine::<&str>::try_new_many(vec![]).unwrap_err(),
+    EmptyStackError
+  );
+
+  #[cfg(feature = "serde")]
+  assert!(
+    serde_json::from_str::<StateMachine<String>>(r#"{"stack":[]}"#).is_err()
+  );
+}
+
+#[test]
+#[should_panic]
+fn new_many_empty() {
+  StateMachine::<&str>::new_many(vec![]);
+}
+
+#[test]
+fn try_from() {
+  let machine = StateMachine::try_from(vec!["world", "map"]).unwrap();
+  assert_eq!(*machine.active(), "map");
+  assert!(StateMachine::<&str>::try_from(vec![]).is_err());
+
+  let machine = StateMachine::try_from_iter(1..=3).unwrap();
+  assert_eq!(machine.get_stack(), &[1, 2, 3]);
+  assert_eq!(
+    StateMachine::try_from_iter(std::iter::empty::<i32>()).unwrap_err(),
+    EmptyStackError
+  );
+}
+
+#[test]
+fn extend() {
+  let mut machine = StateMachine::new("world");
+  machine.extend(["inventory", "map"]);
+  assert_eq!(*machine.active(), "map");
+  assert_eq!(machine.get_ids().len(), 3);
+
+  machine.push_many(["quests"]).unwrap();
+  machine.push_many([]).unwrap();
+  assert_eq!(
+    machine.get_stack(),
+    &["world", "inventory", "map", "quests"]
+  );
+}
+
+#[test]
+fn indexing() {
+  let mut machine = StateMachine::new_many(vec![1, 2, 3]);
+  assert_eq!(machine[0], 1);
+  machine[1] = 20;
+  assert_eq!(machine.get_from_top(0), Some(&3));
+  assert_eq!(machine.get_from_top(1), Some(&20));
+  assert_eq!(machine.get_from_top(3), None);
+  *machine.get_from_top_mut(2).unwrap() = 10;
+  assert_eq!(machine.get_stack(), &[10, 20, 3]);
+}
+
+#[test]
+fn parent() {
+  let mut machine = StateMachine::new("world");
+  assert_eq!(machine.parent(), None);
+  machine.push("pause").unwrap();
+  assert_eq!(machine.parent(), Some(&"world"));
+  *machine.parent_mut().unwrap() = "map";
+  assert_eq!(machine.get_stack(), &["map", "pause"]);
+}
+
+#[test]
+fn active_and_parent_mut() {
+  let mut machine = StateMachine::new(0);
+  let (active, parent) = machine.active_and_parent_mut();
+  *active += 1;
+  assert!(parent.is_none());
+
+  machine.push(10).unwrap();
+  let (active, parent) = machine.active_and_parent_mut();
+  *parent.unwrap() += *active;
+  assert_eq!(machine.get_stack(), &[11, 10]);
+}
+
+#[test]
+fn search() {
+  let machine = StateMachine::new_many(vec!["world", "map", "world", "pause"]);
+  assert!(machine.contains(|s| *s == "map"));
+  assert!(!machine.contains(|s| *s == "title"));
+  assert_eq!(machine.position(|s| *s == "world"), Some(2));
+  assert_eq!(machine.depth_of(|s| *s == "world"), Some(1));
+  assert_eq!(machine.depth_of(|s| *s == "title"), None);
+  assert_eq!(machine.find_map(|s| s.strip_prefix("wor")), Some("ld"));
+}
+
+#[test]
+fn retain() {
+  let mut machine = StateMachine::new_many(vec![1, 2, 3, 4]);
+  let four = machine.active_id();
+  machine.retain(|n| n % 2 == 0).unwrap();
+  assert_eq!(machine.get_stack(), &[2, 4]);
+  assert_eq!(machine.active_id(), four);
+
+  let res = machine.retain_mut(|n| {
+    *n += 1;
+    false
+  });
+  assert!(res.is_err());
+  assert_eq!(machine.get_stack(), &[3, 5]);
+}
+
+#[test]
+fn truncate_to_bottom() {
+  let mut machine =
+    StateMachine::new_many(vec!["title", "world", "map", "legend"]);
+  assert_eq!(machine.truncate_to_bottom(2), Ok(vec!["map", "legend"]));
+  assert_eq!(machine.truncate_to_bottom(5), Ok(vec![]));
+  assert!(machine.truncate_to_bottom(0).is_err());
+  assert_eq!(machine.get_stack(), &["title", "world"]);
+}
+
+#[test]
+fn split_off_top() {
+  use std::num::NonZeroUsize;
+
+  let mut machine =
+    StateMachine::new_many(vec!["world", "wizard 1", "wizard 2"]);
+  let two = NonZeroUsize::new(2).unwrap();
+  let wizard = machine.split_off_top(two).unwrap();
+  assert_eq!(wizard.get_stack(), &["wizard 1", "wizard 2"]);
+  assert_eq!(machine.get_stack(), &["world"]);
+
+  assert!(machine.split_off_top(NonZeroUsize::MIN).is_err());
+}
+
+#[test]
+fn append() {
+  let mut machine =
+    StateMachine::new_many(vec!["world", "wizard 1", "wizard 2"]);
+  let wizard = machine.split_off_top(std::num::NonZeroUsize::MIN).unwrap();
+  machine.push("popup").unwrap();
+  machine.append(wizard).unwrap();
+  assert_eq!(
+    machine.get_stack(),
+    &["world", "wizard 1", "popup", "wizard 2"]
+  );
+  assert_eq!(machine.get_ids().len(), 4);
+}
+
+#[test]
+fn map_machine() {
+  let mut machine = StateMachine::new_many(vec!["1", "2", "3"]);
+  machine.mark("three");
+  let two = machine.id_at(1).unwrap();
+
+  let mut machine = machine.map(|s| s.parse::<i32>().unwrap());
+  assert_eq!(machine.get_stack(), &[1, 2, 3]);
+  assert_eq!(machine.position_of(two), Some(1));
+  machine.push(4).unwrap();
+  machine.apply(Transition::pop_to_mark("three")).unwrap();
+  assert_eq!(machine.get_stack(), &[1, 2, 3]);
+}
+
+#[test]
+fn try_map_machine() {
+  let machine = StateMachine::new_many(vec!["1", "2", "3"]);
+  let machine = machine.try_map(|s| s.parse::<i32>()).unwrap();
+  assert_eq!(*machine.active(), 3);
+
+  let machine = StateMachine::new_many(vec!["1", "two", "3"]);
+  assert!(machine.try_map(|s| s.parse::<i32>()).is_err());
+}
+
+#[test]
+fn view() {
+  fn describe(view: StackView<'_, &str>) -> String {
+    let (under, active) = view.split_last();
+    format!("{} over {}", active, under.join(", "))
+  }
+
+  let machine = StateMachine::new_many(vec!["title", "world", "pause"]);
+  let view = machine.as_view();
+  assert_eq!(describe(view), "pause over title, world");
+  assert_eq!(view.parent(), Some(&"world"));
+  assert_eq!(view.get_from_top(2), Some(&"title"));
+  assert_eq!(view.len().get(), 3);
+  assert_eq!(view.into_iter().count(), 3);
+}
+
+#[test]
+fn take_active() {
+  let mut machine =
+    StateMachine::new_many(vec![String::from("world"), String::from("map")]);
+  let map = machine.take_active();
+  assert_eq!(map, "map");
+  assert_eq!(machine.get_stack(), &["world", ""]);
+}
+
+#[test]
+fn replace_active() {
+  let mut machine = StateMachine::new_many(vec!["world", "map"]);
+  let id = machine.active_id();
+  assert_eq!(machine.replace_active("legend"), "map");
+  assert_eq!(machine.get_stack(), &["world", "legend"]);
+  assert_eq!(machine.active_id(), id);
+}
+
+#[test]
+fn reset() {
+  let mut machine = StateMachine::new_many(vec!["world", "map"]);
+  assert_eq!(machine.reset("title"), Ok(vec!["world", "map"]));
+  assert_eq!(machine.get_stack(), &["title"]);
+}
+
+#[test]
+fn capacity() {
+  let mut machine = StateMachine::with_capacity("world", 8);
+  assert!(machine.capacity() >= 8);
+  machine.reserve(16);
+  assert!(machine.capacity() >= 17);
+  machine.shrink_to_fit();
+  assert_eq!(machine.get_stack(), &["world"]);
+}
+
+#[test]
+fn drain_above() {
+  let mut machine =
+    StateMachine::new_many(vec!["title", "world", "map", "legend"]);
+  assert_eq!(machine.drain_above(1), Ok(vec!["map", "legend"]));
+  assert_eq!(machine.drain_above(1), Ok(vec![]));
+  assert_eq!(
+    machine.drain_above(2),
+    Err(TransitionError::OutOfBounds { index: 2, len: 2 })
+  );
+  assert_eq!(machine.get_stack(), &["title", "world"]);
+}
+
+#[test]
+fn cursor() {
+  let mut machine = StateMachine::new_many(vec!["title", "world", "pause"]);
+  let pause = machine.active_id();
+
+  let mut cursor = machine.cursor().unwrap();
+  assert_eq!(*cursor.get(), "pause");
+  assert!(!cursor.move_up());
+  assert!(cursor.move_down());
+  assert_eq!(cursor.replace("map"), Ok("world"));
+  cursor.insert_below("loading");
+  cursor.insert_above("legend");
+  assert_eq!(*cursor.get(), "map");
+  assert_eq!(cursor.index(), 2);
+  assert!(cursor.move_down());
+  assert_eq!(cursor.remove(), Ok("loading"));
+  assert_eq!(*cursor.get(), "title");
+
+  assert_eq!(machine.get_stack(), &["title", "map", "legend", "pause"]);
+  assert_eq!(machine.position_of(pause), Some(3));
+  assert_eq!(machine.get_ids().len(), 4);
+
+  let mut machine = StateMachine::new("title");
+  assert!(machine.cursor().unwrap().remove().is_err());
+}
+
+#[test]
+fn pairs() {
+  let machine = StateMachine::new_many(vec!["world", "map", "legend"]);
+  let pairs: Vec<_> = machine.pairs().collect();
+  assert_eq!(pairs, vec![(&"world", &"map"), (&"map", &"legend")]);
+  assert_eq!(machine.pairs().next_back(), Some((&"map", &"legend")));
+  assert_eq!(machine.pairs().len(), 2);
+  assert_eq!(StateMachine::new("world").pairs().count(), 0);
+}
+
+#[test]
+fn iter_orders() {
+  let machine = StateMachine::new_many(vec![1, 2, 3]);
+  assert_eq!(
+    machine.iter_top_down().copied().collect::<Vec<_>>(),
+    [3, 2, 1]
+  );
+  assert_eq!(
+    machine.iter_bottom_up().copied().collect::<Vec<_>>(),
+    [1, 2, 3]
+  );
+  assert!(machine.iter().eq(machine.iter_bottom_up()));
+  assert_eq!(machine.iter_top_down().len(), 3);
+}
+
+#[test]
+fn equality() {
+  use std::collections::HashSet;
+
+  let a = StateMachine::new_many(vec!["world", "map"]);
+  let mut b = StateMachine::new("world");
+  b.push("map").unwrap();
+  b.mark("map");
+  assert_eq!(a, b);
+  assert_ne!(a, StateMachine::new("world"));
+
+  let set: HashSet<_> = [a, b.clone()].into_iter().collect();
+  assert_eq!(set.len(), 1);
+  assert!(format!("{:?}", b).contains("map"));
+}
+
+#[test]
+fn default_machine() {
+  #[derive(Default)]
+  struct Game {
+    states: StateMachine<String>,
+  }
+  let game = Game::default();
+  assert_eq!(game.states.get_stack(), &[String::new()]);
+}
+
+#[test]
+fn display() {
+  let machine = StateMachine::new_many(vec!["world", "pause", "settings"]);
+  assert_eq!(machine.to_string(), "world › pause › settings (active)");
+  assert_eq!(
+    machine
+      .display()
+      .separator(" > ")
+      .active_suffix("*")
+      .to_string(),
+    "world > pause > settings*"
+  );
+}
+
+#[test]
+fn borrowing_into_iter() {
+  let mut machine = StateMachine::new_many(vec![1, 2, 3]);
+  for n in &mut machine {
+    *n *= 10;
+  }
+  let mut seen = Vec::new();
+  for n in &machine {
+    seen.push(*n);
+  }
+  assert_eq!(seen, [10, 20, 30]);
+}
+
+#[test]
+fn apply_and_active() {
+  let mut machine = StateMachine::new(String::from("world"));
+  let (outcome, active) = machine
+    .apply_and_active(Transition::Push("map".into()))
+    .unwrap();
+  assert_eq!(outcome, TransitionOutcome::Pushed(0));
+  active.push_str(" of the world");
+  assert_eq!(machine.active(), "map of the world");
+}
+
+#[test]
+fn protect_bottom() {
+  let mut machine = StateMachine::new_many(vec!["shell", "router", "home"]);
+  machine.protect_bottom(2);
+
+  machine.push("settings").unwrap();
+  assert_eq!(
+    machine.apply(Transition::PopAll),
+    Err(TransitionError::Protected)
+  );
+  assert_eq!(
+    machine.get_stack(),
+    &["shell", "router", "home", "settings"]
+  );
+  assert_eq!(
+    machine.apply(Transition::Reset("title")),
+    Err(TransitionError::Protected)
+  );
+  assert_eq!(
+    machine.apply(Transition::RemoveAt(1)),
+    Err(TransitionError::Protected)
+  );
+  assert!(machine.retain(|s| s.starts_with('s')).is_err());
+  assert_eq!(machine.cursor().unwrap().remove(), Ok("settings"));
+  let mut cursor = machine.cursor().unwrap();
+  cursor.move_down();
+  assert_eq!(cursor.replace("menu"), Err(TransitionError::Protected));
+  drop(cursor);
+
+  machine.apply(Transition::PopN(1)).unwrap();
+  assert_eq!(machine.pop(), Err(TransitionError::Protected));
+  machine.apply(Transition::InsertAt(0, "loader")).unwrap();
+  assert_eq!(machine.get_stack(), &["loader", "shell", "router"]);
+
+  machine.protect_bottom(0);
+  assert!(machine.apply(Transition::PopAll).is_ok());
+}
+
+#[test]
+fn dry_run_protected() {
+  let mut machine = StateMachine::new_many(vec!["shell", "home"]);
+  machine.protect_bottom(1);
+  assert_eq!(
+    Transition::Reset("title").dry_run(&machine),
+    Err(TransitionError::Protected)
+  );
+  assert_eq!(
+    Transition::Pop.dry_run(&machine),
+    Ok(TransitionShape::Revealed(1))
+  );
+}
+
+#[test]
+fn freeze() {
+  let mut machine = StateMachine::new_many(vec!["world", "cutscene"]);
+  machine.freeze();
+  assert!(machine.is_frozen());
+  assert_eq!(machine.push("pause"), Err(TransitionError::Locked));
+  assert_eq!(machine.pop(), Err(TransitionError::Locked));
+  assert_eq!(
+    machine.apply_all(vec![Transition::Pop]),
+    Err((0, TransitionError::Locked))
+  );
+  assert_eq!(machine.push_many(["pause"]), Err(TransitionError::Locked));
+  assert!(matches!(machine.cursor(), Err(TransitionError::Locked)));
+
+  machine.defer(Transition::Pop);
+  assert!(machine.flush_deferred().is_empty());
+  assert_eq!(machine.get_stack(), &["world", "cutscene"]);
+
+  machine.unfreeze();
+  assert_eq!(
+    machine.flush_deferred(),
+    vec![Ok(TransitionOutcome::Revealed(vec!["cutscene"]))]
+  );
+  assert_eq!(machine.get_stack(), &["world"]);
+}
+
+#[test]
+fn hooks() {
+  use std::{cell::RefCell, rc::Rc};
+
+  struct Logged(&'static str, Rc<RefCell<Vec<String>>>);
+  impl Logged {
+    fn log(&self, hook: &str) {
+      self.1.borrow_mut().push(format!("{} {}", self.0, hook));
+    }
+  }
+  impl State for Logged {
+    fn on_enter(&mut self, _: &mut ()) -> Result<(), Cow<'static, str>> {
+      self.log("enter");
+      Ok(())
+    }
+    fn on_exit(&mut self, _: &mut ()) -> Result<(), Cow<'static, str>> {
+      self.log("exit");
+      Ok(())
+    }
+    fn on_pause(&mut self, _: &mut ()) {
+      self.log("pause")
+    }
+    fn on_resume(&mut self, _: &mut ()) {
+      self.log("resume")
+    }
+  }
+
+  let log = Rc::new(RefCell::new(Vec::new()));
+  let state = |name| Logged(name, log.clone());
+  let mut machine = StateMachine::new(state("world"));
+  let mut check = |transition, expected: &[&str]| {
+    machine.apply_with_hooks(transition, &mut ()).unwrap();
+    assert_eq!(log.borrow_mut().drain(..).collect::<Vec<_>>(), expected);
+  };
+
+  check(
+    Transition::Push(state("pause")),
+    &["world pause", "pause enter"],
+  );
+  check(
+    Transition::Swap(state("settings")),
+    &["pause exit", "settings enter"],
+  );
+  check(Transition::Pop, &["settings exit", "world resume"]);
+  check(Transition::InsertAt(0, state("title")), &["title enter"]);
+}
+
+#[test]
+fn failing_hooks() {
+  use std::{cell::RefCell, rc::Rc};
+
+  struct Fussy(&'static str, Rc<RefCell<Vec<String>>>);
+  impl Fussy {
+    fn log(&self, hook: &str) {
+      self.1.borrow_mut().push(format!("{} {}", self.0, hook));
+    }
+  }
+  impl State for Fussy {
+    fn on_enter(&mut self, _: &mut ()) -> Result<(), Cow<'static, str>> {
+      self.log("enter");
+      if self.0 == "broken" {
+        Err(format!("missing assets for {}", self.0).into())
+      } else {
+        Ok(())
+      }
+    }
+    fn on_exit(&mut self, _: &mut ()) -> Result<(), Cow<'static, str>> {
+      self.log("exit");
+      if self.0 == "sticky" {
+        Err("won't leave".into())
+      } else {
+        Ok(())
+      }
+    }
+    fn on_pause(&mut self, _: &mut ()) {
+      self.log("pause")
+    }
+    fn on_resume(&mut self, _: &mut ()) {
+      self.log("resume")
+    }
+  }
+
+  let log = Rc::new(RefCell::new(Vec::new()));
+  let state = |name| Fussy(name, log.clone());
+  let mut machine =
+    StateMachine::new_many(vec![state("world"), state("sticky")]);
+  let results = Rc::new(RefCell::new(Vec::new()));
+  let seen = results.clone();
+  machine.add_observer(move |event: &TransitionEvent<Fussy>| {
+    seen.borrow_mut().push(event.result.is_ok());
+  });
+  let mut check = |transition, reason: &'static str, expected: &[&str]| {
+    assert_eq!(
+      machine.apply_with_hooks(transition, &mut ()).err(),
+      Some(TransitionError::HookFailed(reason.into()))
+    );
+    let stack: Vec<_> = machine.iter().map(|state| state.0).collect();
+    assert_eq!(stack, ["world", "sticky"]);
+    assert_eq!(log.borrow_mut().drain(..).collect::<Vec<_>>(), expected);
+  };
+
+  check(
+    Transition::PushMany(Box::new(
+      vec![state("fine"), state("broken")].into_iter(),
+    )),
+    "missing assets for broken",
+    &[
+      "sticky pause",
+      "fine enter",
+      "broken enter",
+      "fine exit",
+      "sticky resume",
+    ],
+  );
+  check(
+    Transition::Swap(state("next")),
+    "won't leave",
+    &["sticky exit"],
+  );
+  // observers only hear about the transitions once the hooks have failed
+  assert_eq!(*results.borrow(), [false, false]);
+}
+
+#[test]
+fn tick() {
+  #[derive(Debug, PartialEq)]
+  struct Countdown(u32);
+  impl State<u32> for Countdown {
+    fn update(&mut self, ticks: &mut u32) -> Transition<Self> {
+      *ticks += 1;
+      self.0 -= 1;
+      match self.0 {
+        0 => Transition::Pop,
+        n if n % 2 == 0 => Transition::Push(Countdown(1)),
+        _ => Transition::None,
+      }
+    }
+  }
+
+  let mut machine = StateMachine::new_many(vec![Countdown(10), Countdown(3)]);
+  let mut ticks = 0;
+  assert_eq!(machine.tick(&mut ticks), Ok(TransitionOutcome::Pushed(0)));
+  assert!(matches!(
+    machine.tick(&mut ticks),
+    Ok(TransitionOutcome::Revealed(states)) if states.len() == 1
+  ));
+  assert_eq!(machine.tick(&mut ticks), Ok(TransitionOutcome::None));
+  assert_eq!(machine.active().0, 1);
+  assert_eq!(ticks, 3);
+}
+
+#[test]
+fn iter_visible() {
+  #[derive(Debug, PartialEq)]
+  struct Screen(&'static str, bool);
+  impl State for Screen {
+    fn draw_previous(&self) -> bool {
+      self.1
+    }
+  }
+
+  let mut machine = StateMachine::new_many(vec![
+    Screen("title", false),
+    Screen("world", false),
+    Screen("hud", true),
+    Screen("pause", true),
+  ]);
+  let names = |machine: &StateMachine<Screen>| {
+    machine.iter_visible().map(|s| s.0).collect::<Vec<_>>()
+  };
+  assert_eq!(names(&machine), ["world", "hud", "pause"]);
+
+  machine.push(Screen("inventory", false)).unwrap();
+  assert_eq!(names(&machine), ["inventory"]);
+
+  let machine =
+    StateMachine::new_many(vec![Screen("a", true), Screen("b", true)]);
+  assert_eq!(names(&machine), ["a", "b"]);
+}
+
+#[test]
+fn tick_all() {
+  #[derive(Debug, PartialEq)]
+  struct Layer {
+    ticks: u32,
+    overlay: bool,
+  }
+  impl State for Layer {
+    fn update(&mut self, _: &mut ()) -> Transition<Self> {
+      self.ticks += 1;
+      Transition::None
+    }
+    fn update_previous(&self) -> bool {
+      self.overlay
+    }
+  }
+  let layer = |overlay| Layer { ticks: 0, overlay };
+
+  let mut machine =
+    StateMachine::new_many(vec![layer(false), layer(false), layer(true)]);
+  assert_eq!(machine.iter_updating_mut().count(), 2);
+  assert_eq!(machine.tick_all(&mut ()).len(), 2);
+  let ticks: Vec<_> = machine.iter().map(|l| l.ticks).collect();
+  assert_eq!(ticks, [0, 1, 1]);
+
+  #[derive(Debug, PartialEq)]
+  struct Scripted(&'static str);
+  impl State for Scripted {
+    fn update(&mut self, _: &mut ()) -> Transition<Self> {
+      match self.0 {
+        "middle" => Transition::Push(Scripted("pushed")),
+        "killer" => Transition::PopN(2),
+        _ => Transition::None,
+      }
+    }
+    fn update_previous(&self) -> bool {
+      self.0 != "bottom"
+    }
+  }
+  let names = |machine: &StateMachine<Scripted>| {
+    machine.iter().map(|state| state.0).collect::<Vec<_>>()
+  };
+
+  // transitions from lower states still apply from the top
+  let mut machine = StateMachine::new_many(vec![
+    Scripted("bottom"),
+    Scripted("middle"),
+    Scripted("top"),
+  ]);
+  assert!(machine.tick_all(&mut ()).iter().all(Result::is_ok));
+  assert_eq!(names(&machine), ["bottom", "middle", "top", "pushed"]);
+
+  // but not if the state they came from is gone
+  let mut machine = StateMachine::new_many(vec![
+    Scripted("bottom"),
+    Scripted("middle"),
+    Scripted("killer"),
+  ]);
+  let middle = machine.id_at(1).unwrap();
+  let results = machine.tick_all(&mut ());
+  assert!(results[0].is_ok());
+  assert_eq!(results[1], Err(TransitionError::NoSuchState(middle)));
+  assert!(results[2].is_ok());
+  assert_eq!(names(&machine), ["bottom"]);
+}
+
+#[test]
+fn pop_with() {
+  use std::any::Any;
+
+  #[derive(Debug, PartialEq)]
+  enum Menu {
+    Main(Option<u32>),
+    Picker,
+  }
+  impl State for Menu {
+    fn on_result(&mut self, result: Box<dyn Any>, _: &mut ()) {
+      if let (Menu::Main(picked), Ok(value)) = (self, result.downcast()) {
+        *picked = Some(*value);
+      }
+    }
+  }
+
+  let mut machine = StateMachine::new(Menu::Main(None));
+  machine.push(Menu::Picker).unwrap();
+  machine
+    .apply_with_hooks(Transition::pop_with(7u32), &mut ())
+    .unwrap();
+  assert_eq!(machine.active(), &Menu::Main(Some(7)));
+
+  // Plain apply just pops.
+  machine.push(Menu::Picker).unwrap();
+  machine.apply(Transition::pop_with(9u32)).unwrap();
+  assert_eq!(machine.active(), &Menu::Main(Some(7)));
+  assert!(machine.apply(Transition::pop_with(())).is_err());
+}
+
+#[test]
+fn push_modal() {
+  let mut machine = StateMachine::new("world");
+  let dialog = machine.push_modal::<bool>("quit?").unwrap();
+  assert_eq!(dialog.id(), machine.active_id());
+  assert_eq!(dialog.poll(&machine), ModalStatus::Open);
+  machine.apply(Transition::pop_with(true)).unwrap();
+  assert_eq!(dialog.poll(&machine), ModalStatus::Answered(true));
+  assert_eq!(dialog.poll(&machine), ModalStatus::Closed);
+
+  // Popping some other way, or with the wrong type, leaves no answer.
+  let dialog = machine.push_modal::<bool>("quit?").unwrap();
+  machine.apply(Transition::pop_with("yes")).unwrap();
+  assert_eq!(dialog.poll(&machine), ModalStatus::Closed);
+  let dialog = machine.push_modal::<bool>("quit?").unwrap();
+  machine.pop().unwrap();
+  assert_eq!(dialog.poll(&machine), ModalStatus::Closed);
+}
+
+#[test]
+fn dispatch_event() {
+  struct Layer {
+    wants: char,
+    got: Vec<char>,
+  }
+  impl HandleEvent<char, u32> for Layer {
+    fn handle_event(&mut self, key: &char, offers: &mut u32) -> EventResponse {
+      *offers += 1;
+      if *key == self.wants {
+        self.got.push(*key);
+        EventResponse::Handled
+      } else {
+        EventResponse::Pass
+      }
+    }
+  }
+
+  let layer = |wants| Layer {
+    wants,
+    got: Vec::new(),
+  };
+  let mut machine = StateMachine::new_many(vec![layer('w'), layer('p')]);
+  let mut offers = 0;
+  assert_eq!(machine.dispatch_event('p', &mut offers), Some(1));
+  assert_eq!(offers, 1);
+  assert_eq!(machine.dispatch_event('w', &mut offers), Some(0));
+  assert_eq!(offers, 3);
+  assert_eq!(machine.dispatch_event('x', &mut offers), None);
+  assert_eq!(offers, 5);
+  assert_eq!(machine[0].got, ['w']);
+  assert_eq!(machine[1].got, ['p']);
+}
+
+#[test]
+fn for_each_requesting() {
+  let mut machine = StateMachine::new_many(vec![1, 2, 3]);
+  let results = machine.for_each_requesting(|state, requests| {
+    *state *= 10;
+    if *state == 30 {
+      requests.request(Transition::Pop);
+    } else {
+      requests.request(Transition::Push(*state + 1));
+    }
+  });
+  assert_eq!(results.len(), 3);
+  assert!(results.iter().all(Result::is_ok));
+  assert_eq!(machine.get_stack(), &[10, 20, 21, 11]);
+
+  let mut requests = TransitionRequests::new();
+  assert!(requests.is_empty());
+  requests.request(Transition::PopN(10));
+  requests.request(Transition::Pop);
+  assert_eq!(requests.len(), 2);
+  let results = machine.apply_requests(requests);
+  assert!(results[0].is_err());
+  assert!(results[1].is_ok());
+  assert_eq!(machine.get_stack(), &[10, 20, 21]);
+}
+
+#[test]
+fn mailbox() {
+  #[derive(Debug, Default)]
+  struct Inbox(Vec<&'static str>);
+  impl Receive<&'static str> for Inbox {
+    fn receive(&mut self, _: StateId, message: &&'static str) {
+      self.0.push(message);
+    }
+  }
+
+  let mut machine = StateMachine::new_many(vec![
+    Inbox::default(),
+    Inbox::default(),
+    Inbox::default(),
+  ]);
+  let ids = machine.get_ids().to_vec();
+  let mut mailbox = Mailbox::new();
+  mailbox.post(ids[2], Recipient::Parent, "parent");
+  mailbox.post(ids[2], Recipient::Bottom, "bottom");
+  mailbox.post(ids[0], Recipient::Id(ids[2]), "top");
+  mailbox.post(ids[1], Recipient::Broadcast, "everyone");
+  mailbox.post(ids[0], Recipient::Parent, "nobody");
+  assert_eq!(mailbox.len(), 5);
+  assert_eq!(machine.deliver(&mut mailbox), 5);
+  assert!(mailbox.is_empty());
+  assert_eq!(machine[0].0, ["bottom", "everyone"]);
+  assert_eq!(machine[1].0, ["parent"]);
+  assert_eq!(machine[2].0, ["top", "everyone"]);
+
+  machine.pop().unwrap();
+  mailbox.post(ids[1], Recipient::Id(ids[2]), "gone");
+  assert_eq!(machine.deliver(&mut mailbox), 0);
+}
+
+#[test]
+fn runner() {
+  use std::time::Duration;
+
+  #[derive(Debug)]
+  struct Counter(u32);
+  impl State for Counter {
+    fn update(&mut self, _: &mut ()) -> Transition<Self> {
+      self.0 += 1;
+      Transition::None
+    }
+  }
+
+  let step = Duration::from_millis(10);
+  let mut runner = Runner::new(StateMachine::new(Counter(0)), step);
+  assert_eq!(runner.timestep(), step);
+  assert!(runner.advance(Duration::from_millis(5), &mut ()).is_empty());
+  assert_eq!(runner.alpha(), 0.5);
+
+  let mut drawn = Vec::new();
+  let results =
+    runner.frame(Duration::from_millis(20), &mut (), |state, alpha| {
+      drawn.push((state.0, alpha))
+    });
+  assert_eq!(results.len(), 2);
+  assert_eq!(drawn, [(2, 0.5)]);
+
+  // A long stall only catches up on a few steps.
+  let mut runner = runner.max_steps(3);
+  assert_eq!(runner.advance(Duration::from_secs(1), &mut ()).len(), 3);
+  assert_eq!(runner.alpha(), 0.0);
+  assert_eq!(runner.into_machine().active().0, 5);
+}
+
+#[test]
+fn push_key() {
+  let mut machine = StateMachine::new("title");
+  machine.register("settings", || "settings");
+  assert!(machine.is_registered("settings"));
+  assert_eq!(machine.construct("settings"), Some("settings"));
+
+  assert_eq!(
+    Transition::push_key("settings").dry_run(&machine),
+    Ok(TransitionShape::Pushed(0))
+  );
+  let outcome = machine.apply(Transition::push_key("settings")).unwrap();
+  assert_eq!(outcome, TransitionOutcome::Pushed(0));
+  assert_eq!(machine.get_stack(), &["title", "settings"]);
+
+  machine.unregister("settings");
+  assert_eq!(
+    Transition::push_key("settings").dry_run(&machine),
+    Err(TransitionError::NoSuchKey)
+  );
+  assert_eq!(
+    machine.apply(Transition::push_key("settings")).err(),
+    Some(TransitionError::NoSuchKey)
+  );
+  let mut stack = vec!["title"];
+  assert!(Transition::push_key("settings").apply(&mut stack).is_err());
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn timed_states() {
+  use std::time::Duration;
+
+  let mut machine = StateMachine::new("game");
+  let toast = machine.push_timed("toast", Duration::from_secs(2)).unwrap();
+  let banner = machine
+    .push_timed("banner", Duration::from_secs(1))
+    .unwrap();
+  assert_eq!(machine.time_left(toast), Some(Duration::from_secs(2)));
+
+  assert!(machine.tick_time(Duration::from_millis(500)).is_empty());
+  let removed = machine.tick_time(Duration::from_millis(500));
+  assert_eq!(removed.len(), 1);
+  assert_eq!(machine.get_stack(), &["game", "toast"]);
+  assert_eq!(machine.time_left(banner), None);
+
+  machine.set_expiry(toast, Duration::from_secs(5));
+  assert!(machine.tick_time(Duration::from_secs(2)).is_empty());
+  machine.clear_expiry(toast);
+  assert!(machine.tick_time(Duration::from_secs(10)).is_empty());
+  assert_eq!(machine.get_stack(), &["game", "toast"]);
+
+  // The last state can't be removed, so it waits.
+  let mut machine = StateMachine::new("splash");
+  let splash = machine.active_id();
+  machine.set_expiry(splash, Duration::ZERO);
+  assert!(machine.tick_time(Duration::ZERO)[0].is_err());
+  machine.push("title").unwrap();
+  assert!(machine.tick_time(Duration::ZERO)[0].is_ok());
+  assert_eq!(machine.get_stack(), &["title"]);
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn track_time() {
+  use std::time::{Duration, Instant};
+
+  let before = Instant::now();
+  let mut machine = StateMachine::new("game");
+  machine.track_time(Duration::from_secs(3));
+  let menu = machine.push_timed("menu", Duration::MAX).unwrap();
+  machine.track_time(Duration::from_secs(2));
+
+  let game = machine.times_at(0).unwrap();
+  assert!(game.pushed_at >= before);
+  assert_eq!(game.active, Duration::from_secs(3));
+  assert_eq!(game.paused, Duration::from_secs(2));
+  let times = machine.times_of(menu).unwrap();
+  assert_eq!(times.active, Duration::from_secs(2));
+  assert_eq!(times.paused, Duration::ZERO);
+
+  machine.pop().unwrap();
+  assert_eq!(machine.times_of(menu), None);
+  assert_eq!(machine.times_at(1), None);
+
+  // popped states' times are forgotten even without tracking any time
+  for _ in 0..100 {
+    machine.push("menu").unwrap();
+    machine.pop().unwrap();
+  }
+  let kept = format!("{:?}", machine).matches("pushed_at").count();
+  assert!(kept <= 2, "kept times for {} states", kept);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn derive_state() {
+  #[derive(Debug, PartialEq)]
+  struct Title;
+  impl State<Vec<&'static str>> for Title {
+    fn update(&mut self, log: &mut Vec<&'static str>) -> Transition<Self> {
+      log.push("title update");
+      Transition::None
+    }
+    fn on_pause(&mut self, log: &mut Vec<&'static str>) {
+      log.push("title pause");
+    }
+  }
+
+  #[derive(Debug, PartialEq)]
+  struct Overlay<'a>(&'a str);
+  impl State<Vec<&'static str>> for Overlay<'static> {
+    fn update(&mut self, _: &mut Vec<&'static str>) -> Transition<Self> {
+      Transition::Pop
+    }
+    fn draw_previous(&self) -> bool {
+      true
+    }
+    fn on_enter(
+      &mut self,
+      log: &mut Vec<&'static str>,
+    ) -> Result<(), Cow<'static, str>> {
+      log.push("overlay enter");
+      Ok(())
+    }
+  }
+
+  #[derive(Debug, PartialEq, State)]
+  enum Screen<'a> {
+    Title(Title),
+    Overlay(Overlay<'a>),
+  }
+
+  let mut log = Vec::new();
+  let mut machine = StateMachine::new(Screen::Title(Title));
+  machine
+    .apply_with_hooks(
+      Transition::Push(Screen::Overlay(Overlay("hi"))),
+      &mut log,
+    )
+    .unwrap();
+  assert_eq!(log, ["title pause", "overlay enter"]);
+  assert_eq!(machine.iter_visible().count(), 2);
+
+  machine.tick(&mut log).unwrap();
+  machine.tick(&mut log).unwrap();
+  assert_eq!(log.last(), Some(&"title update"));
+  assert_eq!(machine.get_stack(), &[Screen::Title(Title)]);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+#[should_panic(expected = "`Screen::Picky` returned a Transition::FromFn")]
+fn derive_state_from_fn() {
+  struct Picky;
+  impl State<()> for Picky {
+    fn update(&mut self, _: &mut ()) -> Transition<Self> {
+      Transition::from_fn(|_| Transition::None)
+    }
+  }
+
+  #[derive(State)]
+  enum Screen {
+    Picky(Picky),
+  }
+
+  let mut machine = StateMachine::new(Screen::Picky(Picky));
+  let _ = machine.tick(&mut ());
+}
+
+#[test]
+fn observers() {
+  use std::{cell::RefCell, rc::Rc};
+
+  let seen = Rc::new(RefCell::new(Vec::new()));
+  let mut machine = StateMachine::new("title");
+  let log = seen.clone();
+  machine.add_observer(move |event: &TransitionEvent<&str>| {
+    log.borrow_mut().push((
+      event.kind,
+      event.result.is_ok(),
+      event.depth_before,
+      event.depth_after,
+    ));
+  });
+
+  machine.push("game").unwrap();
+  assert_eq!(machine.pop(), Ok("game"));
+  assert!(machine.apply(Transition::PopN(3)).is_err());
+  machine.pop_n_and_push(0, ["menu"]).unwrap();
+  machine
+    .apply_all(vec![Transition::Push("shop"), Transition::Pop])
+    .unwrap();
+  assert!(machine
+    .apply_all(vec![Transition::Push("shop"), Transition::PopN(4)])
+    .is_err());
+  assert_eq!(
+    *seen.borrow(),
+    [
+      (TransitionKind::Push, true, 1, 2),
+      (TransitionKind::Pop, true, 2, 1),
+      (TransitionKind::PopN, false, 1, 1),
+      (TransitionKind::PopNAndPush, true, 1, 2),
+      (TransitionKind::Sequence, true, 2, 2),
+      (TransitionKind::Sequence, false, 2, 2),
+    ]
+  );
+
+  machine.clear_observers();
+  machine.pop().unwrap();
+  assert_eq!(seen.borrow().len(), 6);
+}
+
+#[test]
+fn history() {
+  let mut machine = StateMachine::new("title");
+  machine.push("ignored").unwrap();
+  assert_eq!(machine.history().count(), 0);
+
+  machine.record_history(2);
+  machine.push("game").unwrap();
+  machine.push("pause").unwrap();
+  assert!(machine.apply(Transition::PopN(10)).is_err());
+  machine.apply(Transition::PopN(2)).unwrap();
+  let entries: Vec<_> = machine
+    .history()
+    .map(|entry| (entry.kind, entry.removed, entry.depth))
+    .collect();
+  assert_eq!(
+    entries,
+    [(TransitionKind::Push, 0, 4), (TransitionKind::PopN, 2, 2)]
+  );
+  let times: Vec<_> = machine.history().map(|entry| entry.at).collect();
+  assert!(times[0] <= times[1]);
+
+  machine.record_history(1);
+  assert_eq!(machine.history().count(), 1);
+  machine.clear_history();
+  assert_eq!(machine.history().count(), 0);
+  machine.pop().unwrap();
+  assert_eq!(machine.history().count(), 1);
+  machine.stop_recording_history();
+  assert_eq!(machine.history().count(), 0);
+}
+
+#[test]
+fn undo_redo() {
+  use std::num::NonZeroUsize;
+
+  let mut machine = UndoableStateMachine::new(
+    StateMachine::new_many(vec!["a", "b"]),
+    NonZeroUsize::new(3).unwrap(),
+  );
+  assert!(!machine.can_undo());
+  assert_eq!(machine.undo(), Ok(false));
+
+  let transitions = vec![
+    Transition::Push("c"),
+    Transition::Sequence(vec![
+      Transition::PopBelow(1),
+      Transition::InsertAt(0, "z"),
+    ]),
+    Transition::Reset("fresh"),
+    Transition::InsertAt(0, "x"),
+  ];
+  let mut stacks = vec![machine.machine().get_stack().to_vec()];
+  for transition in transitions {
+    machine.apply(transition).unwrap();
+    stacks.push(machine.machine().get_stack().to_vec());
+  }
+
+  // Only the last three can be undone.
+  for stack in stacks[1..4].iter().rev() {
+    assert_eq!(machine.undo(), Ok(true));
+    assert_eq!(machine.machine().get_stack(), stack);
+  }
+  assert_eq!(machine.undo(), Ok(false));
+  for stack in &stacks[2..] {
+    assert_eq!(machine.redo(), Ok(true));
+    assert_eq!(machine.machine().get_stack(), stack);
+  }
+  assert!(!machine.can_redo());
+
+  machine.undo().unwrap();
+  machine.apply(Transition::Push("p")).unwrap();
+  assert!(!machine.can_redo());
+  assert_eq!(machine.redo(), Ok(false));
+  machine.clear_history();
+  assert!(!machine.can_undo());
+
+  // Deferred and held transitions don't take up undo steps
+  assert_eq!(
+    machine
+      .apply(Transition::Sequence(vec![
+        Transition::Push("q"),
+        Transition::Defer(Box::new(Transition::Pop)),
+      ]))
+      .err(),
+    Some(TransitionError::NowhereToDefer)
+  );
+  assert!(!machine.can_undo());
+  assert_eq!(machine.into_machine().get_stack(), &["fresh", "p"]);
+
+  let mut held = StateMachine::new("title");
+  held.set_interceptor(|_: &[&str], transition| Verdict::Hold(transition));
+  let mut machine = UndoableStateMachine::new(held, NonZeroUsize::MIN);
+  assert_eq!(
+    machine.apply(Transition::Push("game")).err(),
+    Some(TransitionError::Held)
+  );
+  assert!(!machine.can_undo());
+}
+
+#[test]
+fn checkpoints() {
+  let mut machine = StateMachine::new_many(vec!["menu", "level 1"]);
+  machine.checkpoint("level start");
+  assert!(machine.has_checkpoint("level start"));
+
+  machine.push("boss").unwrap();
+  machine.apply(Transition::PopBelow(2)).unwrap();
+  assert_eq!(machine.get_stack(), &["boss"]);
+  machine.rollback_to("level start").unwrap();
+  assert_eq!(machine.get_stack(), &["menu", "level 1"]);
+
+  // It's still there, to retry again.
+  machine.push("boss").unwrap();
+  machine.rollback_to("level start").unwrap();
+  assert_eq!(machine.get_stack(), &["menu", "level 1"]);
+
+  assert_eq!(
+    machine.remove_checkpoint("level start"),
+    Some(vec!["menu", "level 1"])
+  );
+  assert_eq!(
+    machine.rollback_to("level start").err(),
+    Some(TransitionError::NoSuchCheckpoint)
+  );
+}
+
+#[test]
+fn snapshots() {
+  let mut machine = StateMachine::new_many(vec!["title", "world"]);
+  machine.mark("overworld");
+  let snapshot = machine.snapshot();
+  assert_eq!(snapshot.states(), &["title", "world"]);
+  assert_eq!(snapshot.marked("overworld"), Some(1));
+
+  machine.reset("credits").unwrap();
+  machine.restore(snapshot.clone()).unwrap();
+  assert_eq!(machine.get_stack(), &["title", "world"]);
+  assert_eq!(machine.marked("overworld"), Some(machine.active_id()));
+
+  #[cfg(feature = "serde")]
+  {
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let back: StackSnapshot<String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.states(), &["title", "world"]);
+
+    let bad: StackSnapshot<String> =
+      serde_json::from_str(r#"{"states":["a"],"marks":[["m",3]]}"#).unwrap();
+    let mut machine = StateMachine::new("b".to_string());
+    assert_eq!(
+      machine.restore(bad).err(),
+      Some(TransitionError::OutOfBounds { index: 3, len: 1 })
+    );
+    let empty: StackSnapshot<String> =
+      serde_json::from_str(r#"{"states":[],"marks":[]}"#).unwrap();
+    assert_eq!(
+      machine.restore(empty).err(),
+      Some(TransitionError::EmptyReplacement)
+    );
+    assert_eq!(machine.get_stack(), &["b"]);
+  }
+}
+
+#[test]
+fn record_and_replay() {
+  let mut recorder = Recorder::new(StateMachine::new("title"));
+  recorder.apply(Transition::Push("game")).unwrap();
+  assert!(recorder.apply(Transition::PopN(5)).is_err());
+  recorder
+    .apply(Transition::from_fn(|_| Transition::Push("pause")))
+    .unwrap();
+  recorder
+    .apply(Transition::Sequence(vec![
+      Transition::Pop,
+      Transition::InsertAt(0, "splash"),
+    ]))
+    .unwrap();
+  assert_eq!(recorder.script().steps().len(), 4);
+  let (machine, script) = recorder.finish();
+  assert_eq!(script.initial(), &["title"]);
+
+  let replayed = script.replay().unwrap();
+  assert_eq!(replayed.get_stack(), machine.get_stack());
+  assert_eq!(replayed.get_ids(), machine.get_ids());
+
+  // States pushed by steps that can't be copied keep their ids on replay
+  let machine = StateMachine::new("title");
+  let title = machine.active_id();
+  let mut recorder = Recorder::new(machine);
+  recorder
+    .apply(Transition::PushMany(Box::new(["menu", "game"].into_iter())))
+    .unwrap();
+  recorder.apply(Transition::RemoveId(title)).unwrap();
+  let (machine, script) = recorder.finish();
+  let replayed = script.replay().unwrap();
+  assert_eq!(replayed.get_stack(), &["menu", "game"]);
+  assert_eq!(replayed.get_ids(), machine.get_ids());
+
+  // Machines can be set up like the recorded one before replaying
+  let mut machine = StateMachine::new("title");
+  machine.register("shop", || "shop");
+  let mut recorder = Recorder::new(machine);
+  recorder.apply(Transition::PushKey("shop".into())).unwrap();
+  let (_, script) = recorder.finish();
+  let replayed = script
+    .replay_with(|machine| machine.register("shop", || "shop"))
+    .unwrap();
+  assert_eq!(replayed.get_stack(), &["title", "shop"]);
+
+  // Steps that can't be copied only touch what they changed, so protected states are fine
+  let mut machine = StateMachine::new("title");
+  machine.protect_bottom(1);
+  let mut recorder = Recorder::new(machine);
+  recorder.apply(Transition::push_with(|| "menu")).unwrap();
+  recorder
+    .apply(Transition::pop_until(|s| *s == "title"))
+    .unwrap();
+  let (machine, script) = recorder.finish();
+  let replayed = script
+    .replay_with(|machine| machine.protect_bottom(1))
+    .unwrap();
+  assert_eq!(replayed.get_stack(), &["title"]);
+  assert_eq!(replayed.get_ids(), machine.get_ids());
+
+  // Starting somewhere else makes the steps do something different.
+  let mut elsewhere =
+    StateMachine::new_many(vec!["a", "b", "c", "d", "e", "f"]);
+  let steps = vec![
+    ScriptStep {
+      transition: Transition::Push("game"),
+      expected: Some(Ok(TransitionShape::Pushed(0))),
+      ids: None,
+    },
+    ScriptStep {
+      transition: Transition::PopN(5),
+      expected: Some(Err(TransitionError::PoppedTooMany {
+        popcnt: 5,
+        available: 1,
+      })),
+      ids: None,
+    },
+  ];
+  assert_eq!(
+    elsewhere.replay(steps),
+    Err(ReplayError::Mismatch {
+      step: 1,
+      expected: Err(TransitionError::PoppedTooMany {
+        popcnt: 5,
+        available: 1
+      }),
+      actual: Ok(TransitionShape::Revealed(5)),
+    })
+  );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn replay_logs() {
+  let mut machine = StateMachine::new_many(vec!["title", "menu"]);
+  machine.push("game").unwrap();
+  machine.mark("game");
+  machine.pop().unwrap();
+  machine.push("game").unwrap();
+  machine.mark("game");
+  let game = machine.active_id();
+
+  let mut recorder = Recorder::new(machine);
+  recorder.apply(Transition::Push("pause")).unwrap();
+  recorder.apply(Transition::Push("options")).unwrap();
+  recorder
+    .apply(Transition::PopToMark("game".into()))
+    .unwrap();
+  recorder.apply(Transition::Push("inventory")).unwrap();
+  recorder.apply(Transition::RemoveId(game)).unwrap();
+  let (machine, script) = recorder.finish();
+
+  let json = serde_json::to_string(&script).unwrap();
+  let loaded: Script<String> = serde_json::from_str(&json).unwrap();
+  let replayed = loaded.replay().unwrap();
+  assert_eq!(replayed.get_stack(), machine.get_stack());
+  assert_eq!(replayed.active_id(), machine.active_id());
+
+  // Tampering with the starting stack makes the replay diverge...
+  let tampered = json.replacen(r#""menu""#, r#""menu","credits""#, 1);
+  let tampered = tampered.replacen(r#""ids":["#, r#""ids":[100,"#, 1);
+  let loaded: Script<String> = serde_json::from_str(&tampered).unwrap();
+  assert_eq!(loaded.replay().unwrap_err(), ReplayError::BadStart);
+  let tampered = json.replacen(r#""title","menu""#, r#""menu","title""#, 1);
+  let loaded: Script<String> = serde_json::from_str(&tampered).unwrap();
+  assert!(loaded.replay().is_ok());
+  let tampered = json.replacen(r#"["game","#, r#"["nothing","#, 1);
+  let loaded: Script<String> = serde_json::from_str(&tampered).unwrap();
+  assert!(matches!(
+    loaded.replay(),
+    Err(ReplayError::Mismatch { step: 2, .. })
+  ));
+  // ...but running it doesn't check.
+  let loaded: Script<String> = serde_json::from_str(&tampered).unwrap();
+  assert!(loaded.run().is_ok());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans() {
+  use std::sync::{Arc, Mutex};
+  use tracing::{span, Event, Metadata, Subscriber};
+
+  #[derive(Default)]
+  struct Collector {
+    spans: Mutex<Vec<String>>,
+    events: Mutex<Vec<(tracing::Level, Option<u64>)>>,
+    current: Mutex<Vec<u64>>,
+  }
+
+  struct Names<'a>(&'a mut String);
+  impl tracing::field::Visit for Names<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+      if field.name() == "name" {
+        self.0.push_str(value);
+      }
+    }
+    fn record_debug(
+      &mut self,
+      _: &tracing::field::Field,
+      _: &dyn std::fmt::Debug,
+    ) {
+    }
+  }
+
+  struct Sub(Arc<Collector>);
+  impl Subscriber for Sub {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+      true
+    }
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+      let mut spans = self.0.spans.lock().unwrap();
+      let mut name = String::new();
+      attrs.record(&mut Names(&mut name));
+      spans.push(name);
+      span::Id::from_u64(spans.len() as u64)
+    }
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+    fn event(&self, event: &Event<'_>) {
+      let current = self.0.current.lock().unwrap().last().copied();
+      let level = *event.metadata().level();
+      self.0.events.lock().unwrap().push((level, current));
+    }
+    fn enter(&self, span: &span::Id) {
+      self.0.current.lock().unwrap().push(span.into_u64());
+    }
+    fn exit(&self, _: &span::Id) {
+      self.0.current.lock().unwrap().pop();
+    }
+  }
+
+  let collector = Arc::new(Collector::default());
+  tracing::subscriber::with_default(Sub(collector.clone()), || {
+    let mut machine = StateMachine::new("title");
+    machine.name_states(|state| state.to_uppercase());
+    machine.push("game").unwrap();
+    assert!(machine.apply(Transition::PopN(3)).is_err());
+    machine.pop().unwrap();
+    assert_eq!(machine.active_name().as_deref(), Some("TITLE"));
+  });
+
+  assert_eq!(*collector.spans.lock().unwrap(), ["GAME", "TITLE"]);
+  let events = collector.events.lock().unwrap();
+  assert_eq!(
+    *events,
+    [
+      (tracing::Level::DEBUG, None),
+      (tracing::Level::WARN, Some(1)),
+      (tracing::Level::DEBUG, Some(1)),
+    ]
+  );
+}
+
+#[cfg(feature = "log")]
+#[test]
+fn log_records() {
+  use std::sync::Mutex;
+
+  static RECORDS: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+  struct Logger;
+  impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+      true
+    }
+    fn log(&self, record: &log::Record<'_>) {
+      let message = record.args().to_string();
+      // other tests are logging at the same time
+      if message.contains("LOGGED") {
+        RECORDS.lock().unwrap().push((record.level(), message));
+      }
+    }
+    fn flush(&self) {}
+  }
+  log::set_logger(&Logger).unwrap();
+  log::set_max_level(log::LevelFilter::Trace);
+
+  let mut machine = StateMachine::new("logged title");
+  machine.name_states(|state| state.to_uppercase());
+  machine.push("logged game").unwrap();
+  assert!(machine.apply(Transition::PopN(3)).is_err());
+
+  let records = RECORDS.lock().unwrap();
+  assert_eq!(
+    *records,
+    [
+      (
+        log::Level::Debug,
+        "applied Push: popped 0, depth 1 -> 2, active LOGGED GAME".into()
+      ),
+      (
+        log::Level::Trace,
+        "stack is now [LOGGED TITLE, LOGGED GAME]".into()
+      ),
+      (
+        log::Level::Warn,
+        "PopN failed at depth 2, active LOGGED GAME: \
+          Tried to pop 3 states, but could only pop 1"
+          .into()
+      ),
+    ]
+  );
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics() {
+  use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName,
+    Metadata, SharedString, Unit,
+  };
+  use std::sync::{Arc, Mutex};
+
+  type Log = Arc<Mutex<Vec<(String, f64)>>>;
+
+  struct Handle(String, Log);
+  impl CounterFn for Handle {
+    fn increment(&self, value: u64) {
+      self.1.lock().unwrap().push((self.0.clone(), value as f64));
+    }
+    fn absolute(&self, _: u64) {}
+  }
+  impl GaugeFn for Handle {
+    fn increment(&self, _: f64) {}
+    fn decrement(&self, _: f64) {}
+    fn set(&self, value: f64) {
+      self.1.lock().unwrap().push((self.0.clone(), value));
+    }
+  }
+  impl HistogramFn for Handle {
+    fn record(&self, _: f64) {
+      // the time itself can't be checked
+      self.1.lock().unwrap().push((self.0.clone(), -1.0));
+    }
+  }
+
+  struct Collector(Log);
+  impl Collector {
+    fn handle(&self, key: &Key) -> Arc<Handle> {
+      let mut name = key.name().to_string();
+      for label in key.labels() {
+        name.push_str(&format!(" {}={}", label.key(), label.value()));
+      }
+      Arc::new(Handle(name, self.0.clone()))
+    }
+  }
+  impl metrics::Recorder for Collector {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {
+    }
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+      Counter::from_arc(self.handle(key))
+    }
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+      Gauge::from_arc(self.handle(key))
+    }
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+      Histogram::from_arc(self.handle(key))
+    }
+  }
+
+  let log = Log::default();
+  metrics::with_local_recorder(&Collector(log.clone()), || {
+    let mut machine = StateMachine::new("title");
+    machine.name_states(|state| state.to_string());
+    machine.push("loading").unwrap();
+    machine.publish_metrics(true);
+    machine.swap("game").unwrap();
+    machine.push("pause").unwrap();
+    assert!(machine.apply(Transition::PopN(5)).is_err());
+    machine.pop().unwrap();
+    machine.publish_metrics(false);
+    machine.push("settings").unwrap();
+  });
+
+  let log = log.lock().unwrap();
+  let expected = [
+    ("gerrymander.transitions kind=Swap", 1.0),
+    ("gerrymander.depth", 2.0),
+    ("gerrymander.transitions kind=Push", 1.0),
+    ("gerrymander.depth", 3.0),
+    ("gerrymander.time_in_state state=game", -1.0),
+    ("gerrymander.transition_errors kind=PopN", 1.0),
+    ("gerrymander.transitions kind=Pop", 1.0),
+    ("gerrymander.depth", 2.0),
+    ("gerrymander.time_in_state state=pause", -1.0),
+  ];
+  let log: Vec<_> = log.iter().map(|(key, v)| (key.as_str(), *v)).collect();
+  assert_eq!(log, expected);
+}
+
+#[test]
+fn transition_graph() {
+  let graph = TransitionGraph::new(|state: &&str| {
+    state.split(' ').next().unwrap().to_owned()
+  });
+  let mut machine = StateMachine::new("title");
+  graph.start(&machine);
+  machine.add_observer(graph.clone());
+
+  machine.push("level 1").unwrap();
+  machine.push("pause").unwrap();
+  machine.pop().unwrap();
+  machine.swap("level 2").unwrap();
+  assert!(machine.apply(Transition::PopN(5)).is_err());
+  machine.push("pause").unwrap();
+  machine.pop().unwrap();
+  machine.push("say \"hi\"").unwrap();
+
+  assert_eq!(graph.count("level", "pause"), 2);
+  assert_eq!(graph.count("title", "level"), 1);
+  assert_eq!(graph.count("level", "level"), 0);
+  assert_eq!(
+    graph.to_dot(),
+    r#"digraph states {
+  "level";
+  "pause";
+  "say";
+  "title";
+  "level" -> "pause" [label="2"];
+  "level" -> "say" [label="1"];
+  "pause" -> "level" [label="2"];
+  "title" -> "level" [label="1"];
+}
+"#
+  );
+  assert_eq!(
+    graph.to_mermaid(),
+    r#"stateDiagram-v2
+  state "level" as s0
+  state "pause" as s1
+  state "say" as s2
+  state "title" as s3
+  [*] --> s3
+  s0 --> s1: 2
+  s0 --> s2: 1
+  s1 --> s0: 2
+  s3 --> s0: 1
+"#
+  );
+
+  graph.clear();
+  assert!(graph.edges().is_empty());
+  graph.record("title");
+  graph.record("level");
+  assert_eq!(graph.edges(), [("title".into(), "level".into(), 1)]);
+}
+
+#[cfg(feature = "egui")]
+#[test]
+fn stack_inspector() {
+  let mut machine = StateMachine::new("title");
+  machine.name_states(|state| state.to_string());
+  machine.record_history(4);
+  machine.register("pause", || "pause");
+  machine.push("game").unwrap();
+
+  let ctx = egui::Context::default();
+  for _ in 0..2 {
+    let _ = ctx.run(egui::RawInput::default(), |ctx| {
+      egui::CentralPanel::default().show(ctx, |ui| {
+        let inspector =
+          StackInspector::new(&mut machine).test_state("menu", || "menu");
+        ui.add(inspector);
+      });
+    });
+  }
+  // nothing was clicked
+  assert_eq!(machine.get_stack(), &["title", "game"]);
+}
+
+#[test]
+fn debug_stack() {
+  let mut machine = StateMachine::new_many(vec!["title", "world"]);
+  machine.protect_bottom(1);
+  machine.push("pause").unwrap();
+  assert_eq!(
+    format!("{:?}", machine.debug_stack()),
+    "2 (top)      \"pause\"  [active]\n\
+     1 (depth 1)  \"world\"\n\
+     0 (depth 2)  \"title\"  [protected]\n"
+  );
+
+  machine.name_states(|state| state.to_uppercase());
+  assert_eq!(
+    format!("{:?}", machine.debug_stack().bottom_first(true)),
+    "0 (depth 2)  \"TITLE\"  \"title\"  [protected]\n\
+     1 (depth 1)  \"WORLD\"  \"world\"\n\
+     2 (top)      \"PAUSE\"  \"pause\"  [active]\n"
+  );
+  assert_eq!(
+    format!("{:?}", machine.debug_stack().states(false).names(true)),
+    "2 (top)      \"PAUSE\"  [active]\n\
+     1 (depth 1)  \"WORLD\"\n\
+     0 (depth 2)  \"TITLE\"  [protected]\n"
+  );
+}
+
+#[test]
+fn audit_trail() {
+  let trail = AuditTrail::new().labeled(|state: &&str| state.to_uppercase());
+  let start = std::time::SystemTime::now();
+  let mut machine = StateMachine::new("title");
+  trail.start(&machine);
+  machine.add_observer(trail.clone());
+
+  machine.push("game").unwrap();
+  assert!(machine.apply(Transition::PopN(2)).is_err());
+  machine.pop().unwrap();
+
+  let entries = trail.entries();
+  assert_eq!(entries.len(), 4);
+  assert!(entries.iter().all(|entry| entry.at >= start));
+  let summary: Vec<_> = entries
+    .iter()
+    .map(|entry| {
+      (
+        entry.kind,
+        entry.outcome.clone(),
+        entry.depth_after,
+        entry.label.as_deref(),
+      )
+    })
+    .collect();
+  assert_eq!(
+    summary,
+    [
+      (
+        TransitionKind::None,
+        Ok(TransitionShape::None),
+        1,
+        Some("TITLE")
+      ),
+      (
+        TransitionKind::Push,
+        Ok(TransitionShape::Pushed(0)),
+        2,
+        Some("GAME")
+      ),
+      (
+        TransitionKind::PopN,
+        Err(TransitionError::PoppedTooMany {
+          popcnt: 2,
+          available: 1
+        }),
+        2,
+        Some("GAME")
+      ),
+      (
+        TransitionKind::Pop,
+        Ok(TransitionShape::Revealed(1)),
+        1,
+        Some("TITLE")
+      ),
+    ]
+  );
+
+  #[cfg(feature = "serde")]
+  {
+    let json = serde_json::to_string(&trail).unwrap();
+    let back: Vec<AuditEntry> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, entries);
+  }
+
+  assert_eq!(trail.take().len(), 4);
+  assert!(trail.is_empty());
+}
+
+#[test]
+fn invariants() {
+  let mut machine = StateMachine::new("title");
+  machine.add_invariant(|stack: &[&str]| {
+    match stack.iter().filter(|state| **state == "pause").count() {
+      0 | 1 => Ok(()),
+      count => Err(format!("{} pause menus", count)),
+    }
+  });
+  machine.on_violation(OnViolation::Reject);
+  machine.push("pause").unwrap();
+  let pause = machine.active_id();
+  assert_eq!(
+    machine.apply(Transition::PushMany(Box::new(
+      ["game", "pause"].into_iter()
+    ))),
+    Err(TransitionError::InvariantViolated)
+  );
+  assert_eq!(machine.get_stack(), &["title", "pause"]);
+  assert_eq!(machine.active_id(), pause);
+  machine.swap("pause").unwrap();
+
+  machine.on_violation(OnViolation::Panic);
+  let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+    let _ = machine.push("pause");
+  }));
+  assert!(panicked.is_err());
+
+  machine.clear_invariants();
+  machine.push("pause").unwrap();
+}
+
+#[test]
+fn interceptor() {
+  let mut machine = StateMachine::new("title");
+  machine.set_interceptor(|_: &[&str], transition| match transition {
+    Transition::Push("cheat") => Verdict::Apply(Transition::None),
+    Transition::Push(state) => Verdict::Hold(Transition::Push(state)),
+    transition => Verdict::Apply(transition),
+  });
+
+  assert_eq!(
+    machine.apply(Transition::Push("game")),
+    Ok(TransitionOutcome::Deferred)
+  );
+  assert_eq!(machine.push("pause"), Err(TransitionError::Held));
+  machine.push("cheat").unwrap();
+  assert_eq!(machine.get_stack(), &["title"]);
+  assert_eq!(machine.held().len(), 2);
+
+  assert_eq!(
+    machine.release_next(),
+    Some(Ok(TransitionOutcome::Pushed(0)))
+  );
+  assert_eq!(machine.get_stack(), &["title", "game"]);
+  // popping isn't held
+  assert_eq!(machine.pop(), Ok("game"));
+  assert_eq!(machine.release_all(), [Ok(TransitionOutcome::Pushed(0))]);
+  assert_eq!(machine.get_stack(), &["title", "pause"]);
+  assert_eq!(machine.release_next(), None);
+
+  assert_eq!(machine.push("game"), Err(TransitionError::Held));
+  machine.discard_held();
+  machine.clear_interceptor();
+  machine.push("game").unwrap();
+  assert_eq!(machine.get_stack(), &["title", "pause", "game"]);
+}
+
+#[test]
+fn release_with_hooks() {
+  #[derive(Debug, PartialEq)]
+  struct Logged(&'static str);
+  impl State<Vec<String>> for Logged {
+    fn update(&mut self, _: &mut Vec<String>) -> Transition<Self> {
+      Transition::Push(Logged("pause"))
+    }
+    fn on_enter(
+      &mut self,
+      log: &mut Vec<String>,
+    ) -> Result<(), Cow<'static, str>> {
+      log.push(format!("{} enter", self.0));
+      Ok(())
+    }
+    fn on_pause(&mut self, log: &mut Vec<String>) {
+      log.push(format!("{} pause", self.0));
+    }
+  }
+
+  let mut log = Vec::new();
+  let mut machine = StateMachine::new(Logged("game"));
+  machine.set_interceptor(|_: &[Logged], transition| Verdict::Hold(transition));
+  assert_eq!(machine.tick(&mut log), Ok(TransitionOutcome::Deferred));
+  machine.push(Logged("menu")).unwrap_err();
+  assert!(log.is_empty());
+
+  assert!(machine.release_next_with_hooks(&mut log).unwrap().is_ok());
+  assert_eq!(log, ["game pause", "pause enter"]);
+  // this one was held from a plain push, so it stays plain
+  assert!(machine.release_next_with_hooks(&mut log).unwrap().is_ok());
+  assert_eq!(log.len(), 2);
+  assert_eq!(machine.len().get(), 3);
+}
+
+#[test]
+fn held_helpers() {
+  let mut machine = StateMachine::new_many(vec!["title", "game", "pause"]);
+  machine.mark("pause");
+  let snapshot = machine.snapshot();
+  machine.set_interceptor(|_: &[&str], transition| Verdict::Hold(transition));
+
+  assert_eq!(machine.pop(), Err(TransitionError::Held));
+  assert_eq!(machine.swap("menu"), Err(TransitionError::Held));
+  assert_eq!(machine.reset("title"), Err(TransitionError::Held));
+  assert_eq!(machine.drain_above(0), Err(TransitionError::Held));
+  assert_eq!(machine.truncate_to_bottom(1), Err(TransitionError::Held));
+  assert!(matches!(
+    machine.split_off_top(std::num::NonZeroUsize::MIN),
+    Err(TransitionError::Held)
+  ));
+  assert_eq!(machine.push("menu"), Err(TransitionError::Held));
+  assert_eq!(machine.push_many(["menu"]), Err(TransitionError::Held));
+  assert_eq!(
+    machine.append(StateMachine::new("menu")),
+    Err(TransitionError::Held)
+  );
+  assert!(matches!(
+    machine.push_modal::<bool>("dialog"),
+    Err(TransitionError::Held)
+  ));
+  #[cfg(feature = "time")]
+  assert_eq!(
+    machine.push_timed("toast", std::time::Duration::from_secs(1)),
+    Err(TransitionError::Held)
+  );
+  assert_eq!(machine.get_stack(), &["title", "game", "pause"]);
+  assert_eq!(
+    machine.held().len(),
+    10 + usize::from(cfg!(feature = "time"))
+  );
+
+  machine.discard_held();
+  assert_eq!(machine.restore(snapshot), Ok(TransitionOutcome::Deferred));
+  assert!(machine.marked("pause").is_some());
+  machine.release_next().unwrap().unwrap();
+  assert_eq!(machine.marked("pause"), None);
+}
+
+#[test]
+fn state_stats() {
+  let stats = StateStats::new(|state: &&str| state.len());
+  let mut machine = StateMachine::new("title");
+  stats.start(&machine);
+  machine.add_observer(stats.clone());
+
+  machine.push("shop").unwrap();
+  machine.swap("menu").unwrap();
+  machine.pop().unwrap();
+  machine.push("game").unwrap();
+  assert!(machine.apply(Transition::PopN(4)).is_err());
+
+  assert_eq!(stats.get(&5).pushes, 1);
+  let four = stats.get(&4);
+  assert_eq!((four.pushes, four.pops, four.swaps), (3, 2, 1));
+  assert!(four.average_lifetime().is_some());
+  assert_eq!(stats.get(&5).average_lifetime(), None);
+  assert_eq!(stats.get(&9), KindStats::default());
+  assert_eq!(stats.all().len(), 2);
+
+  stats.clear();
+  machine.pop().unwrap();
+  assert_eq!(stats.get(&4).pops, 1);
+  assert_eq!(stats.get(&4).pushes, 0);
+}
+
+#[test]
+fn apply_draining() {
+  let mut machine =
+    StateMachine::new_many(vec!["title", "game", "pause", "menu"]);
+  let drained = machine.apply_draining(Transition::PopN(2)).unwrap();
+  assert_eq!(drained.shape(), &TransitionShape::Revealed(2));
+  assert_eq!(drained.collect::<Vec<_>>(), ["pause", "menu"]);
+
+  let mut drained = machine.apply_draining(Transition::Swap("level")).unwrap();
+  assert_eq!(drained.shape(), &TransitionShape::SwappedIn(1, 0));
+  assert_eq!(drained.len(), 1);
+  assert_eq!(drained.next(), Some("game"));
+  drop(drained);
+
+  // left over states are dropped
+  machine
+    .apply_draining(Transition::Sequence(vec![
+      Transition::Push("a"),
+      Transition::Push("b"),
+      Transition::PopN(2),
+      Transition::RemoveAt(0),
+    ]))
+    .unwrap();
+  assert_eq!(machine.get_stack(), &["level"]);
+
+  let drained = machine.apply_draining(Transition::Push("pause")).unwrap();
+  assert_eq!(drained.len(), 0);
+  drop(drained);
+  assert!(machine.apply_draining(Transition::PopN(5)).is_err());
+}
+
+#[test]
+fn apply_discard() {
+  use std::{cell::Cell, rc::Rc};
+
+  struct Counted(Rc<Cell<usize>>);
+  impl Drop for Counted {
+    fn drop(&mut self) {
+      self.0.set(self.0.get() + 1);
+    }
+  }
+
+  let dropped = Rc::new(Cell::new(0));
+  let state = || Counted(dropped.clone());
+  let mut machine = StateMachine::new_many(vec![state(), state(), state()]);
+  assert_eq!(
+    machine.apply_discard(Transition::PopN(2)),
+    Ok(TransitionShape::Revealed(2))
+  );
+  assert_eq!(dropped.get(), 2);
+  assert_eq!(
+    machine.apply_discard(Transition::Swap(state())),
+    Ok(TransitionShape::SwappedIn(1, 0))
+  );
+  assert_eq!(dropped.get(), 3);
+  assert_eq!(
+    machine.apply_discard(Transition::Pop),
+    Err(TransitionError::PoppedTooMany {
+      popcnt: 1,
+      available: 0
+    })
+  );
+  assert_eq!(dropped.get(), 3);
+}